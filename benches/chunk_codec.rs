@@ -0,0 +1,104 @@
+//! Compares the size and load time of the chunk save formats on a generated sample world.
+#![feature(test)]
+
+extern crate test;
+
+mod common;
+
+use survival::coords::ChunkPos;
+use survival::save::codec::{decode, encode};
+use survival::save::envelope::Format;
+use survival::save::RegionFiles;
+use survival::systems::chunk::Chunk;
+use survival::systems::world_gen::WorldGenerator;
+use test::Bencher;
+
+const WORLD_SEED: u64 = 0;
+const WORLD_RADIUS: i32 = 8;
+
+/// Generate every chunk of the sample world.
+fn sample_world() -> Vec<Chunk> {
+    let regions = RegionFiles::new(std::env::temp_dir());
+    let generator = WorldGenerator::meadow(WORLD_SEED);
+    let mut chunks = Vec::new();
+    for x in -WORLD_RADIUS..WORLD_RADIUS {
+        for y in -WORLD_RADIUS..WORLD_RADIUS {
+            chunks.push(Chunk::generated(
+                &regions,
+                ChunkPos::new(x, y, 0),
+                &generator,
+            ));
+        }
+    }
+    chunks
+}
+
+fn encode_world(format: Format) -> Vec<Vec<u8>> {
+    let chunks = sample_world();
+    let saves = chunks
+        .iter()
+        .map(|chunk| encode(chunk, format).unwrap())
+        .collect();
+
+    // The sample world should never be written to disk
+    chunks.into_iter().for_each(Chunk::discard);
+    saves
+}
+
+/// Print the total size of the sample world in each format once per run.
+fn report_sizes() {
+    common::report_once(|| {
+        [Format::Ron, Format::Binary, Format::CompressedBinary]
+            .iter()
+            .map(|format| {
+                let saves = encode_world(*format);
+                let total: usize = saves.iter().map(Vec::len).sum();
+                format!(
+                    "{:?}: {} chunks, {} bytes total, {} bytes per chunk",
+                    format,
+                    saves.len(),
+                    total,
+                    total / saves.len()
+                )
+            })
+            .collect()
+    });
+}
+
+fn bench_load(b: &mut Bencher, format: Format) {
+    report_sizes();
+
+    let saves = encode_world(format);
+    b.bytes = saves.iter().map(|save| save.len() as u64).sum();
+    b.iter(|| {
+        for save in &saves {
+            test::black_box(decode(save).unwrap());
+        }
+    });
+}
+
+#[bench]
+fn load_ron(b: &mut Bencher) {
+    bench_load(b, Format::Ron);
+}
+
+#[bench]
+fn load_binary(b: &mut Bencher) {
+    bench_load(b, Format::Binary);
+}
+
+#[bench]
+fn load_compressed_binary(b: &mut Bencher) {
+    bench_load(b, Format::CompressedBinary);
+}
+
+#[bench]
+fn save_binary(b: &mut Bencher) {
+    let chunks = sample_world();
+    b.iter(|| {
+        for chunk in &chunks {
+            test::black_box(encode(chunk, Format::Binary).unwrap());
+        }
+    });
+    chunks.into_iter().for_each(Chunk::discard);
+}
@@ -0,0 +1,15 @@
+//! Reporting shared by the benches.
+
+use std::sync::Once;
+
+static REPORT: Once = Once::new();
+
+/// Print the lines of a report the first time any bench of the run asks for it, so benches can
+/// share a report without printing it more than once.
+pub fn report_once<F: FnOnce() -> Vec<String>>(report: F) {
+    REPORT.call_once(|| {
+        for line in report() {
+            println!("{}", line);
+        }
+    });
+}
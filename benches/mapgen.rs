@@ -0,0 +1,123 @@
+//! Shows how map generation scales with the number of points, from 1k to 200k.
+#![feature(test)]
+
+extern crate test;
+
+mod common;
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use survival::mapgen::{
+    Cell, CellData, CellIndex, ClimateSettings, Generator, GeneratorConfig, IndexPoint,
+    IslandGeneratorSettings, Point,
+};
+use test::Bencher;
+
+const SIZES: [usize; 6] = [1_000, 5_000, 20_000, 50_000, 100_000, 200_000];
+const LOOKUPS: usize = 10_000;
+
+/// Map with `points` cells of about the size of the cells of the default map.
+fn config(points: usize) -> GeneratorConfig {
+    let default = GeneratorConfig::default();
+    GeneratorConfig {
+        num_points: points,
+        box_size: default.box_size * (points as f64 / default.num_points as f64).sqrt(),
+        ..default
+    }
+}
+
+fn cells(config: &GeneratorConfig) -> HashMap<IndexPoint, Cell<CellData>> {
+    Generator::new(StdRng::seed_from_u64(0)).gen_voronoi::<CellData>(config)
+}
+
+fn random_points(config: &GeneratorConfig) -> Vec<Point> {
+    let mut rng = StdRng::seed_from_u64(1);
+    (0..LOOKUPS)
+        .map(|_| {
+            Point::new(
+                rng.gen::<f64>() * config.box_size,
+                rng.gen::<f64>() * config.box_size,
+            )
+        })
+        .collect()
+}
+
+/// Print the time of every step of the generation for each map size once per run.
+fn report_scaling() {
+    common::report_once(|| {
+        let mut lines = Vec::new();
+        for &points in &SIZES {
+            let config = config(points);
+            let mut generator = Generator::new(StdRng::seed_from_u64(0));
+
+            let start = Instant::now();
+            let mut cells = generator.gen_voronoi::<CellData>(&config);
+            let voronoi = start.elapsed();
+
+            let start = Instant::now();
+            generator.create_island(&config, &IslandGeneratorSettings::default(), &mut cells);
+            generator.assign_biomes(&config, &ClimateSettings::default(), &mut cells);
+            let shaping = start.elapsed();
+
+            let start = Instant::now();
+            let index = CellIndex::new(&config, &cells);
+            let indexing = start.elapsed();
+
+            let start = Instant::now();
+            for point in random_points(&config) {
+                test::black_box(index.cell_at(&cells, point));
+            }
+            let lookups = start.elapsed();
+
+            lines.push(format!(
+                "{:>7} points: voronoi {:?}, island and biomes {:?}, index {:?}, {} lookups {:?}",
+                points, voronoi, shaping, indexing, LOOKUPS, lookups
+            ));
+        }
+        lines
+    });
+}
+
+fn bench_voronoi(b: &mut Bencher, points: usize) {
+    report_scaling();
+
+    let config = config(points);
+    b.iter(|| test::black_box(cells(&config)));
+}
+
+fn bench_lookup(b: &mut Bencher, points: usize) {
+    report_scaling();
+
+    let config = config(points);
+    let cells = cells(&config);
+    let index = CellIndex::new(&config, &cells);
+    let targets = random_points(&config);
+    b.iter(|| {
+        for point in &targets {
+            test::black_box(index.cell_at(&cells, *point));
+        }
+    });
+}
+
+#[bench]
+fn voronoi_1k(b: &mut Bencher) {
+    bench_voronoi(b, 1_000);
+}
+
+#[bench]
+fn voronoi_10k(b: &mut Bencher) {
+    bench_voronoi(b, 10_000);
+}
+
+#[bench]
+fn lookup_1k(b: &mut Bencher) {
+    bench_lookup(b, 1_000);
+}
+
+#[bench]
+fn lookup_100k(b: &mut Bencher) {
+    bench_lookup(b, 100_000);
+}
@@ -1,9 +1,12 @@
-use amethyst::core::math::Vector2;
+use std::ops::Deref;
+
+use amethyst::ecs::storage::MaskedStorage;
 use amethyst::{assets::Handle, ecs::prelude::*, renderer::SpriteSheetHandle, shrev::EventChannel};
 use bitflags::*;
 use serde::{Deserialize, Serialize};
 use specs_derive::Component;
 
+use crate::coords::{WorldPos, SURFACE_LEVEL};
 use crate::utils::HasChannel;
 
 #[derive(Component, Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -97,6 +100,25 @@ pub struct Tree {
     branches: f32,
 }
 
+impl Tree {
+    pub fn new(kind: TreeKind, size: f32, branches: f32) -> Self {
+        Self {
+            kind,
+            size,
+            branches,
+        }
+    }
+
+    pub fn size(&self) -> f32 {
+        self.size
+    }
+
+    /// Grow the tree by `amount` without exceeding `max_size`.
+    pub fn grow(&mut self, amount: f32, max_size: f32) {
+        self.size = (self.size + amount).min(max_size).max(self.size);
+    }
+}
+
 #[derive(Component, Default, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 #[storage(DenseVecStorage)]
 pub struct TimeAvailable(pub u64);
@@ -160,6 +182,12 @@ bitflags! {
 #[storage(DenseVecStorage)]
 pub struct Interactable(InteractionType);
 
+impl Interactable {
+    pub fn new(interactions: InteractionType) -> Self {
+        Interactable(interactions)
+    }
+}
+
 pub struct MaterialStatus {
     // TODO: Deterioration? Damage? HP?
 }
@@ -168,5 +196,26 @@ pub struct MaterialStatus {
 #[storage(DenseVecStorage)]
 pub struct Wearing;
 
+/// Position of the player, kept up to date by the movement system.
 #[derive(Component, Clone, Copy, Debug)]
-pub struct PlayerPosition(pub Vector2<f32>);
+pub struct PlayerPosition(pub WorldPos);
+
+/// Vertical level an entity is on. Entities without one are on the surface.
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[storage(DenseVecStorage)]
+pub struct ZLevel(pub i32);
+
+impl ZLevel {
+    /// Level the player is on.
+    pub fn of_player<P, L>(players: &Storage<Player, P>, levels: &Storage<ZLevel, L>) -> i32
+    where
+        P: Deref<Target = MaskedStorage<Player>>,
+        L: Deref<Target = MaskedStorage<ZLevel>>,
+    {
+        (players, levels.maybe())
+            .join()
+            .next()
+            .and_then(|(_, level)| level)
+            .map_or(SURFACE_LEVEL, |level| level.0)
+    }
+}
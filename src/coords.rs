@@ -0,0 +1,280 @@
+//! Positions in the three spaces of the world and the conversions between them.
+//!
+//! - `WorldPos` is in world units, the space transforms and the camera live in.
+//! - `TilePos` addresses a single tile. A tile covers `TILE_SIZE` world units along each axis,
+//!   starting at its origin.
+//! - `ChunkPos` addresses a chunk of `CHUNK_SIZE` x `CHUNK_SIZE` tiles.
+//!
+//! Tiles and chunks are on a vertical level, world positions are not. All conversions round toward
+//! negative infinity so the tiles and chunks on either side of zero are the same size.
+
+use std::fmt;
+
+use amethyst::core::math::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// Size of a tile in world units.
+pub const TILE_SIZE: f32 = 128.;
+
+/// Number of tiles along each side of a chunk.
+pub const CHUNK_SIZE: i32 = 16;
+
+/// Level of the ground the world is generated around. Higher levels are above it.
+pub const SURFACE_LEVEL: i32 = 0;
+
+/// Position in world units.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorldPos {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl WorldPos {
+    pub fn new(x: f32, y: f32) -> Self {
+        WorldPos { x, y }
+    }
+
+    /// Position of a transform translation. The depth is dropped.
+    pub fn from_translation(translation: &Vector3<f32>) -> Self {
+        WorldPos::new(translation.x, translation.y)
+    }
+
+    /// Position in fractional tiles.
+    pub fn tiles(self) -> Vector2<f32> {
+        Vector2::new(self.x / TILE_SIZE, self.y / TILE_SIZE)
+    }
+
+    /// Position of a point given in fractional tiles.
+    pub fn from_tiles(tiles: Vector2<f32>) -> Self {
+        WorldPos::new(tiles.x * TILE_SIZE, tiles.y * TILE_SIZE)
+    }
+
+    /// Tile containing this position on the given level.
+    pub fn tile(self, level: i32) -> TilePos {
+        let tiles = self.tiles();
+        TilePos::new(tiles.x.floor() as i32, tiles.y.floor() as i32, level)
+    }
+
+    /// Chunk containing this position on the given level.
+    pub fn chunk(self, level: i32) -> ChunkPos {
+        self.tile(level).chunk()
+    }
+
+    pub fn vector(self) -> Vector2<f32> {
+        Vector2::new(self.x, self.y)
+    }
+}
+
+/// Position of a single tile.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct TilePos {
+    pub x: i32,
+    pub y: i32,
+    /// Level the tile is on.
+    pub z: i32,
+}
+
+impl TilePos {
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        TilePos { x, y, z }
+    }
+
+    /// Tile containing a position given in fractional tiles.
+    pub fn containing(tiles: Vector2<f64>, level: i32) -> Self {
+        TilePos::new(tiles.x.floor() as i32, tiles.y.floor() as i32, level)
+    }
+
+    /// Tile at a position inside of a chunk.
+    pub fn from_local(chunk: ChunkPos, x: usize, y: usize) -> Self {
+        let origin = chunk.origin();
+        TilePos::new(origin.x + x as i32, origin.y + y as i32, chunk.z)
+    }
+
+    pub fn chunk(self) -> ChunkPos {
+        ChunkPos::new(
+            floor_div(self.x, CHUNK_SIZE),
+            floor_div(self.y, CHUNK_SIZE),
+            self.z,
+        )
+    }
+
+    /// Position of the tile inside of its chunk, as indices into its tile layers.
+    pub fn local(self) -> (usize, usize) {
+        let origin = self.chunk().origin();
+        ((self.x - origin.x) as usize, (self.y - origin.y) as usize)
+    }
+
+    /// Corner of the tile with the lowest coordinates.
+    pub fn origin(self) -> WorldPos {
+        WorldPos::new(self.x as f32 * TILE_SIZE, self.y as f32 * TILE_SIZE)
+    }
+
+    pub fn center(self) -> WorldPos {
+        WorldPos::new(
+            (self.x as f32 + 0.5) * TILE_SIZE,
+            (self.y as f32 + 0.5) * TILE_SIZE,
+        )
+    }
+}
+
+/// Position of a chunk. Saved as a `(x, y, z)` tuple.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(from = "(i32, i32, i32)", into = "(i32, i32, i32)")]
+pub struct ChunkPos {
+    pub x: i32,
+    pub y: i32,
+    /// Level the chunk is on.
+    pub z: i32,
+}
+
+impl ChunkPos {
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        ChunkPos { x, y, z }
+    }
+
+    /// Tile of the chunk with the lowest coordinates.
+    pub fn origin(self) -> TilePos {
+        TilePos::new(self.x * CHUNK_SIZE, self.y * CHUNK_SIZE, self.z)
+    }
+
+    /// The chunk next to this one on the same level.
+    pub fn offset(self, x: i32, y: i32) -> Self {
+        ChunkPos::new(self.x + x, self.y + y, self.z)
+    }
+}
+
+impl From<(i32, i32, i32)> for ChunkPos {
+    fn from((x, y, z): (i32, i32, i32)) -> Self {
+        ChunkPos::new(x, y, z)
+    }
+}
+
+impl From<ChunkPos> for (i32, i32, i32) {
+    fn from(pos: ChunkPos) -> Self {
+        (pos.x, pos.y, pos.z)
+    }
+}
+
+impl fmt::Display for ChunkPos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}, {}, {})", self.x, self.y, self.z)
+    }
+}
+
+/// Divide rounding toward negative infinity.
+pub fn floor_div(value: i32, divisor: i32) -> i32 {
+    let quotient = value / divisor;
+    if value % divisor < 0 {
+        quotient - 1
+    } else {
+        quotient
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    const SAMPLES: usize = 10_000;
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0x5EED)
+    }
+
+    /// Tile coordinates around zero, near the limits and anywhere in between.
+    fn tile_coord(rng: &mut StdRng) -> i32 {
+        match rng.gen_range(0, 3) {
+            0 => rng.gen_range(-64, 64),
+            1 => rng.gen_range(-(1 << 20), 1 << 20),
+            _ => rng.gen_range(i32::min_value() / CHUNK_SIZE, i32::max_value() / CHUNK_SIZE),
+        }
+    }
+
+    fn tile_pos(rng: &mut StdRng) -> TilePos {
+        TilePos::new(tile_coord(rng), tile_coord(rng), rng.gen_range(-8, 8))
+    }
+
+    #[test]
+    fn floor_div_rounds_down() {
+        assert_eq!(floor_div(15, 16), 0);
+        assert_eq!(floor_div(16, 16), 1);
+        assert_eq!(floor_div(-1, 16), -1);
+        assert_eq!(floor_div(-16, 16), -1);
+        assert_eq!(floor_div(-17, 16), -2);
+    }
+
+    #[test]
+    fn negative_tiles_split_into_chunks() {
+        let tile = TilePos::new(-1, -16, -2);
+        assert_eq!(tile.chunk(), ChunkPos::new(-1, -1, -2));
+        assert_eq!(tile.local(), (15, 0));
+
+        let world = WorldPos::new(-0.5, -TILE_SIZE * 16.0 - 0.5);
+        assert_eq!(world.tile(0), TilePos::new(-1, -17, 0));
+        assert_eq!(world.chunk(0), ChunkPos::new(-1, -2, 0));
+    }
+
+    #[test]
+    fn tiles_round_trip_through_chunks() {
+        let mut rng = rng();
+        for _ in 0..SAMPLES {
+            let tile = tile_pos(&mut rng);
+            let (x, y) = tile.local();
+            assert!(x < CHUNK_SIZE as usize && y < CHUNK_SIZE as usize);
+            assert_eq!(TilePos::from_local(tile.chunk(), x, y), tile);
+        }
+    }
+
+    #[test]
+    fn tiles_round_trip_through_the_world() {
+        let mut rng = rng();
+        for _ in 0..SAMPLES {
+            // World positions are f32, so stay where a tile is still wider than one ulp
+            let tile = TilePos::new(
+                rng.gen_range(-(1 << 16), 1 << 16),
+                rng.gen_range(-(1 << 16), 1 << 16),
+                rng.gen_range(-8, 8),
+            );
+            assert_eq!(tile.origin().tile(tile.z), tile);
+            assert_eq!(tile.center().tile(tile.z), tile);
+            assert_eq!(tile.center().chunk(tile.z), tile.chunk());
+        }
+    }
+
+    #[test]
+    fn world_positions_land_in_their_tile() {
+        let mut rng = rng();
+        for _ in 0..SAMPLES {
+            let world = WorldPos::new(rng.gen_range(-1.0e6, 1.0e6), rng.gen_range(-1.0e6, 1.0e6));
+            let origin = world.tile(0).origin();
+            assert!(origin.x <= world.x && world.x < origin.x + TILE_SIZE);
+            assert!(origin.y <= world.y && world.y < origin.y + TILE_SIZE);
+            assert_eq!(WorldPos::from_tiles(world.tiles()).tile(0), world.tile(0));
+        }
+    }
+
+    #[test]
+    fn chunks_round_trip_through_their_origin() {
+        let mut rng = rng();
+        for _ in 0..SAMPLES {
+            let chunk = tile_pos(&mut rng).chunk();
+            assert_eq!(chunk.origin().chunk(), chunk);
+            assert_eq!(chunk.origin().local(), (0, 0));
+            assert_eq!(ChunkPos::from(<(i32, i32, i32)>::from(chunk)), chunk);
+        }
+    }
+
+    #[test]
+    fn chunk_saves_as_a_tuple() {
+        let chunk = ChunkPos::new(-3, 7, -1);
+        assert_eq!(ron::ser::to_string(&chunk).unwrap(), "(-3,7,-1)");
+        assert_eq!(ron::de::from_str::<ChunkPos>("(-3,7,-1)").unwrap(), chunk);
+    }
+}
@@ -13,6 +13,9 @@ use nphysics2d::math::{Inertia, Velocity};
 use nphysics2d::object::{BodyStatus, ColliderDesc, RigidBodyDesc};
 use serde::{Deserialize, Serialize};
 
+use crate::coords::SURFACE_LEVEL;
+use crate::save::entities::SavedEntity;
+
 #[derive(Serialize, Deserialize, Derivative)]
 #[derivative(Debug)]
 pub struct WorldEntity {
@@ -20,6 +23,13 @@ pub struct WorldEntity {
     #[derivative(Debug = "ignore")]
     display: EntityGraphics,
     pub pos: Vector2<f64>,
+    /// Level the entity is on.
+    #[serde(default)]
+    pub level: i32,
+    /// Components of the ECS entity this was saved from, followed by everything it carries. They
+    /// are recreated when the chunk loads.
+    #[serde(default)]
+    pub saved: Vec<SavedEntity>,
 }
 
 impl WorldEntity {
@@ -32,9 +42,21 @@ impl WorldEntity {
 
         Self {
             display: EntityGraphics::None,
-            pos: Vector2::new(0.0, 0.0),
+            pos,
+            level: SURFACE_LEVEL,
+            saved: Vec::new(),
         }
     }
+
+    pub fn on_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn with_saved(mut self, saved: Vec<SavedEntity>) -> Self {
+        self.saved = saved;
+        self
+    }
 }
 
 pub enum EntityGraphics {
@@ -43,10 +65,10 @@ pub enum EntityGraphics {
     None,
 }
 
-// TODO make serializing work for entities
+// Graphics are not saved. Saved ECS entities get their sprites back from their other components.
 impl Default for EntityGraphics {
     fn default() -> Self {
-        unimplemented!()
+        EntityGraphics::None
     }
 }
 
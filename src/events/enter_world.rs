@@ -1,27 +1,39 @@
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use amethyst::renderer::SpriteSheet;
 use amethyst::{
     assets::AssetStorage,
-    core::components::Transform,
-    ecs::Builder,
+    core::{components::Transform, Time},
+    ecs::{Builder, Read, SystemData, WriteExpect},
     renderer::{Camera, Projection, Rgba, SpriteRender, Transparent},
     StateData, StateEvent, Trans,
 };
-use log::info;
+use log::{error, info};
 
-use crate::components::{Actionable, Player, TimeAvailable};
+use crate::components::{Actionable, Player, PlayerPosition, TimeAvailable};
+use crate::coords::WorldPos;
+use crate::save::entities::EntityStorages;
+use crate::save::player::PlayerSave;
+use crate::save::ActiveWorld;
 use crate::settings;
+use crate::systems::chunk::WorldChunks;
+use crate::systems::time::TimeState;
 use crate::tiles::{TileAsset, TileAssets, Tiles};
 use crate::GameDispatchers;
 
-pub struct Level;
+#[derive(Default)]
+pub struct Level {
+    /// Time the level was entered at, used to track the play time of the world.
+    started: Duration,
+}
 
 impl<'a, 'b> amethyst::State<GameDispatchers<'a, 'b>, StateEvent> for Level {
     fn on_start(&mut self, data: StateData<'_, GameDispatchers<'_, '_>>) {
         let world = data.world;
         info!("Started level setup");
+        self.started = world.read_resource::<Time>().absolute_real_time();
 
         // Fetch resources needed for init
         world.add_resource(Tiles::new(100, 100));
@@ -29,29 +41,74 @@ impl<'a, 'b> amethyst::State<GameDispatchers<'a, 'b>, StateEvent> for Level {
         let map_sprite_sheet_handle = context.as_ref().unwrap();
         let game_settings = world.res.fetch::<settings::Config>().clone();
 
-        // Create player
-        world
-            .create_entity()
-            .with({
-                let mut transform = Transform::default();
-                transform.set_translation_xyz(300.0, -300.0, 0.0);
-                transform.set_scale(
-                    game_settings.graphics.scale,
-                    game_settings.graphics.scale,
-                    1.,
-                );
-                transform
-            })
-            .with(Player)
-            .with(SpriteRender {
-                sprite_sheet: map_sprite_sheet_handle.clone(),
-                sprite_number: 25,
+        // Restore the player of the world, or create a new one the first time it is played
+        <EntityStorages as SystemData>::setup(&mut world.res);
+        let world_path = world.read_resource::<ActiveWorld>().path.clone();
+        let saved = match PlayerSave::read(&world_path) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to load the player, creating a new one: {}", e);
+                None
+            }
+        };
+
+        let player = saved.and_then(|save| {
+            world.add_resource(save.time);
+            world.exec(|mut storages: EntityStorages| {
+                let player = storages.restore_player(save.entities)?;
+                storages.set_level(player, save.level);
+                Some(player)
             })
-            .with(TimeAvailable::default())
-            .with(Actionable::default())
-            .with(Transparent)
-            .with(Rgba::RED)
-            .build();
+        });
+        let player = player.unwrap_or_else(|| {
+            world
+                .create_entity()
+                .with({
+                    let mut transform = Transform::default();
+                    transform.set_translation_xyz(300.0, -300.0, 0.0);
+                    transform.set_scale(
+                        game_settings.graphics.scale,
+                        game_settings.graphics.scale,
+                        1.,
+                    );
+                    transform
+                })
+                .with(Player)
+                .with(TimeAvailable::default())
+                .build()
+        });
+
+        // Chunks stream around the player right away instead of once it first moves
+        let position = world
+            .read_storage::<Transform>()
+            .get(player)
+            .map(|transform| WorldPos::from_translation(transform.translation()))
+            .unwrap_or_default();
+        world.add_resource(PlayerPosition(position));
+
+        // Components which are not saved with the player
+        world
+            .write_storage::<SpriteRender>()
+            .insert(
+                player,
+                SpriteRender {
+                    sprite_sheet: map_sprite_sheet_handle.clone(),
+                    sprite_number: 25,
+                },
+            )
+            .unwrap();
+        world
+            .write_storage::<Actionable>()
+            .insert(player, Actionable::default())
+            .unwrap();
+        world
+            .write_storage::<Transparent>()
+            .insert(player, Transparent)
+            .unwrap();
+        world
+            .write_storage::<Rgba>()
+            .insert(player, Rgba::RED)
+            .unwrap();
 
         // Create camera
         world
@@ -69,6 +126,33 @@ impl<'a, 'b> amethyst::State<GameDispatchers<'a, 'b>, StateEvent> for Level {
         info!("Finished level setup");
     }
 
+    fn on_stop(&mut self, data: StateData<'_, GameDispatchers<'_, '_>>) {
+        // Loaded chunks are saved when the world is dropped, so they need their entities first.
+        // Entities outside of them wait in the pending store for their chunk.
+        data.world.exec(
+            |(mut chunks, mut storages, time): (
+                WriteExpect<WorldChunks>,
+                EntityStorages,
+                Read<TimeState>,
+            )| {
+                chunks.store_all(&mut storages, time.current_time);
+                storages.store_strays(&mut chunks);
+            },
+        );
+
+        #[cfg(not(feature = "no-save"))]
+        save_level(data.world);
+
+        let played = data.world.read_resource::<Time>().absolute_real_time() - self.started;
+        let player = data.world.read_resource::<PlayerPosition>().0;
+        let mut active = data.world.write_resource::<ActiveWorld>();
+        active.manifest.play_time += played;
+        active.manifest.player_position = Some(player.tiles());
+        if let Err(e) = active.save_manifest() {
+            error!("Unable to save world manifest: {}", e);
+        }
+    }
+
     fn handle_event(
         &mut self,
         data: StateData<'_, GameDispatchers<'_, '_>>,
@@ -107,4 +191,29 @@ impl<'a, 'b> amethyst::State<GameDispatchers<'a, 'b>, StateEvent> for Level {
     }
 }
 
+/// Write the player and the entities waiting for their chunks into the world folder.
+#[cfg(not(feature = "no-save"))]
+fn save_level(world: &mut amethyst::ecs::World) {
+    let world_path = world.read_resource::<ActiveWorld>().path.clone();
+
+    let (entities, level) = world
+        .exec(|mut storages: EntityStorages| (storages.save_player(), storages.player_level()));
+    let player = PlayerSave {
+        time: *world.read_resource::<TimeState>(),
+        level,
+        entities,
+    };
+    if let Err(e) = player.write(&world_path) {
+        error!("Unable to save the player: {}", e);
+    }
+
+    if let Err(e) = world
+        .read_resource::<WorldChunks>()
+        .pending()
+        .write(&world_path)
+    {
+        error!("Unable to save entities waiting for their chunks: {}", e);
+    }
+}
+
 pub static mut SHEET_INIT: AtomicBool = AtomicBool::new(false);
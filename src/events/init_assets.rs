@@ -9,6 +9,7 @@ use amethyst::{
 use log::info;
 use log::trace;
 
+use crate::coords::ChunkPos;
 use crate::events::Level;
 use crate::render::tile_pass::ChunkRender;
 use crate::settings;
@@ -78,7 +79,7 @@ impl<'a, 'b> amethyst::State<GameDispatchers<'a, 'b>, StateEvent> for FirstLoad
             .register_tile_comp::<amethyst::core::transform::GlobalTransform, crate::tiles::TileId>(
             );
         world.register_tile_comp::<crate::tiles::TileEntities, crate::tiles::TileId>();
-        world.add_resource(HashMap::<(i32, i32), ChunkRender>::new());
+        world.add_resource(HashMap::<ChunkPos, ChunkRender>::new());
         world.add_resource(TileAssets(Vec::new()));
         world.add_resource(progress);
 
@@ -99,6 +100,6 @@ impl<'a, 'b> amethyst::State<GameDispatchers<'a, 'b>, StateEvent> for FirstLoad
         &mut self,
         _: StateData<'_, GameDispatchers<'_, '_>>,
     ) -> Trans<GameDispatchers<'a, 'b>, StateEvent> {
-        Trans::Switch(Box::new(Level))
+        Trans::Switch(Box::new(Level::default()))
     }
 }
@@ -23,8 +23,10 @@ pub mod mapgen;
 
 pub mod assets;
 pub mod components;
+pub mod coords;
 pub mod events;
 pub mod render;
+pub mod save;
 pub mod settings;
 pub mod systems;
 pub mod tiles;
@@ -55,6 +57,20 @@ pub fn run() -> amethyst::Result<()> {
 
     let game_config = settings::Config::load(root.join("game_settings.ron"));
 
+    let worlds = save::Worlds::new(root.join("worlds"));
+    // Saves from before there were named worlds are kept in their own world
+    worlds.migrate_legacy(
+        &root.join("saves"),
+        game_config.world.seed,
+        &game_config.world.terrain,
+    )?;
+    let world = worlds.open_or_create(
+        &game_config.world.name,
+        game_config.world.seed,
+        &game_config.world.terrain,
+    )?;
+    info!("Opened world {} at {:?}", world.manifest.name, world.path);
+
     let render_bundle = RenderBundle::new(pipe, Some(display_config.clone()))
         .with_sprite_sheet_processor()
         .with_sprite_visibility_sorting(&[])
@@ -75,7 +91,7 @@ pub fn run() -> amethyst::Result<()> {
         .with_level(systems::MovementSystem::default(), "movement", &[])
         .with_level(systems::TimeSystem::default(), "time", &[])
         .with_level(
-            ChunkLoadSystem::new(root.join("saves")),
+            ChunkLoadSystem::new(world.path.clone()),
             "chunk_loader",
             &[],
         )
@@ -84,10 +100,16 @@ pub fn run() -> amethyst::Result<()> {
             "entity_chunk",
             &[],
         )
+        .with_level(
+            systems::tile_edit::TileEditSystem,
+            "tile_edit",
+            &["chunk_loader"],
+        )
         .with_core_bundle(render_bundle)?;
 
     let mut game = Application::build(root, crate::events::FirstLoad::default())?
         .with_frame_limit(FrameRateLimitStrategy::Unlimited, 9999)
+        .with_resource(world)
         .build(game_data)?;
 
     info!("Starting game loop");
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use super::SEA_LEVEL;
+
+/// Height above the sea level which is still washed over by the sea.
+const BEACH_HEIGHT: f64 = 0.03;
+
+/// Height above the sea level up to which very wet land turns into swamp.
+const SWAMP_HEIGHT: f64 = 0.1;
+
+/// Kind of landscape of a map cell, picked from its height, moisture and temperature.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Biome {
+    Ocean,
+    Beach,
+    Snow,
+    Tundra,
+    Taiga,
+    Grassland,
+    TemperateForest,
+    TemperateRainforest,
+    Desert,
+    Savanna,
+    TropicalForest,
+    TropicalRainforest,
+    Swamp,
+}
+
+impl Default for Biome {
+    fn default() -> Self {
+        Biome::Ocean
+    }
+}
+
+impl Biome {
+    /// Classify a cell following a Whittaker diagram. Moisture and temperature range from 0 to 1,
+    /// the temperature already includes the cooling from the height of the cell.
+    pub fn classify(height: f64, moisture: f64, temperature: f64) -> Self {
+        if height < SEA_LEVEL {
+            return Biome::Ocean;
+        }
+        if height < SEA_LEVEL + BEACH_HEIGHT {
+            return Biome::Beach;
+        }
+        if temperature >= 0.3 && moisture >= 0.85 && height < SEA_LEVEL + SWAMP_HEIGHT {
+            return Biome::Swamp;
+        }
+
+        if temperature < 0.1 {
+            Biome::Snow
+        } else if temperature < 0.3 {
+            if moisture < 0.25 {
+                Biome::Tundra
+            } else {
+                Biome::Taiga
+            }
+        } else if temperature < 0.65 {
+            if moisture < 0.2 {
+                Biome::Desert
+            } else if moisture < 0.45 {
+                Biome::Grassland
+            } else if moisture < 0.8 {
+                Biome::TemperateForest
+            } else {
+                Biome::TemperateRainforest
+            }
+        } else if moisture < 0.25 {
+            Biome::Desert
+        } else if moisture < 0.5 {
+            Biome::Savanna
+        } else if moisture < 0.8 {
+            Biome::TropicalForest
+        } else {
+            Biome::TropicalRainforest
+        }
+    }
+
+    /// Color the biome is painted with in map images.
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            Biome::Ocean => [0, 191, 255],
+            Biome::Beach => [238, 214, 175],
+            Biome::Snow => [248, 248, 248],
+            Biome::Tundra => [187, 187, 170],
+            Biome::Taiga => [153, 170, 119],
+            Biome::Grassland => [196, 212, 170],
+            Biome::TemperateForest => [103, 148, 89],
+            Biome::TemperateRainforest => [68, 136, 85],
+            Biome::Desert => [233, 221, 199],
+            Biome::Savanna => [210, 185, 139],
+            Biome::TropicalForest => [85, 153, 68],
+            Biome::TropicalRainforest => [51, 119, 85],
+            Biome::Swamp => [85, 107, 47],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_follows_the_diagram() {
+        assert_eq!(Biome::classify(0.2, 1.0, 0.5), Biome::Ocean);
+        assert_eq!(Biome::classify(SEA_LEVEL + 0.01, 0.5, 0.5), Biome::Beach);
+        assert_eq!(Biome::classify(0.9, 0.9, 0.05), Biome::Snow);
+        assert_eq!(Biome::classify(0.8, 0.1, 0.2), Biome::Tundra);
+        assert_eq!(Biome::classify(0.8, 0.6, 0.2), Biome::Taiga);
+        assert_eq!(Biome::classify(0.7, 0.3, 0.5), Biome::Grassland);
+        assert_eq!(Biome::classify(0.7, 0.6, 0.5), Biome::TemperateForest);
+        assert_eq!(Biome::classify(0.7, 0.1, 0.9), Biome::Desert);
+        assert_eq!(Biome::classify(0.7, 0.4, 0.9), Biome::Savanna);
+        assert_eq!(Biome::classify(0.7, 0.9, 0.9), Biome::TropicalRainforest);
+    }
+
+    #[test]
+    fn wet_lowlands_are_swamps() {
+        assert_eq!(Biome::classify(SEA_LEVEL + 0.05, 0.9, 0.5), Biome::Swamp);
+        // Too high or too cold to stay waterlogged
+        assert_eq!(Biome::classify(0.8, 0.9, 0.5), Biome::TemperateRainforest);
+        assert_eq!(Biome::classify(SEA_LEVEL + 0.05, 0.9, 0.2), Biome::Taiga);
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    inside_poly, sorted_keys, Cell, CellData, GeneratorConfig, IndexPoint, Point, SEA_LEVEL,
+};
+
+/// Offsets of the four neighbors of a pixel. The neighbor in direction `d` sees the pixel in
+/// direction `d ^ 1`.
+const DIRECTIONS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErosionSettings {
+    /// Number of rounds of rain falling, carrying sediment downhill and evaporating.
+    pub hydraulic_iterations: usize,
+    /// Water falling on every pixel each round.
+    pub rain: f64,
+    /// Height of ground dissolved by one unit of water.
+    pub solubility: f64,
+    /// Share of the water evaporating each round.
+    pub evaporation: f64,
+    /// Sediment one unit of water can carry, the rest is deposited.
+    pub capacity: f64,
+    /// Number of rounds of steep slopes crumbling.
+    pub thermal_iterations: usize,
+    /// Steepest stable slope, in height per map unit.
+    pub talus: f64,
+    /// Share of the material above the stable slope sliding down each round.
+    pub thermal_rate: f64,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        Self {
+            hydraulic_iterations: 50,
+            rain: 0.01,
+            solubility: 0.01,
+            evaporation: 0.5,
+            capacity: 0.01,
+            thermal_iterations: 50,
+            talus: 0.004,
+            thermal_rate: 0.5,
+        }
+    }
+}
+
+/// Square raster of heights covering the map, with `(0, 0)` in the corner at the map origin. The
+/// passes work in whole rounds so every pixel is updated in parallel from the last round.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Heightmap {
+    size: usize,
+    scale: f64,
+    heights: Vec<f64>,
+}
+
+impl Heightmap {
+    /// Flat heightmap of `size` by `size` pixels, each `scale` map units wide.
+    pub fn new(size: usize, scale: f64) -> Self {
+        Self {
+            size,
+            scale,
+            heights: vec![0.; size * size],
+        }
+    }
+
+    /// Rasterize the heights of the cells. Every pixel takes the height of the cell containing its
+    /// center, pixels outside of every cell are at the bottom of the sea.
+    pub fn from_cells(
+        config: &GeneratorConfig,
+        cells: &HashMap<IndexPoint, Cell<CellData>>,
+        size: usize,
+    ) -> Self {
+        let mut map = Self::new(size, config.box_size / size as f64);
+
+        let pixels = sorted_keys(cells)
+            .par_iter()
+            .map(|key| map.rasterize(&cells[key]))
+            .collect::<Vec<_>>();
+        for (index, height) in pixels.into_iter().flatten() {
+            map.heights[index] = height;
+        }
+
+        map
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Width of a pixel in map units.
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn heights(&self) -> &[f64] {
+        &self.heights
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f64 {
+        self.heights[y * self.size + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, height: f64) {
+        self.heights[y * self.size + x] = height;
+    }
+
+    /// Height of the pixel under a point of the map.
+    pub fn at(&self, point: Point) -> Option<f64> {
+        let x = (point.x / self.scale).floor();
+        let y = (point.y / self.scale).floor();
+        if x < 0. || y < 0. || x >= self.size as f64 || y >= self.size as f64 {
+            return None;
+        }
+        Some(self.get(x as usize, y as usize))
+    }
+
+    /// Run the hydraulic and then the thermal pass.
+    pub fn erode(&mut self, settings: &ErosionSettings) {
+        self.hydraulic(settings);
+        self.thermal(settings);
+    }
+
+    /// Rain dissolves the ground, the water runs to lower pixels with its sediment and deposits
+    /// it where it evaporates. Water running off the edges of the map is lost to the sea.
+    pub fn hydraulic(&mut self, settings: &ErosionSettings) {
+        let count = self.heights.len();
+        let mut water = vec![0.; count];
+        let mut sediment = vec![0.; count];
+
+        for _ in 0..settings.hydraulic_iterations {
+            self.heights
+                .par_iter_mut()
+                .zip(water.par_iter_mut())
+                .zip(sediment.par_iter_mut())
+                .for_each(|((height, water), sediment)| {
+                    *water += settings.rain;
+                    let dissolved = settings.solubility * *water;
+                    *height -= dissolved;
+                    *sediment += dissolved;
+                });
+
+            // Split the water of every pixel between its lower neighbors, so the surface of the
+            // pixel and those neighbors ends up level
+            let outflow = (0..count)
+                .into_par_iter()
+                .map(|index| {
+                    let surface = self.heights[index] + water[index];
+                    let mut drops = [0.; 4];
+                    let mut total = 0.;
+                    let mut level = surface;
+                    let mut lower = 1.;
+                    for (d, neighbor) in self.neighbors(index).iter().enumerate() {
+                        // The sea past the edges of the map is empty
+                        let other = neighbor.map_or(0., |n| self.heights[n] + water[n]);
+                        if other < surface {
+                            drops[d] = surface - other;
+                            total += drops[d];
+                            level += other;
+                            lower += 1.;
+                        }
+                    }
+                    if total <= 0. {
+                        return [0.; 4];
+                    }
+
+                    let moved = water[index].min(surface - level / lower);
+                    for drop in drops.iter_mut() {
+                        *drop *= moved / total;
+                    }
+                    drops
+                })
+                .collect::<Vec<_>>();
+
+            let moved = (0..count)
+                .into_par_iter()
+                .map(|index| {
+                    let out = outflow[index].iter().sum::<f64>();
+                    let kept = if water[index] > 0. {
+                        1. - out / water[index]
+                    } else {
+                        1.
+                    };
+                    let mut new_water = water[index] - out;
+                    let mut new_sediment = sediment[index] * kept;
+                    for (d, neighbor) in self.neighbors(index).iter().enumerate() {
+                        if let Some(n) = *neighbor {
+                            let inflow = outflow[n][d ^ 1];
+                            if inflow > 0. {
+                                new_water += inflow;
+                                new_sediment += sediment[n] * inflow / water[n];
+                            }
+                        }
+                    }
+                    (new_water, new_sediment)
+                })
+                .collect::<Vec<_>>();
+
+            self.heights
+                .par_iter_mut()
+                .zip(water.par_iter_mut())
+                .zip(sediment.par_iter_mut())
+                .zip(moved.par_iter())
+                .for_each(|(((height, water), sediment), moved)| {
+                    *water = moved.0 * (1. - settings.evaporation);
+                    *sediment = moved.1;
+                    let capacity = settings.capacity * *water;
+                    if *sediment > capacity {
+                        *height += *sediment - capacity;
+                        *sediment = capacity;
+                    }
+                });
+        }
+
+        // Whatever is still carried settles where it is
+        self.heights
+            .par_iter_mut()
+            .zip(sediment.par_iter())
+            .for_each(|(height, sediment)| *height += sediment);
+    }
+
+    /// Slopes steeper than the talus crumble, moving material down to the lower neighbors until
+    /// they are stable. No material is lost.
+    pub fn thermal(&mut self, settings: &ErosionSettings) {
+        let talus = settings.talus * self.scale;
+
+        for _ in 0..settings.thermal_iterations {
+            let outflow = (0..self.heights.len())
+                .into_par_iter()
+                .map(|index| {
+                    let height = self.heights[index];
+                    let mut drops = [0.; 4];
+                    let mut total = 0.;
+                    let mut steepest = 0.;
+                    for (d, neighbor) in self.neighbors(index).iter().enumerate() {
+                        if let Some(n) = *neighbor {
+                            let drop = height - self.heights[n];
+                            if drop > talus {
+                                drops[d] = drop;
+                                total += drop;
+                                steepest = f64::max(steepest, drop);
+                            }
+                        }
+                    }
+                    if total <= 0. {
+                        return [0.; 4];
+                    }
+
+                    let moved = settings.thermal_rate * (steepest - talus) / 2.;
+                    for drop in drops.iter_mut() {
+                        *drop *= moved / total;
+                    }
+                    drops
+                })
+                .collect::<Vec<_>>();
+
+            let heights = (0..self.heights.len())
+                .into_par_iter()
+                .map(|index| {
+                    let mut height = self.heights[index] - outflow[index].iter().sum::<f64>();
+                    for (d, neighbor) in self.neighbors(index).iter().enumerate() {
+                        if let Some(n) = *neighbor {
+                            height += outflow[n][d ^ 1];
+                        }
+                    }
+                    height
+                })
+                .collect();
+            self.heights = heights;
+        }
+    }
+
+    /// Save the heightmap in the colors of `ImageMode::Height`.
+    pub fn save_image(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let size = self.size as u32;
+        let imgbuf = image::ImageBuffer::from_fn(size, size, |x, y| {
+            let height = self.get(x as usize, y as usize);
+            if height < SEA_LEVEL {
+                image::Rgb([0, 191, 255])
+            } else {
+                image::Rgb([(height.min(1.) * 255.) as u8, 0, 0])
+            }
+        });
+        imgbuf.save(path)
+    }
+
+    /// Pixels whose center lies in the polygon of a cell, with the height of the cell.
+    fn rasterize(&self, cell: &Cell<CellData>) -> Vec<(usize, f64)> {
+        if cell.polygon.is_empty() {
+            return Vec::new();
+        }
+
+        let pixel = |v: f64| ((v / self.scale).max(0.) as usize).min(self.size - 1);
+        let min_x = cell
+            .polygon
+            .iter()
+            .map(|p| p.x)
+            .fold(std::f64::MAX, f64::min);
+        let max_x = cell
+            .polygon
+            .iter()
+            .map(|p| p.x)
+            .fold(std::f64::MIN, f64::max);
+        let min_y = cell
+            .polygon
+            .iter()
+            .map(|p| p.y)
+            .fold(std::f64::MAX, f64::min);
+        let max_y = cell
+            .polygon
+            .iter()
+            .map(|p| p.y)
+            .fold(std::f64::MIN, f64::max);
+
+        let mut pixels = Vec::new();
+        for y in pixel(min_y)..=pixel(max_y) {
+            for x in pixel(min_x)..=pixel(max_x) {
+                let center =
+                    Point::new((x as f64 + 0.5) * self.scale, (y as f64 + 0.5) * self.scale);
+                if inside_poly(center, &cell.polygon) {
+                    pixels.push((y * self.size + x, cell.data.height));
+                }
+            }
+        }
+        pixels
+    }
+
+    fn neighbors(&self, index: usize) -> [Option<usize>; 4] {
+        let x = (index % self.size) as isize;
+        let y = (index / self.size) as isize;
+        let size = self.size as isize;
+
+        let mut neighbors = [None; 4];
+        for (d, (dx, dy)) in DIRECTIONS.iter().enumerate() {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx >= 0 && ny >= 0 && nx < size && ny < size {
+                neighbors[d] = Some((ny * size + nx) as usize);
+            }
+        }
+        neighbors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cone with its peak in the middle of the map.
+    fn cone(size: usize) -> Heightmap {
+        let mut map = Heightmap::new(size, 1.);
+        let middle = size as f64 / 2.;
+        for y in 0..size {
+            for x in 0..size {
+                let distance = ((x as f64 - middle).powi(2) + (y as f64 - middle).powi(2)).sqrt();
+                map.set(x, y, (1. - distance / middle).max(0.));
+            }
+        }
+        map
+    }
+
+    fn steepest(map: &Heightmap) -> f64 {
+        (0..map.heights.len())
+            .flat_map(|index| {
+                map.neighbors(index)
+                    .iter()
+                    .filter_map(|n| *n)
+                    .map(|n| map.heights[index] - map.heights[n])
+                    .collect::<Vec<_>>()
+            })
+            .fold(0., f64::max)
+    }
+
+    #[test]
+    fn thermal_keeps_material_and_flattens_slopes() {
+        let mut map = cone(32);
+        map.set(16, 16, 3.);
+        let before = map.heights.iter().sum::<f64>();
+        let steep = steepest(&map);
+
+        map.thermal(&ErosionSettings {
+            thermal_iterations: 100,
+            talus: 0.05,
+            ..Default::default()
+        });
+
+        assert!((map.heights.iter().sum::<f64>() - before).abs() < 1e-9);
+        assert!(steepest(&map) < steep);
+    }
+
+    #[test]
+    fn hydraulic_wears_down_peaks() {
+        let mut map = cone(32);
+        let peak = map.get(16, 16);
+        map.hydraulic(&ErosionSettings::default());
+
+        assert!(map.get(16, 16) < peak);
+        assert!(map.heights.iter().all(|height| height.is_finite()));
+    }
+
+    #[test]
+    fn erosion_is_deterministic() {
+        let mut a = cone(24);
+        let mut b = cone(24);
+        a.erode(&ErosionSettings::default());
+        b.erode(&ErosionSettings::default());
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn cells_are_rasterized() {
+        use rand::SeedableRng;
+
+        let config = GeneratorConfig {
+            num_points: 200,
+            ..Default::default()
+        };
+        let mut generator = super::super::Generator::new(rand::rngs::StdRng::seed_from_u64(3));
+        let mut cells = generator.gen_voronoi::<CellData>(&config);
+        generator.create_island(&config, &Default::default(), &mut cells);
+
+        let map = Heightmap::from_cells(&config, &cells, 100);
+        let mut checked = 0;
+        for cell in cells.values() {
+            // Only the pixel under the center of the cell, if its own center is in the cell too
+            let x = (cell.position.x.into_inner() / map.scale()).floor();
+            let y = (cell.position.y.into_inner() / map.scale()).floor();
+            let center = Point::new((x + 0.5) * map.scale(), (y + 0.5) * map.scale());
+            if inside_poly(center, &cell.polygon) {
+                assert_eq!(map.at(center), Some(cell.data.height));
+                checked += 1;
+            }
+        }
+        assert!(checked > cells.len() / 2);
+    }
+}
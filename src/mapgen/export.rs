@@ -0,0 +1,342 @@
+//! Exports of generated maps for tools outside of the game.
+//!
+//! - 16-bit grayscale heightmaps, for terrain editors and engines which want more than 256 levels.
+//! - GeoJSON of the cell polygons with their climate and biome, for GIS tools and map reviews.
+//! - RON dumps of the whole cell graph, which load back into a generator session as they were.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use amethyst::error::{format_err, Error, ResultExt};
+use image::{ColorType, ImageDecoder};
+use ordered_float::OrderedFloat;
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+
+use super::{sorted_keys, Biome, Cell, CellData, GeneratorConfig, Heightmap, IndexPoint, Point};
+
+impl Heightmap {
+    /// Save the heights as a 16-bit grayscale PNG, from black at 0 to white at 1.
+    pub fn save_png16(&self, path: &Path) -> io::Result<()> {
+        let data = self
+            .heights()
+            .iter()
+            .flat_map(|height| {
+                let level = (height.max(0.).min(1.) * f64::from(u16::max_value())).round() as u16;
+                // PNG stores samples wider than a byte most significant byte first
+                level.to_be_bytes().to_vec()
+            })
+            .collect::<Vec<_>>();
+
+        let size = self.size() as u32;
+        let file = BufWriter::new(fs::File::create(path)?);
+        image::png::PNGEncoder::new(file).encode(&data, size, size, ColorType::Gray(16))
+    }
+
+    /// Read a heightmap saved by `save_png16`. The pixels are `scale` map units wide.
+    pub fn load_png16(path: &Path, scale: f64) -> io::Result<Self> {
+        let invalid =
+            |e: image::ImageError| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+
+        let file = BufReader::new(fs::File::open(path)?);
+        let decoder = image::png::PNGDecoder::new(file).map_err(invalid)?;
+        let (width, height) = decoder.dimensions();
+        if decoder.colortype() != ColorType::Gray(16) || width != height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is not a square 16-bit grayscale image", path),
+            ));
+        }
+        let data = decoder.read_image().map_err(invalid)?;
+
+        let size = width as usize;
+        let mut heightmap = Heightmap::new(size, scale);
+        for (i, sample) in data.chunks(2).enumerate() {
+            let level = u16::from_be_bytes([sample[0], sample[1]]);
+            heightmap.set(
+                i % size,
+                i / size,
+                f64::from(level) / f64::from(u16::max_value()),
+            );
+        }
+        Ok(heightmap)
+    }
+}
+
+/// Attributes of a cell stored with its polygon in GeoJSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CellProperties {
+    /// Center of the cell, which is also its key in the cell graph.
+    pub center: [f64; 2],
+    pub height: f64,
+    pub moisture: f64,
+    pub temperature: f64,
+    pub biome: Biome,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Geometry {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Rings of the polygon, only the outer ring for cells. Rings end on their first point.
+    pub coordinates: Vec<Vec<[f64; 2]>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Feature {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub geometry: Geometry,
+    pub properties: CellProperties,
+}
+
+/// The cells of a map as a GeoJSON feature collection, one polygon feature per cell. Coordinates
+/// are in map units with the y axis pointing down like the map images.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeatureCollection {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub features: Vec<Feature>,
+}
+
+impl FeatureCollection {
+    pub fn from_cells(cells: &HashMap<IndexPoint, Cell<CellData>>) -> Self {
+        let features = sorted_keys(cells)
+            .into_iter()
+            .map(|key| &cells[&key])
+            .filter(|cell| !cell.polygon.is_empty())
+            .map(|cell| {
+                let mut ring = cell.polygon.iter().map(|p| [p.x, p.y]).collect::<Vec<_>>();
+                if ring.first() != ring.last() {
+                    ring.push(ring[0]);
+                }
+
+                Feature {
+                    kind: "Feature".to_string(),
+                    geometry: Geometry {
+                        kind: "Polygon".to_string(),
+                        coordinates: vec![ring],
+                    },
+                    properties: CellProperties {
+                        center: [cell.position.x.into_inner(), cell.position.y.into_inner()],
+                        height: cell.data.height,
+                        moisture: cell.data.moisture,
+                        temperature: cell.data.temperature,
+                        biome: cell.data.biome,
+                    },
+                }
+            })
+            .collect();
+
+        Self {
+            kind: "FeatureCollection".to_string(),
+            features,
+        }
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let file = BufReader::new(fs::File::open(path)?);
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(fs::File::create(path)?);
+        Ok(serde_json::to_writer_pretty(file, self)?)
+    }
+}
+
+/// A cell with everything the generator knows about it. Points are stored as plain pairs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedCell {
+    pub position: (f64, f64),
+    pub polygon: Vec<(f64, f64)>,
+    pub neighbors: Vec<(f64, f64)>,
+    pub height: f64,
+    pub moisture: f64,
+    pub temperature: f64,
+    pub biome: Biome,
+}
+
+/// Dump of the full cell graph of a map, which loads back into the same cells it was made from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CellGraph {
+    pub config: GeneratorConfig,
+    pub cells: Vec<SavedCell>,
+}
+
+fn pair(point: IndexPoint) -> (f64, f64) {
+    (point.x.into_inner(), point.y.into_inner())
+}
+
+fn index_point((x, y): (f64, f64)) -> IndexPoint {
+    IndexPoint::new(OrderedFloat(x), OrderedFloat(y))
+}
+
+impl CellGraph {
+    pub fn new(config: &GeneratorConfig, cells: &HashMap<IndexPoint, Cell<CellData>>) -> Self {
+        let cells = sorted_keys(cells)
+            .into_iter()
+            .map(|key| {
+                let cell = &cells[&key];
+                SavedCell {
+                    position: pair(cell.position),
+                    polygon: cell.polygon.iter().map(|p| (p.x, p.y)).collect(),
+                    neighbors: cell.neighbors.iter().cloned().map(pair).collect(),
+                    height: cell.data.height,
+                    moisture: cell.data.moisture,
+                    temperature: cell.data.temperature,
+                    biome: cell.data.biome,
+                }
+            })
+            .collect();
+
+        Self {
+            config: config.clone(),
+            cells,
+        }
+    }
+
+    /// Rebuild the cells, ready to be handed to a `Generator` again.
+    pub fn cells(&self) -> HashMap<IndexPoint, Cell<CellData>> {
+        self.cells
+            .iter()
+            .map(|saved| {
+                let position = index_point(saved.position);
+                let cell = Cell {
+                    position,
+                    polygon: saved
+                        .polygon
+                        .iter()
+                        .map(|&(x, y)| Point::new(x, y))
+                        .collect(),
+                    neighbors: saved.neighbors.iter().cloned().map(index_point).collect(),
+                    data: CellData {
+                        height: saved.height,
+                        moisture: saved.moisture,
+                        temperature: saved.temperature,
+                        biome: saved.biome,
+                        ..Default::default()
+                    },
+                };
+                (position, cell)
+            })
+            .collect()
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let file = fs::File::open(path)
+            .with_context(|_| format_err!("Failed to open cell graph {:?}", path))?;
+
+        ron::de::from_reader(BufReader::new(file))
+            .map_err(|e| format_err!("Unable to parse cell graph {:?}: {}", path, e))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let ron = to_string_pretty(self, PrettyConfig::default())
+            .map_err(|e| format_err!("Unable to serialize cell graph: {}", e))?;
+
+        fs::write(path, ron.as_bytes())
+            .with_context(|_| format_err!("Failed to write cell graph {:?}", path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::super::{ClimateSettings, Generator, TerrainStrategy};
+    use super::*;
+
+    fn map() -> (GeneratorConfig, HashMap<IndexPoint, Cell<CellData>>) {
+        let config = GeneratorConfig {
+            num_points: 200,
+            ..Default::default()
+        };
+        let mut generator = Generator::new(StdRng::seed_from_u64(9));
+        let mut cells = generator.run(&config, &TerrainStrategy::default());
+        generator.assign_biomes(&config, &ClimateSettings::default(), &mut cells);
+        (config, cells)
+    }
+
+    #[test]
+    fn heightmap_round_trips_through_png16() {
+        let mut heightmap = Heightmap::new(32, 2.);
+        for y in 0..32 {
+            for x in 0..32 {
+                heightmap.set(x, y, (x * 32 + y) as f64 / 1023.);
+            }
+        }
+
+        let path = std::env::temp_dir().join("survival_heightmap16.png");
+        heightmap.save_png16(&path).unwrap();
+        let loaded = Heightmap::load_png16(&path, 2.).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.size(), 32);
+        assert_eq!(loaded.scale(), 2.);
+        let step = 1. / f64::from(u16::max_value());
+        for (a, b) in heightmap.heights().iter().zip(loaded.heights()) {
+            assert!((a - b).abs() <= step / 2.);
+        }
+    }
+
+    #[test]
+    fn geojson_round_trip() {
+        let (_, cells) = map();
+        let collection = FeatureCollection::from_cells(&cells);
+        assert!(!collection.features.is_empty());
+        for feature in &collection.features {
+            let ring = &feature.geometry.coordinates[0];
+            assert!(ring.len() >= 4);
+            assert_eq!(ring.first(), ring.last());
+
+            let center = feature.properties.center;
+            let key = IndexPoint::new(OrderedFloat(center[0]), OrderedFloat(center[1]));
+            assert_eq!(cells[&key].data.biome, feature.properties.biome);
+        }
+
+        let path = std::env::temp_dir().join("survival_cells.geojson");
+        collection.write(&path).unwrap();
+        let loaded = FeatureCollection::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.features.len(), collection.features.len());
+        for (a, b) in collection.features.iter().zip(&loaded.features) {
+            assert_eq!(a.properties.biome, b.properties.biome);
+            assert!((a.properties.height - b.properties.height).abs() < 1e-9);
+            let ring = a.geometry.coordinates[0].iter();
+            for (p, q) in ring.zip(b.geometry.coordinates[0].iter()) {
+                assert!((p[0] - q[0]).abs() < 1e-9 && (p[1] - q[1]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn cell_graph_round_trip() {
+        let (config, cells) = map();
+        let graph = CellGraph::new(&config, &cells);
+
+        let path = std::env::temp_dir().join("survival_cell_graph.ron");
+        graph.write(&path).unwrap();
+        let loaded = CellGraph::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, graph);
+
+        let restored = loaded.cells();
+        assert_eq!(restored.len(), cells.len());
+        for (key, cell) in &cells {
+            let other = &restored[key];
+            assert_eq!(other.polygon, cell.polygon);
+            assert_eq!(other.neighbors, cell.neighbors);
+            assert_eq!(other.data.height, cell.data.height);
+            assert_eq!(other.data.biome, cell.data.biome);
+            // Every neighbor is a cell of the restored graph
+            assert!(other.neighbors.iter().all(|n| restored.contains_key(n)));
+        }
+        assert_eq!(CellGraph::new(&loaded.config, &restored), graph);
+    }
+}
@@ -0,0 +1,615 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use amethyst::core::math as na;
+use ordered_float::OrderedFloat;
+
+use super::{convert_point, sorted_keys, Cell, CellData, IndexPoint, Point, SEA_LEVEL};
+use crate::components::ObstructionType;
+
+/// Difference of heights below which a cell is not considered flooded.
+const FLOOD_EPSILON: f64 = 1e-9;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HydrologySettings {
+    /// Number of cells which have to drain through a cell for a river to run through it.
+    pub river_threshold: f64,
+    /// Width of a river at the threshold in map units. Rivers widen with the root of their flow.
+    pub river_width: f64,
+    /// Depth of a river at the threshold. Rivers deepen with the root of their flow.
+    pub river_depth: f64,
+    /// Current of a river which falls by the whole height of the map over one map unit.
+    pub current_scale: f64,
+    /// Depth of water over a height difference of the whole map, used for lakes and the sea.
+    pub depth_scale: f64,
+}
+
+impl Default for HydrologySettings {
+    fn default() -> Self {
+        Self {
+            river_threshold: 30.0,
+            river_width: 1.5,
+            river_depth: 1.0,
+            current_scale: 50.0,
+            depth_scale: 20.0,
+        }
+    }
+}
+
+/// Depression of the island filled up with water to the height where it spills over.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Lake {
+    pub cells: Vec<IndexPoint>,
+    /// Height of the water surface.
+    pub surface: f64,
+}
+
+/// Straight stretch of a river along the edge between two corners of a cell.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RiverSegment {
+    pub from: Point,
+    pub to: Point,
+    pub width: f64,
+    pub depth: f64,
+    pub current: f64,
+}
+
+/// River from its source to where it flows into the sea, a lake or a bigger river.
+#[derive(Clone, PartialEq, Debug)]
+pub struct River {
+    /// Cells the river runs through, the last one being its mouth.
+    pub cells: Vec<IndexPoint>,
+    pub segments: Vec<RiverSegment>,
+}
+
+/// Where the water of the island flows. Every cell drains into one of its neighbors until the
+/// water reaches the sea, depressions on the way are filled up into lakes.
+#[derive(Debug)]
+pub struct Hydrology {
+    settings: HydrologySettings,
+    downhill: HashMap<IndexPoint, IndexPoint>,
+    /// Height of the water surface of every cell, which is above the ground of flooded cells.
+    surface: HashMap<IndexPoint, f64>,
+    flow: HashMap<IndexPoint, f64>,
+    lakes: Vec<Lake>,
+    rivers: Vec<River>,
+    /// River segments touching the corners of every cell, as indices of the river and segment.
+    river_segments: HashMap<IndexPoint, Vec<(usize, usize)>>,
+}
+
+impl Hydrology {
+    pub fn new(settings: &HydrologySettings, cells: &HashMap<IndexPoint, Cell<CellData>>) -> Self {
+        let mut hydrology = Self {
+            settings: *settings,
+            downhill: HashMap::new(),
+            surface: HashMap::new(),
+            flow: HashMap::new(),
+            lakes: Vec::new(),
+            rivers: Vec::new(),
+            river_segments: HashMap::new(),
+        };
+
+        let order = hydrology.fill_depressions(cells);
+        hydrology.accumulate_flow(&order);
+        hydrology.find_lakes(cells);
+        hydrology.trace_rivers(cells);
+        hydrology
+    }
+
+    /// Cell the water of a cell flows into. Cells in the sea drain nowhere.
+    pub fn downhill(&self, cell: &IndexPoint) -> Option<IndexPoint> {
+        self.downhill.get(cell).cloned()
+    }
+
+    /// Number of cells draining through a cell, including the cell itself.
+    pub fn flow(&self, cell: &IndexPoint) -> f64 {
+        self.flow.get(cell).cloned().unwrap_or(0.)
+    }
+
+    pub fn lakes(&self) -> &[Lake] {
+        &self.lakes
+    }
+
+    pub fn rivers(&self) -> &[River] {
+        &self.rivers
+    }
+
+    /// Depth of the lake covering a cell, if it is flooded.
+    pub fn lake_depth(&self, cell: &IndexPoint, height: f64) -> Option<f64> {
+        let surface = *self.surface.get(cell)?;
+        if height >= SEA_LEVEL && surface - height > FLOOD_EPSILON {
+            Some((surface - height) * self.settings.depth_scale)
+        } else {
+            None
+        }
+    }
+
+    /// Sea over ground of the given height.
+    pub fn sea(&self, height: f64) -> ObstructionType {
+        ObstructionType::Liquid {
+            depth: ((SEA_LEVEL - height).max(0.) * self.settings.depth_scale) as f32,
+            current: 0.,
+        }
+    }
+
+    /// Water at a point inside of a cell, as the obstruction a tile there should get.
+    pub fn liquid_at(&self, cell: &Cell<CellData>, point: Point) -> Option<ObstructionType> {
+        let height = cell.data.height;
+        if height < SEA_LEVEL {
+            return Some(self.sea(height));
+        }
+        if let Some(depth) = self.lake_depth(&cell.position, height) {
+            return Some(ObstructionType::Liquid {
+                depth: depth as f32,
+                current: 0.,
+            });
+        }
+
+        self.river_segments
+            .get(&cell.position)
+            .into_iter()
+            .flatten()
+            .map(|&(river, segment)| &self.rivers[river].segments[segment])
+            .find(|segment| segment_distance(point, segment) <= segment.width / 2.)
+            .map(|segment| ObstructionType::Liquid {
+                depth: segment.depth as f32,
+                current: segment.current as f32,
+            })
+    }
+
+    /// Flood the island from the sea inward, always continuing from the lowest water surface. Each
+    /// cell drains into the cell it was flooded from, so the water of every cell reaches the sea
+    /// and cells below the surface they were reached at are under a lake. Returns the cells in the
+    /// order they were flooded, which puts every cell after the one it drains into.
+    fn fill_depressions(&mut self, cells: &HashMap<IndexPoint, Cell<CellData>>) -> Vec<IndexPoint> {
+        let key = |point: &IndexPoint| (point.x, point.y);
+
+        let mut open = BinaryHeap::new();
+        let keys = sorted_keys(cells);
+        for point in &keys {
+            let height = cells[point].data.height;
+            if height < SEA_LEVEL {
+                self.surface.insert(*point, height);
+                open.push(Reverse((OrderedFloat(height), key(point))));
+            }
+        }
+
+        // Without a sea the water drains out through the lowest cell
+        if open.is_empty() {
+            if let Some(lowest) = keys
+                .iter()
+                .min_by_key(|point| OrderedFloat(cells[point].data.height))
+            {
+                let height = cells[lowest].data.height;
+                self.surface.insert(*lowest, height);
+                open.push(Reverse((OrderedFloat(height), key(lowest))));
+            }
+        }
+
+        let mut order = Vec::with_capacity(cells.len());
+        while let Some(Reverse((surface, (x, y)))) = open.pop() {
+            let point = IndexPoint::new(x, y);
+            order.push(point);
+
+            for neighbor in &cells[&point].neighbors {
+                if self.surface.contains_key(neighbor) {
+                    continue;
+                }
+
+                let surface = cells[neighbor].data.height.max(surface.into_inner());
+                self.surface.insert(*neighbor, surface);
+                self.downhill.insert(*neighbor, point);
+                open.push(Reverse((OrderedFloat(surface), key(neighbor))));
+            }
+        }
+        order
+    }
+
+    fn accumulate_flow(&mut self, order: &[IndexPoint]) {
+        for point in order.iter().rev() {
+            let flow = {
+                let flow = self.flow.entry(*point).or_insert(0.);
+                *flow += 1.;
+                *flow
+            };
+            if let Some(downhill) = self.downhill.get(point) {
+                *self.flow.entry(*downhill).or_insert(0.) += flow;
+            }
+        }
+    }
+
+    /// Group the flooded cells into lakes of connected cells sharing a water surface.
+    fn find_lakes(&mut self, cells: &HashMap<IndexPoint, Cell<CellData>>) {
+        let mut seen = HashSet::new();
+        for point in sorted_keys(cells) {
+            if seen.contains(&point) || self.lake_depth(&point, cells[&point].data.height).is_none()
+            {
+                continue;
+            }
+
+            let surface = self.surface[&point];
+            let mut lake = Vec::new();
+            let mut queue = VecDeque::new();
+            seen.insert(point);
+            queue.push_back(point);
+            while let Some(point) = queue.pop_front() {
+                lake.push(point);
+                for neighbor in &cells[&point].neighbors {
+                    let flooded = self
+                        .lake_depth(neighbor, cells[neighbor].data.height)
+                        .is_some();
+                    if flooded
+                        && (self.surface[neighbor] - surface).abs() <= FLOOD_EPSILON
+                        && seen.insert(*neighbor)
+                    {
+                        queue.push_back(*neighbor);
+                    }
+                }
+            }
+
+            self.lakes.push(Lake {
+                cells: lake,
+                surface,
+            });
+        }
+    }
+
+    /// Follow the water downhill from every cell where enough of it gathers for a river to start,
+    /// until it reaches the sea, a lake or another river.
+    fn trace_rivers(&mut self, cells: &HashMap<IndexPoint, Cell<CellData>>) {
+        let is_river = |hydrology: &Self, point: &IndexPoint| {
+            let height = cells[point].data.height;
+            height >= SEA_LEVEL
+                && hydrology.lake_depth(point, height).is_none()
+                && hydrology.flow(point) >= hydrology.settings.river_threshold
+        };
+
+        let keys = sorted_keys(cells);
+        let mut fed = HashSet::new();
+        let mut corners: HashMap<IndexPoint, Vec<IndexPoint>> = HashMap::new();
+        for point in &keys {
+            if let Some(downhill) = self.downhill.get(point) {
+                if is_river(self, point) {
+                    fed.insert(*downhill);
+                }
+            }
+            for corner in &cells[point].polygon {
+                corners
+                    .entry(convert_point(*corner))
+                    .or_default()
+                    .push(*point);
+            }
+        }
+
+        let mut traced = HashSet::new();
+        for source in keys {
+            if !is_river(self, &source) || fed.contains(&source) {
+                continue;
+            }
+
+            let mut path = vec![source];
+            let mut point = source;
+            traced.insert(point);
+            while let Some(next) = self.downhill(&point) {
+                path.push(next);
+                if !is_river(self, &next) || !traced.insert(next) {
+                    break;
+                }
+                point = next;
+            }
+
+            let river = River {
+                segments: self.route(cells, &path),
+                cells: path,
+            };
+            for (index, segment) in river.segments.iter().enumerate() {
+                let mut touched = [segment.from, segment.to]
+                    .iter()
+                    .filter_map(|corner| corners.get(&convert_point(*corner)))
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                touched.sort_by(|a, b| (a.x, a.y).cmp(&(b.x, b.y)));
+                touched.dedup();
+                for cell in touched {
+                    self.river_segments
+                        .entry(cell)
+                        .or_default()
+                        .push((self.rivers.len(), index));
+                }
+            }
+            self.rivers.push(river);
+        }
+    }
+
+    /// Run a river along the edges of the cells on its path. In every cell it follows the border
+    /// from where it came in to the closest corner the cell shares with the next one, the source
+    /// cell is entered at the corner farthest from there.
+    fn route(
+        &self,
+        cells: &HashMap<IndexPoint, Cell<CellData>>,
+        path: &[IndexPoint],
+    ) -> Vec<RiverSegment> {
+        let mut segments = Vec::new();
+        let mut corner: Option<Point> = None;
+        for step in path.windows(2) {
+            let (from, to) = (&cells[&step[0]], &cells[&step[1]]);
+            let polygon = &from.polygon;
+            if polygon.is_empty() {
+                continue;
+            }
+
+            let mut exits = (0..polygon.len())
+                .filter(|&index| to.polygon.contains(&polygon[index]))
+                .collect::<Vec<_>>();
+            if exits.is_empty() {
+                exits.push(closest_corner(polygon, to_point(to.position)));
+            }
+
+            let entry = match corner {
+                Some(point) => match polygon.iter().position(|vertex| *vertex == point) {
+                    Some(index) => index,
+                    None => {
+                        // The cells do not share the corner, so cross over to the closest one
+                        let index = closest_corner(polygon, point);
+                        segments.push(self.segment(from, to, point, polygon[index]));
+                        index
+                    }
+                },
+                None => farthest_corner(polygon, polygon[exits[0]]),
+            };
+
+            let walk = exits
+                .iter()
+                .map(|&exit| border_walk(polygon, entry, exit))
+                .min_by_key(|walk| OrderedFloat(walk_length(polygon, walk)))
+                .unwrap();
+            for edge in walk.windows(2) {
+                segments.push(self.segment(from, to, polygon[edge[0]], polygon[edge[1]]));
+            }
+            corner = walk.last().map(|&index| polygon[index]);
+        }
+        segments
+    }
+
+    /// Segment of the river flowing from one cell to another. The current follows the slope of
+    /// the water between the centers of the cells.
+    fn segment(
+        &self,
+        from: &Cell<CellData>,
+        to: &Cell<CellData>,
+        start: Point,
+        end: Point,
+    ) -> RiverSegment {
+        let size = (self.flow(&from.position) / self.settings.river_threshold).sqrt();
+        let drop = (self.surface[&from.position] - self.surface[&to.position]).max(0.);
+        let length =
+            na::distance(&to_point(from.position), &to_point(to.position)).max(std::f64::EPSILON);
+        RiverSegment {
+            from: start,
+            to: end,
+            width: self.settings.river_width * size,
+            depth: self.settings.river_depth * size,
+            current: self.settings.current_scale * drop / length,
+        }
+    }
+}
+
+fn to_point(point: IndexPoint) -> Point {
+    Point::new(point.x.into_inner(), point.y.into_inner())
+}
+
+fn closest_corner(polygon: &[Point], point: Point) -> usize {
+    (0..polygon.len())
+        .min_by_key(|&index| OrderedFloat(na::distance(&polygon[index], &point)))
+        .unwrap()
+}
+
+fn farthest_corner(polygon: &[Point], point: Point) -> usize {
+    (0..polygon.len())
+        .max_by_key(|&index| OrderedFloat(na::distance(&polygon[index], &point)))
+        .unwrap()
+}
+
+/// Corners passed going around a polygon from one corner to another, in the direction with the
+/// shorter way.
+fn border_walk(polygon: &[Point], from: usize, to: usize) -> Vec<usize> {
+    let count = polygon.len();
+    let forward = (0..=(to + count - from) % count)
+        .map(|step| (from + step) % count)
+        .collect::<Vec<_>>();
+    let backward = (0..=(from + count - to) % count)
+        .map(|step| (from + count - step) % count)
+        .collect::<Vec<_>>();
+
+    if walk_length(polygon, &backward) < walk_length(polygon, &forward) {
+        backward
+    } else {
+        forward
+    }
+}
+
+fn walk_length(polygon: &[Point], walk: &[usize]) -> f64 {
+    walk.windows(2)
+        .map(|edge| na::distance(&polygon[edge[0]], &polygon[edge[1]]))
+        .sum()
+}
+
+/// Distance from a point to the closest point of a river segment.
+fn segment_distance(point: Point, segment: &RiverSegment) -> f64 {
+    let along = segment.to - segment.from;
+    let length = along.norm_squared();
+    if length <= std::f64::EPSILON {
+        return na::distance(&point, &segment.from);
+    }
+
+    let t = ((point - segment.from).dot(&along) / length)
+        .max(0.)
+        .min(1.);
+    na::distance(&point, &(segment.from + along * t))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::super::{Generator, GeneratorConfig, IslandGeneratorSettings};
+    use super::*;
+
+    /// A row of square cells ten units wide with the given heights.
+    fn row(heights: &[f64]) -> HashMap<IndexPoint, Cell<CellData>> {
+        let center =
+            |x: usize| IndexPoint::new(OrderedFloat(x as f64 * 10. + 5.), OrderedFloat(5.));
+
+        let mut cells = HashMap::new();
+        for (x, height) in heights.iter().enumerate() {
+            let left = x as f64 * 10.;
+            let mut neighbors = Vec::new();
+            if x > 0 {
+                neighbors.push(center(x - 1));
+            }
+            if x + 1 < heights.len() {
+                neighbors.push(center(x + 1));
+            }
+
+            cells.insert(
+                center(x),
+                Cell {
+                    position: center(x),
+                    polygon: vec![
+                        Point::new(left, 0.),
+                        Point::new(left + 10., 0.),
+                        Point::new(left + 10., 10.),
+                        Point::new(left, 10.),
+                    ],
+                    neighbors,
+                    data: CellData {
+                        height: *height,
+                        ..Default::default()
+                    },
+                },
+            );
+        }
+        cells
+    }
+
+    fn liquid(hydrology: &Hydrology, cell: &Cell<CellData>, point: Point) -> Option<(f32, f32)> {
+        match hydrology.liquid_at(cell, point) {
+            Some(ObstructionType::Liquid { depth, current }) => Some((depth, current)),
+            Some(other) => panic!("Expected a liquid, got {:?}", other),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn water_runs_to_the_sea() {
+        let cells = row(&[0.2, 0.6, 0.7, 0.65, 0.8, 0.9, 1.0]);
+        let keys = sorted_keys(&cells);
+        let hydrology = Hydrology::new(
+            &HydrologySettings {
+                river_threshold: 2.,
+                ..Default::default()
+            },
+            &cells,
+        );
+
+        assert_eq!(hydrology.downhill(&keys[0]), None);
+        for x in 1..keys.len() {
+            assert_eq!(hydrology.downhill(&keys[x]), Some(keys[x - 1]));
+            assert_eq!(hydrology.flow(&keys[x]), (keys.len() - x) as f64);
+        }
+        assert_eq!(hydrology.flow(&keys[0]), keys.len() as f64);
+
+        // The dip is filled up to the cell it spills over
+        assert_eq!(
+            hydrology.lakes(),
+            &[Lake {
+                cells: vec![keys[3]],
+                surface: 0.7
+            }]
+        );
+
+        // One river runs into the lake, another one from its outlet into the sea
+        let rivers = hydrology
+            .rivers()
+            .iter()
+            .map(|river| river.cells.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rivers,
+            vec![
+                vec![keys[2], keys[1], keys[0]],
+                vec![keys[5], keys[4], keys[3]]
+            ]
+        );
+    }
+
+    #[test]
+    fn liquids_have_depth_and_current() {
+        let cells = row(&[0.2, 0.6, 0.7, 0.65, 0.8, 0.9, 1.0]);
+        let keys = sorted_keys(&cells);
+        let hydrology = Hydrology::new(
+            &HydrologySettings {
+                river_threshold: 2.,
+                ..Default::default()
+            },
+            &cells,
+        );
+        let at = |x: usize, point: Point| liquid(&hydrology, &cells[&keys[x]], point);
+
+        let (depth, current) = at(0, Point::new(5., 5.)).unwrap();
+        assert!((depth - 6.).abs() < 1e-4 && current == 0.);
+        let (depth, current) = at(3, Point::new(31., 1.)).unwrap();
+        assert!((depth - 1.).abs() < 1e-4 && current == 0.);
+
+        // The river from the top runs along the edges of its cells and falls by 0.1 over 10 units
+        let (_, current) = at(5, Point::new(55., 9.7)).unwrap();
+        assert!((current - 0.5).abs() < 1e-4);
+        assert!(at(4, Point::new(45., 9.8)).is_some());
+        assert!(at(5, Point::new(55., 5.)).is_none());
+        assert!(at(6, Point::new(65., 5.)).is_none());
+    }
+
+    #[test]
+    fn island_drains_completely() {
+        let mut generator = Generator::new(rand::rngs::StdRng::from_seed([3; 32]));
+        let config = GeneratorConfig {
+            num_points: 300,
+            ..Default::default()
+        };
+        let mut cells = generator.gen_voronoi::<CellData>(&config);
+        generator.create_island(&config, &IslandGeneratorSettings::default(), &mut cells);
+
+        let hydrology = Hydrology::new(&HydrologySettings::default(), &cells);
+        let drained: f64 = cells
+            .keys()
+            .filter(|point| hydrology.downhill(point).is_none())
+            .map(|point| hydrology.flow(point))
+            .sum();
+        assert_eq!(drained, cells.len() as f64);
+
+        let corners = cells
+            .values()
+            .flat_map(|cell| cell.polygon.iter().cloned().map(convert_point))
+            .collect::<HashSet<_>>();
+        for river in hydrology.rivers() {
+            assert!(!river.segments.is_empty());
+            for segment in &river.segments {
+                assert!(corners.contains(&convert_point(segment.from)));
+                assert!(corners.contains(&convert_point(segment.to)));
+            }
+            for pair in river.segments.windows(2) {
+                assert_eq!(pair[0].to, pair[1].from);
+            }
+            let mouth = &cells[river.cells.last().unwrap()];
+            assert!(
+                mouth.data.height < SEA_LEVEL
+                    || hydrology
+                        .lake_depth(&mouth.position, mouth.data.height)
+                        .is_some()
+                    || hydrology.flow(&mouth.position)
+                        >= HydrologySettings::default().river_threshold
+            );
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use array_init::array_init;
+
+use super::{
+    inside_poly, Biome, Cell, CellData, CellIndex, ClimateSettings, Generator, GeneratorConfig,
+    Hydrology, HydrologySettings, IndexPoint, MapSeed, Point, TerrainStrategy,
+};
+use crate::components::ObstructionType;
+use crate::coords::{ChunkPos, TilePos, CHUNK_SIZE};
+
+/// Number of tiles along one unit of the map.
+pub const TILES_PER_UNIT: f64 = 2.0;
+
+/// What the ground of a tile is made of.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Terrain {
+    Water,
+    Sand,
+    Grass,
+    Forest,
+    Rock,
+}
+
+impl From<Biome> for Terrain {
+    fn from(biome: Biome) -> Self {
+        match biome {
+            Biome::Ocean => Terrain::Water,
+            Biome::Beach | Biome::Desert => Terrain::Sand,
+            Biome::Snow | Biome::Tundra => Terrain::Rock,
+            Biome::Grassland | Biome::Savanna => Terrain::Grass,
+            Biome::Taiga
+            | Biome::TemperateForest
+            | Biome::TemperateRainforest
+            | Biome::TropicalForest
+            | Biome::TropicalRainforest
+            | Biome::Swamp => Terrain::Forest,
+        }
+    }
+}
+
+/// Terrain of every tile of a chunk, indexed like its tile layers.
+pub type ChunkTerrain = [[Terrain; CHUNK_SIZE as usize]; CHUNK_SIZE as usize];
+
+/// The island of a world seed, generated once and then cut into the chunks of the world. The
+/// middle of the island is at the origin of the world, everything beyond the map is open sea.
+pub struct IslandMap {
+    config: GeneratorConfig,
+    terrain: TerrainStrategy,
+    cells: HashMap<IndexPoint, Cell<CellData>>,
+    index: CellIndex,
+    hydrology: Hydrology,
+}
+
+impl IslandMap {
+    pub fn generate(seed: u64, terrain: &TerrainStrategy) -> Self {
+        Self::with_config(seed, terrain, GeneratorConfig::default())
+    }
+
+    pub fn with_config(seed: u64, terrain: &TerrainStrategy, config: GeneratorConfig) -> Self {
+        let mut generator = Generator::new(MapSeed::from(seed).rng());
+        let mut cells = generator.run(&config, terrain);
+        generator.assign_biomes(&config, &ClimateSettings::default(), &mut cells);
+        let hydrology = Hydrology::new(&HydrologySettings::default(), &cells);
+
+        let index = CellIndex::new(&config, &cells);
+
+        Self {
+            config,
+            terrain: terrain.clone(),
+            cells,
+            index,
+            hydrology,
+        }
+    }
+
+    /// Strategy the land of the island was shaped with.
+    pub fn strategy(&self) -> &TerrainStrategy {
+        &self.terrain
+    }
+
+    pub fn hydrology(&self) -> &Hydrology {
+        &self.hydrology
+    }
+
+    /// Point of the map in the middle of a tile. Map images have their y axis pointing down, so
+    /// it is flipped for the world to look like the previews of the terrain generator.
+    pub fn point_of(&self, tile: TilePos) -> Point {
+        let half = self.config.box_size / 2.;
+        Point::new(
+            half + (f64::from(tile.x) + 0.5) / TILES_PER_UNIT,
+            half - (f64::from(tile.y) + 0.5) / TILES_PER_UNIT,
+        )
+    }
+
+    /// Cell whose polygon contains a point of the map.
+    pub fn cell_at(&self, point: Point) -> Option<&Cell<CellData>> {
+        self.index.cell_at(&self.cells, point)
+    }
+
+    pub fn terrain(&self, tile: TilePos) -> Terrain {
+        let point = self.point_of(tile);
+        self.terrain_in(self.cell_at(point), point)
+    }
+
+    /// Water covering a tile, with its depth and current.
+    pub fn liquid_at(&self, tile: TilePos) -> Option<ObstructionType> {
+        let point = self.point_of(tile);
+        match self.cell_at(point) {
+            Some(cell) => self.hydrology.liquid_at(cell, point),
+            None => Some(self.hydrology.sea(0.)),
+        }
+    }
+
+    /// Terrain of every tile of a chunk. Only the cells overlapping the chunk are looked at.
+    pub fn chunk_terrain(&self, chunk: ChunkPos) -> ChunkTerrain {
+        let origin = chunk.origin();
+        let first = self.point_of(origin);
+        let last = self.point_of(TilePos::new(
+            origin.x + CHUNK_SIZE - 1,
+            origin.y + CHUNK_SIZE - 1,
+            origin.z,
+        ));
+        let min = Point::new(first.x.min(last.x), first.y.min(last.y));
+        let max = Point::new(first.x.max(last.x), first.y.max(last.y));
+
+        let candidates = self.index.overlapping(min, max);
+
+        array_init(|x| {
+            array_init(|y| {
+                let point = self.point_of(TilePos::from_local(chunk, x, y));
+                let cell = candidates
+                    .iter()
+                    .filter(|bounds| bounds.overlaps(point, point))
+                    .map(|bounds| &self.cells[&bounds.key])
+                    .find(|cell| inside_poly(point, &cell.polygon));
+                self.terrain_in(cell, point)
+            })
+        })
+    }
+
+    fn terrain_in(&self, cell: Option<&Cell<CellData>>, point: Point) -> Terrain {
+        match cell {
+            Some(cell) if self.hydrology.liquid_at(cell, point).is_none() => {
+                Terrain::from(cell.data.biome)
+            }
+            _ => Terrain::Water,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn island() -> IslandMap {
+        IslandMap::with_config(
+            11,
+            &TerrainStrategy::default(),
+            GeneratorConfig {
+                num_points: 300,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn cells_contain_their_center() {
+        let island = island();
+        for bounds in island.index.bounds() {
+            let center = Point::new(bounds.key.x.into_inner(), bounds.key.y.into_inner());
+            assert_eq!(island.cell_at(center).unwrap().position, bounds.key);
+        }
+    }
+
+    #[test]
+    fn chunks_match_their_tiles() {
+        let island = island();
+        for &chunk in &[ChunkPos::new(0, 0, 0), ChunkPos::new(-3, 2, 0)] {
+            let terrain = island.chunk_terrain(chunk);
+            for x in 0..CHUNK_SIZE as usize {
+                for y in 0..CHUNK_SIZE as usize {
+                    let tile = TilePos::from_local(chunk, x, y);
+                    assert_eq!(terrain[x][y], island.terrain(tile));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn island_rises_from_the_sea() {
+        let island = island();
+        assert_ne!(island.terrain(TilePos::new(0, 0, 0)), Terrain::Water);
+        assert!(island.liquid_at(TilePos::new(0, 0, 0)).is_none());
+
+        let far = TilePos::new(10_000, -10_000, 0);
+        assert_eq!(island.terrain(far), Terrain::Water);
+        match island.liquid_at(far) {
+            Some(ObstructionType::Liquid { depth, current }) => {
+                assert!(depth > 0. && current == 0.)
+            }
+            other => panic!("Expected open sea, got {:?}", other),
+        }
+    }
+}
@@ -1,9 +1,38 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use ordered_float::OrderedFloat;
 use rand::Rng;
 use rayon::prelude::*;
-
+use serde::{Deserialize, Serialize};
+
+pub use self::biome::Biome;
+pub use self::erosion::{ErosionSettings, Heightmap};
+pub use self::export::{CellGraph, FeatureCollection};
+pub use self::hydrology::{Hydrology, HydrologySettings};
+pub use self::island::{IslandMap, Terrain};
+pub use self::scatter::{ScatterKind, ScatterRule, ScatterRules, Scattered, Species, SCATTER_FILE};
+pub use self::seed::MapSeed;
+pub use self::spatial::CellIndex;
+pub use self::terrain::TerrainGenerator;
+pub use crate::settings::{
+    ArchipelagoSettings, ContinentSettings, FlatSettings, IslandGeneratorSettings, TerrainStrategy,
+};
+
+pub mod biome;
+pub mod erosion;
+pub mod export;
+pub mod hydrology;
+pub mod island;
+pub mod scatter;
+pub mod seed;
+pub mod spatial;
+pub mod terrain;
+
+/// Cells below this height are under water.
+pub const SEA_LEVEL: f64 = 0.5;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GeneratorConfig {
     pub num_points: usize,
     pub num_lloyd: usize,
@@ -27,27 +56,50 @@ fn convert_point(other: Point) -> IndexPoint {
     IndexPoint::new(OrderedFloat(other.x), OrderedFloat(other.y))
 }
 
+/// Whether a point is inside of a polygon, by counting the edges a ray from the point crosses.
 fn inside_poly(target: Point, points: &[Point]) -> bool {
-    let mut c: i32 = 0;
+    let mut inside = false;
     for i in 0..points.len() {
-        for j in (0..points.len()).rev() {
-            if (points[i].y > target.y) != (points[j].y > target.y)
-                && (target.x
-                    < (points[j].x - points[i].x) * (target.y - points[i].y)
-                        / (points[j].y - points[i].y)
-                        + points[i].x)
-            {
-                c = !c;
-            }
+        let j = if i == 0 { points.len() - 1 } else { i - 1 };
+        if (points[i].y > target.y) != (points[j].y > target.y)
+            && (target.x
+                < (points[j].x - points[i].x) * (target.y - points[i].y)
+                    / (points[j].y - points[i].y)
+                    + points[i].x)
+        {
+            inside = !inside;
         }
     }
-    c == 0
+    inside
 }
 
 #[derive(Default, Debug)]
 pub struct CellData {
     height: f64,
     used: bool,
+    /// How wet the cell is, from 0 to 1.
+    moisture: f64,
+    /// How warm the cell is, from 0 to 1.
+    temperature: f64,
+    biome: Biome,
+}
+
+impl CellData {
+    pub fn height(&self) -> f64 {
+        self.height
+    }
+
+    pub fn moisture(&self) -> f64 {
+        self.moisture
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    pub fn biome(&self) -> Biome {
+        self.biome
+    }
 }
 
 #[derive(Debug)]
@@ -64,22 +116,47 @@ pub struct Generator<R> {
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-pub struct IslandGeneratorSettings {
-    pub height: f64,
-    pub radius: f64,
-    pub sharpness: f64,
+pub struct ClimateSettings {
+    /// Temperature at sea level in the middle of the map.
+    pub temperature: f64,
+    /// How much warmer the bottom edge of the map is than the top edge.
+    pub latitude_range: f64,
+    /// How much colder the highest peak is than the sea level.
+    pub lapse_rate: f64,
+    /// Share of the moisture kept for every cell of distance from the water.
+    pub moisture_falloff: f64,
+    /// Largest random change of the moisture and temperature of a cell.
+    pub variation: f64,
 }
 
-impl Default for IslandGeneratorSettings {
+impl Default for ClimateSettings {
     fn default() -> Self {
         Self {
-            height: 1.0,
-            radius: 0.95,
-            sharpness: 0.2,
+            temperature: 0.6,
+            latitude_range: 0.3,
+            lapse_rate: 0.6,
+            moisture_falloff: 0.85,
+            variation: 0.1,
         }
     }
 }
 
+/// What map images are colored by.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ImageMode {
+    /// Water in blue and land in shades of red by height.
+    Height,
+    /// Every cell in the color of its biome.
+    Biome,
+}
+
+/// Keys of all cells in a fixed order, so results do not depend on the order of the map.
+fn sorted_keys<T>(cells: &HashMap<IndexPoint, Cell<T>>) -> Vec<IndexPoint> {
+    let mut keys = cells.keys().cloned().collect::<Vec<_>>();
+    keys.sort_by(|a, b| (a.x, a.y).cmp(&(b.x, b.y)));
+    keys
+}
+
 impl<R> Generator<R>
 where
     R: Rng + Send + Sync + Clone + ?Sized,
@@ -91,7 +168,16 @@ where
         }
     }
 
-    pub fn run(&mut self, _: &GeneratorConfig) {}
+    /// Generate the cells of a map and shape their land.
+    pub fn run<T: TerrainGenerator>(
+        &mut self,
+        config: &GeneratorConfig,
+        terrain: &T,
+    ) -> HashMap<IndexPoint, Cell<CellData>> {
+        let mut cells = self.gen_voronoi::<CellData>(config);
+        terrain.shape(&mut self.rng, config, &mut cells);
+        cells
+    }
 
     pub fn create_island(
         &mut self,
@@ -99,64 +185,65 @@ where
         settings: &IslandGeneratorSettings,
         cells: &mut HashMap<IndexPoint, Cell<CellData>>,
     ) {
-        //let start_cell = self.rng.gen_range(0, cells.len());
-        // Find the center polygon
-
-        use amethyst::core::math as na;
-        let mut center = Point::new(0., 0.);
-        let target = Point::new(config.box_size / 2., config.box_size / 2.);
-        for (key, _) in cells.iter() {
-            let point = Point::new(key.x.into_inner(), key.y.into_inner());
-            if na::distance(&target, &center) > na::distance(&target, &point) {
-                center = point;
+        settings.shape(&mut self.rng, config, cells);
+    }
+
+    /// Give every cell a moisture and temperature and classify its biome. Moisture falls off with
+    /// the distance from the water, temperature drops toward the top of the map and with height.
+    pub fn assign_biomes(
+        &mut self,
+        config: &GeneratorConfig,
+        settings: &ClimateSettings,
+        cells: &mut HashMap<IndexPoint, Cell<CellData>>,
+    ) {
+        // Count the steps from every cell to the closest water
+        let mut distances = HashMap::new();
+        let mut queue = VecDeque::new();
+        for (key, cell) in cells.iter() {
+            if cell.data.height < SEA_LEVEL {
+                distances.insert(*key, 0);
+                queue.push_back(*key);
             }
         }
-
-        let mut height = settings.height;
-
-        let mut queue = Vec::new();
-        queue.push(IndexPoint::new(
-            OrderedFloat(center.x),
-            OrderedFloat(center.y),
-        ));
-        cells.get_mut(&queue[0]).unwrap().data.height = height;
-
-        let mut i = 0;
-        while i < queue.len() && height > 0.01 {
-            height = cells[&queue[i]].data.height * settings.radius;
-
-            let neighbors = cells[&queue[i]].neighbors.clone();
-            neighbors.iter().for_each(|n| {
-                let cell = cells.get_mut(n).unwrap();
-                if !cell.data.used {
-                    let modifier: f64 = if settings.sharpness == 0. {
-                        1.0
-                    } else {
-                        let r: f64 = self.rng.gen();
-                        r * settings.sharpness + 1.1 - settings.sharpness
-                    };
-
-                    cell.data.height += height * modifier;
-                    cell.data.used = true;
-
-                    if cell.data.height > 1. {
-                        cell.data.height = 1.;
-                    }
-
-                    queue.push(*n);
+        while let Some(key) = queue.pop_front() {
+            let distance = distances[&key] + 1;
+            for neighbor in &cells[&key].neighbors {
+                if !distances.contains_key(neighbor) {
+                    distances.insert(*neighbor, distance);
+                    queue.push_back(*neighbor);
                 }
-            });
+            }
+        }
 
-            i += 1;
+        for key in sorted_keys(cells) {
+            let moisture_noise: f64 = self.rng.gen();
+            let temperature_noise: f64 = self.rng.gen();
+
+            let cell = cells.get_mut(&key).unwrap();
+            let moisture = match distances.get(&key) {
+                Some(distance) => settings.moisture_falloff.powi(*distance),
+                None => 0.,
+            };
+            let latitude = key.y.into_inner() / config.box_size - 0.5;
+            let elevation = ((cell.data.height - SEA_LEVEL) / (1. - SEA_LEVEL)).max(0.);
+            let temperature = settings.temperature + latitude * settings.latitude_range
+                - elevation * settings.lapse_rate;
+
+            let data = &mut cell.data;
+            data.moisture = (moisture + (moisture_noise - 0.5) * settings.variation)
+                .max(0.)
+                .min(1.);
+            data.temperature = (temperature + (temperature_noise - 0.5) * settings.variation)
+                .max(0.)
+                .min(1.);
+            data.biome = Biome::classify(data.height, data.moisture, data.temperature);
         }
     }
 
-    pub fn gen_voronoi<T: Default>(
+    pub fn gen_voronoi<T: Default + Send>(
         &mut self,
         config: &GeneratorConfig,
     ) -> HashMap<IndexPoint, Cell<T>> {
-        let mut ret = HashMap::new();
-
         let mut vor_pts = Vec::new();
         for _ in 0..config.num_points {
             let p = self.sample_point(config);
@@ -195,46 +282,40 @@ where
             .map(|t| (dt_points[t.0], dt_points[t.1], dt_points[t.2]))
             .collect::<Vec<_>>();
 
-        for cell in &diagram.cells() {
-            let mut neighbors = HashSet::new();
-
-            let point = IndexPoint::new(cell.centroid.x, cell.centroid.y);
-            for triangle in &triangles {
-                if triangle.0 == point || triangle.1 == point || triangle.2 == point {
-                    neighbors.insert(triangle.0);
-                    neighbors.insert(triangle.1);
-                    neighbors.insert(triangle.2);
-                }
-            }
-            neighbors.remove(&point);
-            let mut n_vec = neighbors.drain().collect::<Vec<IndexPoint>>();
-            n_vec.sort_by(|a, b| {
-                use std::cmp::Ordering;
-                let x = a.x.cmp(&b.x);
-                let y = a.x.cmp(&b.y);
-                if x == Ordering::Equal && y == Ordering::Equal {
-                    Ordering::Equal
-                } else {
-                    x
-                }
-            });
+        // The neighbors of a cell are the other corners of the triangles around its center,
+        // collected in a single pass over the triangles
+        let mut adjacency: HashMap<IndexPoint, HashSet<IndexPoint>> =
+            HashMap::with_capacity(dt_points.len());
+        for &(a, b, c) in &triangles {
+            adjacency.entry(a).or_default().extend(&[b, c]);
+            adjacency.entry(b).or_default().extend(&[a, c]);
+            adjacency.entry(c).or_default().extend(&[a, b]);
+        }
 
-            ret.insert(
-                point,
-                Cell {
+        diagram
+            .cells()
+            .par_iter()
+            .map(|cell| {
+                let point = IndexPoint::new(cell.centroid.x, cell.centroid.y);
+                let mut neighbors = adjacency
+                    .get(&point)
+                    .map(|found| found.iter().filter(|n| **n != point).cloned().collect())
+                    .unwrap_or_else(Vec::new);
+                neighbors.sort_by(|a, b| (a.x, a.y).cmp(&(b.x, b.y)));
+
+                let cell = Cell {
                     position: point,
                     polygon: cell
                         .points
-                        .par_iter()
+                        .iter()
                         .map(|p| Point::new(p.x(), p.y()))
                         .collect::<Vec<_>>(),
-                    neighbors: n_vec,
+                    neighbors,
                     data: T::default(),
-                },
-            );
-        }
-
-        ret
+                };
+                (point, cell)
+            })
+            .collect()
     }
 
     pub fn save_heightmap_image(
@@ -243,9 +324,31 @@ where
         path: &std::path::Path,
         cells: &HashMap<IndexPoint, Cell<CellData>>,
     ) -> std::io::Result<()> {
+        self.save_image(config, path, cells, ImageMode::Height)
+    }
+
+    pub fn save_image(
+        &self,
+        config: &GeneratorConfig,
+        path: &std::path::Path,
+        cells: &HashMap<IndexPoint, Cell<CellData>>,
+        mode: ImageMode,
+    ) -> std::io::Result<()> {
+        self.render_image(config, cells, mode).save(path)
+    }
+
+    /// Draw the cells of a map into an image with one pixel per map unit.
+    pub fn render_image(
+        &self,
+        config: &GeneratorConfig,
+        cells: &HashMap<IndexPoint, Cell<CellData>>,
+        mode: ImageMode,
+    ) -> image::RgbImage {
         let mut imgbuf = image::ImageBuffer::new(config.box_size as u32, config.box_size as u32);
 
-        for (_, cell) in cells.iter() {
+        // Neighboring polygons overlap along their edges, so the order decides which one is drawn
+        for key in sorted_keys(cells) {
+            let cell = &cells[&key];
             let mut points = cell
                 .polygon
                 .iter()
@@ -258,17 +361,22 @@ where
                 points.remove(points.len() - 1);
             }
 
-            let height_color = (cell.data.height * 255.) as u8;
-            let color = if cell.data.height < 0.5 {
-                image::Rgb([0, 191, 255])
-            } else {
-                image::Rgb([height_color, 0, 0])
+            let color = match mode {
+                ImageMode::Height => {
+                    let height_color = (cell.data.height * 255.) as u8;
+                    if cell.data.height < SEA_LEVEL {
+                        image::Rgb([0, 191, 255])
+                    } else {
+                        image::Rgb([height_color, 0, 0])
+                    }
+                }
+                ImageMode::Biome => image::Rgb(cell.data.biome.color()),
             };
 
             imageproc::drawing::draw_convex_polygon_mut(&mut imgbuf, &points, color);
         }
 
-        imgbuf.save(path)
+        imgbuf
     }
 
     fn sample_point(&mut self, config: &GeneratorConfig) -> (f64, f64) {
@@ -328,6 +436,82 @@ mod tests {
             .save_heightmap_image(&config, &Path::new("/tmp/test.png"), &cells)
             .unwrap();
     }
+
+    #[test]
+    pub fn neighbors_are_mutual() {
+        let config = GeneratorConfig {
+            num_points: 500,
+            ..Default::default()
+        };
+        let cells =
+            Generator::new(rand::rngs::StdRng::from_seed([3; 32])).gen_voronoi::<CellData>(&config);
+
+        for (key, cell) in &cells {
+            assert!(!cell.neighbors.is_empty());
+            assert!(!cell.neighbors.contains(key));
+            let order = cell
+                .neighbors
+                .iter()
+                .map(|n| (n.x, n.y))
+                .collect::<Vec<_>>();
+            assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+            for neighbor in &cell.neighbors {
+                assert!(cells[neighbor].neighbors.contains(key));
+            }
+        }
+    }
+
+    #[test]
+    pub fn inside_poly_concave() {
+        // An L shape, with the notch in the top right
+        let shape = [
+            Point::new(0., 0.),
+            Point::new(10., 0.),
+            Point::new(10., 5.),
+            Point::new(5., 5.),
+            Point::new(5., 10.),
+            Point::new(0., 10.),
+        ];
+        assert!(inside_poly(Point::new(2., 2.), &shape));
+        assert!(inside_poly(Point::new(8., 2.), &shape));
+        assert!(inside_poly(Point::new(2., 8.), &shape));
+        assert!(!inside_poly(Point::new(8., 8.), &shape));
+        assert!(!inside_poly(Point::new(-1., 2.), &shape));
+    }
+
+    fn biome_map(seed: [u8; 32]) -> Vec<(IndexPoint, Biome, f64, f64)> {
+        let mut generator = Generator::new(rand::rngs::StdRng::from_seed(seed));
+        let config = GeneratorConfig {
+            num_points: 200,
+            ..Default::default()
+        };
+
+        let mut cells = generator.gen_voronoi::<CellData>(&config);
+        generator.create_island(&config, &IslandGeneratorSettings::default(), &mut cells);
+        generator.assign_biomes(&config, &ClimateSettings::default(), &mut cells);
+
+        sorted_keys(&cells)
+            .into_iter()
+            .map(|key| {
+                let data = &cells[&key].data;
+                (key, data.biome(), data.moisture(), data.temperature())
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn biomes_are_deterministic() {
+        let first = biome_map([7; 32]);
+        assert_eq!(first, biome_map([7; 32]));
+        assert_ne!(first, biome_map([8; 32]));
+
+        // Water is always soaked, land gets drier further inland
+        for (_, biome, moisture, _) in &first {
+            if *biome == Biome::Ocean {
+                assert!(*moisture > 0.9);
+            }
+        }
+    }
 }
 
 // Generate dt
@@ -0,0 +1,337 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use amethyst::core::math::{self as na, Vector2};
+use amethyst::error::{format_err, Error, ResultExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{Biome, IslandMap, Point, Terrain, SEA_LEVEL};
+use crate::components::{ObstructionType, TreeKind};
+use crate::coords::{ChunkPos, TilePos, CHUNK_SIZE};
+
+/// Rules used to scatter objects over the surface of generated worlds.
+pub const SCATTER_FILE: &str = "resources/data/scatter.ron";
+
+/// Number of candidates tried around a point before it stops spawning new ones.
+const ATTEMPTS: usize = 30;
+
+/// What kind of object is scattered over the ground.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScatterKind {
+    Tree(TreeKind),
+    Bush,
+    Rock,
+}
+
+/// Object placed on a freshly generated chunk. Turned into an entity when the chunk activates.
+#[derive(Clone, Debug)]
+pub struct Scattered {
+    /// Position in fractional tiles.
+    pub pos: Vector2<f32>,
+    pub kind: ScatterKind,
+    pub size: f32,
+    pub obstruction: ObstructionType,
+}
+
+/// One kind of object a rule can place.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Species {
+    pub kind: ScatterKind,
+    /// How often the species is picked compared to the others of its rule.
+    pub weight: u32,
+    /// Smallest and largest size of a placed object.
+    pub size: (f32, f32),
+    pub obstruction: ObstructionType,
+}
+
+/// Objects growing in some biomes between two elevations. Elevations go from 0 at the sea level to
+/// 1 at the highest peaks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScatterRule {
+    pub biomes: Vec<Biome>,
+    #[serde(default)]
+    pub min_elevation: f64,
+    #[serde(default = "max_elevation")]
+    pub max_elevation: f64,
+    /// Smallest distance between two objects, in tiles.
+    pub spacing: f64,
+    /// Share of the sampled points which get an object, from 0 to 1.
+    pub density: f64,
+    pub species: Vec<Species>,
+}
+
+fn max_elevation() -> f64 {
+    1.
+}
+
+impl ScatterRule {
+    fn accepts(&self, biome: Biome, elevation: f64) -> bool {
+        self.biomes.contains(&biome)
+            && elevation >= self.min_elevation
+            && elevation <= self.max_elevation
+    }
+
+    fn pick<R: Rng + ?Sized>(&self, rng: &mut R, total: u32) -> &Species {
+        let mut roll = rng.gen_range(0, total);
+        for species in &self.species {
+            if roll < species.weight {
+                return species;
+            }
+            roll -= species.weight;
+        }
+        unreachable!("The roll is below the total weight of the species")
+    }
+}
+
+/// Every scatter rule, applied in order. Objects of earlier rules keep their space, so rules for
+/// large objects should come first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScatterRules {
+    pub rules: Vec<ScatterRule>,
+}
+
+impl ScatterRules {
+    pub fn load<P: AsRef<Path>>(path: &P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = fs::File::open(path)
+            .with_context(|_| format_err!("Failed to open scatter rules {:?}", path))?;
+
+        ron::de::from_reader(file)
+            .map_err(|e| format_err!("Unable to parse scatter rules {:?}: {}", path, e))
+    }
+
+    /// Objects growing on a chunk of the island. Chunks are sampled on their own, so objects on
+    /// either side of a chunk border may be closer than their spacing.
+    pub fn scatter<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        island: &IslandMap,
+        chunk: ChunkPos,
+    ) -> Vec<Scattered> {
+        let origin = chunk.origin();
+        let mut placed: Vec<Scattered> = Vec::new();
+
+        for rule in &self.rules {
+            let total = rule
+                .species
+                .iter()
+                .map(|species| species.weight)
+                .sum::<u32>();
+            if total == 0 || rule.spacing <= 0. {
+                continue;
+            }
+
+            for point in poisson_disk(rng, CHUNK_SIZE as f64, rule.spacing) {
+                let pos = Vector2::new(
+                    (origin.x as f64 + point.x) as f32,
+                    (origin.y as f64 + point.y) as f32,
+                );
+                let spacing = rule.spacing as f32;
+                if placed
+                    .iter()
+                    .any(|other| (other.pos - pos).norm() < spacing)
+                {
+                    continue;
+                }
+                if rng.gen::<f64>() >= rule.density {
+                    continue;
+                }
+
+                let tile = TilePos::new(pos.x.floor() as i32, pos.y.floor() as i32, chunk.z);
+                if island.terrain(tile) == Terrain::Water {
+                    continue;
+                }
+                let cell = match island.cell_at(island.point_of(tile)) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let elevation = (cell.data.height - SEA_LEVEL) / (1. - SEA_LEVEL);
+                if !rule.accepts(cell.data.biome, elevation) {
+                    continue;
+                }
+
+                let species = rule.pick(rng, total);
+                let (min, max) = species.size;
+                let size = if min < max {
+                    rng.gen_range(min, max)
+                } else {
+                    min
+                };
+                placed.push(Scattered {
+                    pos,
+                    kind: species.kind.clone(),
+                    size,
+                    obstruction: species.obstruction,
+                });
+            }
+        }
+        placed
+    }
+}
+
+/// Points spread over a square of `size` with no two points closer than `radius`, using Bridson's
+/// algorithm. The points are as dense as the spacing allows without forming a grid.
+pub fn poisson_disk<R: Rng + ?Sized>(rng: &mut R, size: f64, radius: f64) -> Vec<Point> {
+    if size <= 0. || radius <= 0. {
+        return Vec::new();
+    }
+
+    // Every grid cell holds at most one point
+    let cell = radius / 2f64.sqrt();
+    let width = (size / cell).ceil() as usize;
+    let mut grid: Vec<Option<usize>> = vec![None; width * width];
+    let grid_pos = |point: Point| {
+        (
+            ((point.x / cell) as usize).min(width - 1),
+            ((point.y / cell) as usize).min(width - 1),
+        )
+    };
+
+    let first = Point::new(rng.gen::<f64>() * size, rng.gen::<f64>() * size);
+    let (x, y) = grid_pos(first);
+    grid[y * width + x] = Some(0);
+    let mut points = vec![first];
+    let mut active = vec![0];
+
+    while !active.is_empty() {
+        let i = rng.gen_range(0, active.len());
+        let center = points[active[i]];
+
+        let mut found = false;
+        for _ in 0..ATTEMPTS {
+            let angle = rng.gen::<f64>() * 2. * PI;
+            let distance = radius * (1. + rng.gen::<f64>());
+            let candidate = Point::new(
+                center.x + angle.cos() * distance,
+                center.y + angle.sin() * distance,
+            );
+            if candidate.x < 0. || candidate.y < 0. || candidate.x >= size || candidate.y >= size {
+                continue;
+            }
+
+            let (cx, cy) = grid_pos(candidate);
+            let crowded = (cy.saturating_sub(2)..(cy + 3).min(width)).any(|y| {
+                (cx.saturating_sub(2)..(cx + 3).min(width)).any(|x| match grid[y * width + x] {
+                    Some(other) => na::distance(&points[other], &candidate) < radius,
+                    None => false,
+                })
+            });
+            if !crowded {
+                grid[cy * width + cx] = Some(points.len());
+                active.push(points.len());
+                points.push(candidate);
+                found = true;
+                break;
+            }
+        }
+
+        if !found {
+            active.swap_remove(i);
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::super::GeneratorConfig;
+    use super::*;
+    use crate::components::TreeKind;
+    use crate::settings::TerrainStrategy;
+
+    #[test]
+    fn poisson_disk_keeps_its_spacing() {
+        let points = poisson_disk(&mut StdRng::seed_from_u64(3), 16., 1.5);
+        assert!(points.len() > 40);
+        for (i, a) in points.iter().enumerate() {
+            assert!(a.x >= 0. && a.y >= 0. && a.x < 16. && a.y < 16.);
+            for b in &points[i + 1..] {
+                assert!(na::distance(a, b) >= 1.5);
+            }
+        }
+
+        assert_eq!(
+            points,
+            poisson_disk(&mut StdRng::seed_from_u64(3), 16., 1.5)
+        );
+        assert!(poisson_disk(&mut StdRng::seed_from_u64(3), 16., 0.).is_empty());
+    }
+
+    #[test]
+    fn scatter_follows_the_rules() {
+        let island = IslandMap::with_config(
+            11,
+            &TerrainStrategy::default(),
+            GeneratorConfig {
+                num_points: 300,
+                ..Default::default()
+            },
+        );
+        let land = vec![
+            Biome::Beach,
+            Biome::Snow,
+            Biome::Tundra,
+            Biome::Taiga,
+            Biome::Grassland,
+            Biome::TemperateForest,
+            Biome::TemperateRainforest,
+            Biome::Desert,
+            Biome::Savanna,
+            Biome::TropicalForest,
+            Biome::TropicalRainforest,
+            Biome::Swamp,
+        ];
+        let rules = ScatterRules {
+            rules: vec![ScatterRule {
+                biomes: land,
+                min_elevation: 0.,
+                max_elevation: 1.,
+                spacing: 2.,
+                density: 1.,
+                species: vec![Species {
+                    kind: ScatterKind::Tree(TreeKind::Oak),
+                    weight: 1,
+                    size: (1., 2.),
+                    obstruction: ObstructionType::Vegetation(0.5),
+                }],
+            }],
+        };
+
+        let chunk = ChunkPos::new(0, 0, 0);
+        let placed = rules.scatter(&mut StdRng::seed_from_u64(1), &island, chunk);
+        assert!(!placed.is_empty());
+        for object in &placed {
+            let tile = TilePos::new(object.pos.x.floor() as i32, object.pos.y.floor() as i32, 0);
+            assert_eq!(tile.chunk(), chunk);
+            assert_ne!(island.terrain(tile), Terrain::Water);
+            assert!(object.size >= 1. && object.size < 2.);
+        }
+
+        let again = rules.scatter(&mut StdRng::seed_from_u64(1), &island, chunk);
+        assert_eq!(
+            placed.iter().map(|o| o.pos).collect::<Vec<_>>(),
+            again.iter().map(|o| o.pos).collect::<Vec<_>>()
+        );
+
+        // Nothing grows out at sea
+        let sea = ChunkPos::new(500, 500, 0);
+        assert!(rules
+            .scatter(&mut StdRng::seed_from_u64(1), &island, sea)
+            .is_empty());
+    }
+
+    #[test]
+    fn rules_file_parses() {
+        let rules = ScatterRules::load(&SCATTER_FILE).unwrap();
+        assert!(!rules.rules.is_empty());
+        for rule in &rules.rules {
+            assert!(rule.spacing > 0.);
+            assert!(rule.species.iter().any(|species| species.weight > 0));
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+
+use super::{inside_poly, sorted_keys, Cell, GeneratorConfig, IndexPoint, Point};
+
+/// Box around the polygon of a cell, to skip most cells without testing their polygon.
+#[derive(Debug)]
+pub struct CellBounds {
+    pub key: IndexPoint,
+    pub min: Point,
+    pub max: Point,
+}
+
+impl CellBounds {
+    pub fn overlaps(&self, min: Point, max: Point) -> bool {
+        self.min.x <= max.x && min.x <= self.max.x && self.min.y <= max.y && min.y <= self.max.y
+    }
+}
+
+/// Grid of buckets over the map, each listing the cells whose bounds overlap it. Finding the cell
+/// of a point only tests the few cells of its bucket instead of every cell of the map.
+#[derive(Debug)]
+pub struct CellIndex {
+    bounds: Vec<CellBounds>,
+    /// Number of buckets along each side of the map.
+    columns: usize,
+    bucket_size: f64,
+    /// Indexes into `bounds`, in the order of the sorted cell keys.
+    buckets: Vec<Vec<usize>>,
+}
+
+impl CellIndex {
+    /// Index the cells of a map, with about one cell per bucket.
+    pub fn new<T: Sync>(config: &GeneratorConfig, cells: &HashMap<IndexPoint, Cell<T>>) -> Self {
+        let bounds = sorted_keys(cells)
+            .into_par_iter()
+            .filter(|key| !cells[key].polygon.is_empty())
+            .map(|key| {
+                let polygon = &cells[&key].polygon;
+                let mut min = polygon[0];
+                let mut max = polygon[0];
+                for point in polygon {
+                    min = Point::new(min.x.min(point.x), min.y.min(point.y));
+                    max = Point::new(max.x.max(point.x), max.y.max(point.y));
+                }
+                CellBounds { key, min, max }
+            })
+            .collect::<Vec<_>>();
+
+        let columns = ((bounds.len() as f64).sqrt().ceil() as usize).max(1);
+        let bucket_size = config.box_size / columns as f64;
+        let mut index = Self {
+            bounds,
+            columns,
+            bucket_size,
+            buckets: vec![Vec::new(); columns * columns],
+        };
+
+        for (i, bounds) in index.bounds.iter().enumerate() {
+            let (min_x, min_y) = index.bucket(bounds.min);
+            let (max_x, max_y) = index.bucket(bounds.max);
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    index.buckets[y * columns + x].push(i);
+                }
+            }
+        }
+        index
+    }
+
+    /// Bounds of every indexed cell, in the order of the sorted cell keys.
+    pub fn bounds(&self) -> &[CellBounds] {
+        &self.bounds
+    }
+
+    /// Bucket containing a point. Points beyond the map are clamped into the buckets at its edge.
+    fn bucket(&self, point: Point) -> (usize, usize) {
+        let last = (self.columns - 1) as f64;
+        let clamp = |v: f64| (v / self.bucket_size).max(0.).min(last) as usize;
+        (clamp(point.x), clamp(point.y))
+    }
+
+    /// Cell whose polygon contains a point.
+    pub fn cell_at<'a, T>(
+        &self,
+        cells: &'a HashMap<IndexPoint, Cell<T>>,
+        point: Point,
+    ) -> Option<&'a Cell<T>> {
+        let (x, y) = self.bucket(point);
+        self.buckets[y * self.columns + x]
+            .iter()
+            .map(|&i| &self.bounds[i])
+            .filter(|bounds| bounds.overlaps(point, point))
+            .map(|bounds| &cells[&bounds.key])
+            .find(|cell| inside_poly(point, &cell.polygon))
+    }
+
+    /// Every cell whose bounds overlap a box, each once and in the order of the sorted keys.
+    pub fn overlapping(&self, min: Point, max: Point) -> Vec<&CellBounds> {
+        let (min_x, min_y) = self.bucket(min);
+        let (max_x, max_y) = self.bucket(max);
+
+        let mut found = Vec::new();
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                found.extend_from_slice(&self.buckets[y * self.columns + x]);
+            }
+        }
+        found.sort();
+        found.dedup();
+        found
+            .into_iter()
+            .map(|i| &self.bounds[i])
+            .filter(|bounds| bounds.overlaps(min, max))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::super::{CellData, Generator};
+    use super::*;
+
+    #[test]
+    fn index_matches_a_full_scan() {
+        let config = GeneratorConfig {
+            num_points: 1000,
+            ..Default::default()
+        };
+        let cells = Generator::new(StdRng::seed_from_u64(4)).gen_voronoi::<CellData>(&config);
+        let index = CellIndex::new(&config, &cells);
+
+        let scan = |point: Point| {
+            sorted_keys(&cells)
+                .into_iter()
+                .map(|key| &cells[&key])
+                .find(|cell| inside_poly(point, &cell.polygon))
+                .map(|cell| cell.position)
+        };
+
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..2000 {
+            let point = Point::new(
+                rng.gen_range(-10., config.box_size + 10.),
+                rng.gen_range(-10., config.box_size + 10.),
+            );
+            let found = index.cell_at(&cells, point).map(|cell| cell.position);
+            assert_eq!(found, scan(point));
+        }
+
+        let min = Point::new(100., 100.);
+        let max = Point::new(150., 120.);
+        let expected = index
+            .bounds()
+            .iter()
+            .filter(|bounds| bounds.overlaps(min, max))
+            .map(|bounds| bounds.key)
+            .collect::<Vec<_>>();
+        let found = index
+            .overlapping(min, max)
+            .into_iter()
+            .map(|bounds| bounds.key)
+            .collect::<Vec<_>>();
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+
+use amethyst::core::math as na;
+use ordered_float::OrderedFloat;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use super::{
+    ArchipelagoSettings, Cell, CellData, ContinentSettings, FlatSettings, GeneratorConfig,
+    IndexPoint, IslandGeneratorSettings, Point, TerrainStrategy,
+};
+
+/// Shapes the land of a map by giving every cell its height. The heights are between 0 and 1,
+/// with the sea below `SEA_LEVEL`.
+pub trait TerrainGenerator {
+    fn shape<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        config: &GeneratorConfig,
+        cells: &mut HashMap<IndexPoint, Cell<CellData>>,
+    );
+}
+
+impl TerrainGenerator for TerrainStrategy {
+    fn shape<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        config: &GeneratorConfig,
+        cells: &mut HashMap<IndexPoint, Cell<CellData>>,
+    ) {
+        match self {
+            TerrainStrategy::Island(settings) => settings.shape(rng, config, cells),
+            TerrainStrategy::Continent(settings) => settings.shape(rng, config, cells),
+            TerrainStrategy::Archipelago(settings) => settings.shape(rng, config, cells),
+            TerrainStrategy::Flat(settings) => settings.shape(rng, config, cells),
+        }
+    }
+}
+
+/// Heights fall off from the cell closest to the middle of the map, neighbor by neighbor.
+impl TerrainGenerator for IslandGeneratorSettings {
+    fn shape<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        config: &GeneratorConfig,
+        cells: &mut HashMap<IndexPoint, Cell<CellData>>,
+    ) {
+        // Find the center polygon
+        let mut center = Point::new(0., 0.);
+        let target = Point::new(config.box_size / 2., config.box_size / 2.);
+        for (key, _) in cells.iter() {
+            let point = Point::new(key.x.into_inner(), key.y.into_inner());
+            if na::distance(&target, &center) > na::distance(&target, &point) {
+                center = point;
+            }
+        }
+
+        let mut height = self.height;
+
+        let mut queue = Vec::new();
+        queue.push(IndexPoint::new(
+            OrderedFloat(center.x),
+            OrderedFloat(center.y),
+        ));
+        cells.get_mut(&queue[0]).unwrap().data.height = height;
+
+        let mut i = 0;
+        while i < queue.len() && height > 0.01 {
+            height = cells[&queue[i]].data.height * self.radius;
+
+            let neighbors = cells[&queue[i]].neighbors.clone();
+            neighbors.iter().for_each(|n| {
+                let cell = cells.get_mut(n).unwrap();
+                if !cell.data.used {
+                    let modifier: f64 = if self.sharpness == 0. {
+                        1.0
+                    } else {
+                        let r: f64 = rng.gen();
+                        r * self.sharpness + 1.1 - self.sharpness
+                    };
+
+                    cell.data.height += height * modifier;
+                    cell.data.used = true;
+
+                    if cell.data.height > 1. {
+                        cell.data.height = 1.;
+                    }
+
+                    queue.push(*n);
+                }
+            });
+
+            i += 1;
+        }
+    }
+}
+
+impl TerrainGenerator for ContinentSettings {
+    fn shape<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        config: &GeneratorConfig,
+        cells: &mut HashMap<IndexPoint, Cell<CellData>>,
+    ) {
+        let noise = Noise::new(rng);
+        for cell in cells.values_mut() {
+            let (x, y) = relative(config, cell.position);
+            let distance = ((x - 0.5).powi(2) + (y - 0.5).powi(2)).sqrt() * 2.;
+            let detail = noise.fbm(
+                x * self.frequency,
+                y * self.frequency,
+                self.octaves,
+                self.persistence,
+                self.lacunarity,
+            );
+
+            cell.data.height = clamp(self.height + detail * 0.5 - self.falloff * distance.powi(2));
+        }
+    }
+}
+
+impl TerrainGenerator for ArchipelagoSettings {
+    fn shape<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        config: &GeneratorConfig,
+        cells: &mut HashMap<IndexPoint, Cell<CellData>>,
+    ) {
+        let noise = Noise::new(rng);
+
+        // Keep the islands away from the edges so they do not get cut off
+        let islands = (0..self.islands)
+            .map(|_| {
+                let x = 0.2 + rng.gen::<f64>() * 0.6;
+                let y = 0.2 + rng.gen::<f64>() * 0.6;
+                let radius =
+                    self.min_radius + rng.gen::<f64>() * (self.max_radius - self.min_radius);
+                (x, y, radius)
+            })
+            .collect::<Vec<_>>();
+
+        for cell in cells.values_mut() {
+            let (x, y) = relative(config, cell.position);
+            let peak = islands
+                .iter()
+                .map(|(ix, iy, radius)| {
+                    let distance = ((x - ix).powi(2) + (y - iy).powi(2)).sqrt() / radius;
+                    self.height * (1. - distance.powi(2)).max(0.)
+                })
+                .fold(0., f64::max);
+            let coast = noise.fbm(x * 8., y * 8., 4, 0.5, 2.);
+
+            cell.data.height = clamp(peak + self.roughness * coast);
+        }
+    }
+}
+
+impl TerrainGenerator for FlatSettings {
+    fn shape<R: Rng + ?Sized>(
+        &self,
+        _: &mut R,
+        _: &GeneratorConfig,
+        cells: &mut HashMap<IndexPoint, Cell<CellData>>,
+    ) {
+        for cell in cells.values_mut() {
+            cell.data.height = clamp(self.height);
+        }
+    }
+}
+
+/// Position of a cell with the map scaled to one unit.
+fn relative(config: &GeneratorConfig, position: IndexPoint) -> (f64, f64) {
+    (
+        position.x.into_inner() / config.box_size,
+        position.y.into_inner() / config.box_size,
+    )
+}
+
+fn clamp(height: f64) -> f64 {
+    height.max(0.).min(1.)
+}
+
+/// Two dimensional gradient noise on a lattice shuffled by the rng of the map. Values are roughly
+/// between -1 and 1 and zero on the lattice points.
+struct Noise {
+    permutation: Vec<usize>,
+}
+
+impl Noise {
+    fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let mut permutation = (0..256).collect::<Vec<_>>();
+        permutation.shuffle(rng);
+        // Doubled so lookups of a hash plus one never wrap
+        let permutation = permutation
+            .iter()
+            .chain(permutation.iter())
+            .cloned()
+            .collect();
+        Self { permutation }
+    }
+
+    fn get(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let xi = (x0 as i64 & 255) as usize;
+        let yi = (y0 as i64 & 255) as usize;
+
+        let p = &self.permutation;
+        let corner = |dx: usize, dy: usize| {
+            let hash = p[p[xi + dx] + yi + dy];
+            gradient(hash, fx - dx as f64, fy - dy as f64)
+        };
+
+        let u = fade(fx);
+        let v = fade(fy);
+        let bottom = lerp(corner(0, 0), corner(1, 0), u);
+        let top = lerp(corner(0, 1), corner(1, 1), u);
+        lerp(bottom, top, v)
+    }
+
+    /// Fractal brownian motion, layers of noise with rising frequency and falling amplitude.
+    fn fbm(&self, x: f64, y: f64, octaves: usize, persistence: f64, lacunarity: f64) -> f64 {
+        let mut total = 0.;
+        let mut amplitudes = 0.;
+        let mut amplitude = 1.;
+        let mut frequency = 1.;
+        for _ in 0..octaves {
+            total += self.get(x * frequency, y * frequency) * amplitude;
+            amplitudes += amplitude;
+            amplitude *= persistence;
+            frequency *= lacunarity;
+        }
+
+        if amplitudes > 0. {
+            total / amplitudes
+        } else {
+            0.
+        }
+    }
+}
+
+fn gradient(hash: usize, x: f64, y: f64) -> f64 {
+    match hash & 7 {
+        0 => x + y,
+        1 => x - y,
+        2 => -x + y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::super::{sorted_keys, Generator, SEA_LEVEL};
+    use super::*;
+
+    fn heights<T: TerrainGenerator>(seed: u64, terrain: &T) -> Vec<f64> {
+        let config = GeneratorConfig {
+            num_points: 500,
+            ..Default::default()
+        };
+        let cells = Generator::new(StdRng::seed_from_u64(seed)).run(&config, terrain);
+        sorted_keys(&cells)
+            .iter()
+            .map(|key| cells[key].data.height)
+            .collect()
+    }
+
+    #[test]
+    fn strategies_are_deterministic() {
+        for terrain in &[
+            TerrainStrategy::Island(Default::default()),
+            TerrainStrategy::Continent(Default::default()),
+            TerrainStrategy::Archipelago(Default::default()),
+        ] {
+            let first = heights(5, terrain);
+            assert_eq!(first, heights(5, terrain));
+            assert_ne!(first, heights(6, terrain));
+
+            assert!(first.iter().all(|height| *height >= 0. && *height <= 1.));
+            assert!(first.iter().any(|height| *height < SEA_LEVEL));
+            assert!(first.iter().any(|height| *height >= SEA_LEVEL));
+        }
+    }
+
+    #[test]
+    fn strategy_matches_its_settings() {
+        let settings = ContinentSettings::default();
+        assert_eq!(
+            heights(3, &TerrainStrategy::Continent(settings)),
+            heights(3, &settings)
+        );
+    }
+
+    #[test]
+    fn flat_world() {
+        let flat = heights(1, &FlatSettings { height: 0.7 });
+        assert_eq!(flat, vec![0.7; flat.len()]);
+    }
+
+    #[test]
+    fn noise_is_zero_on_the_lattice() {
+        let noise = Noise::new(&mut StdRng::seed_from_u64(0));
+        for &(x, y) in &[(0., 0.), (3., -7.), (-12., 40.)] {
+            assert_eq!(noise.get(x, y), 0.);
+        }
+        for i in 0..100 {
+            let value = noise.fbm(i as f64 * 0.37, i as f64 * 0.11, 4, 0.5, 2.);
+            assert!(value.abs() <= 1.);
+        }
+    }
+}
@@ -7,10 +7,11 @@ use specs_derive::Component;
 
 pub use pass::TileRenderPass;
 
+use crate::coords::{ChunkPos, TilePos};
 use crate::specs_static::{Id, Storage};
 use crate::systems::chunk::Chunk;
-use crate::tiles::TileAsset;
-use crate::utils::TILE_SIZE;
+use crate::systems::chunk_layers::{ChunkLayers, TileLayer};
+use crate::tiles::{TileAsset, TileId};
 
 mod pass;
 mod specs;
@@ -21,25 +22,56 @@ pub struct TextureUsage {
     data: Vec<f32>,
     len: u32,
 }
-pub type WriteChunkRender<'a> = WriteExpect<'a, HashMap<(i32, i32), ChunkRender>>;
+pub type WriteChunkRender<'a> = WriteExpect<'a, HashMap<ChunkPos, ChunkRender>>;
 
+/// Tile instances of a chunk grouped by texture. Layers are drawn in order, so the usages of a
+/// layer come after the ones of the layers below it.
 #[derive(Debug, Component)]
 #[storage(HashMapStorage)]
 pub struct ChunkRender {
     inner: Vec<TextureUsage>,
 }
 
+/// Tile drawn in place of chunks which are still being loaded.
+pub const PLACEHOLDER_TILE: TileId = TileId(0);
+
 pub fn compile_chunk(chunk: &Chunk, tile_specs: &[TileAsset]) -> ChunkRender {
+    compile_layers(chunk.pos, &chunk.layers, tile_specs)
+}
+
+/// Compile a chunk with its ground filled with `PLACEHOLDER_TILE`.
+pub fn compile_placeholder(pos: ChunkPos, tile_specs: &[TileAsset]) -> ChunkRender {
+    let layers = ChunkLayers::with_ground([[PLACEHOLDER_TILE; 16]; 16]);
+    compile_layers(pos, &layers, tile_specs)
+}
+
+fn compile_layers(pos: ChunkPos, layers: &ChunkLayers, tile_specs: &[TileAsset]) -> ChunkRender {
+    let mut inner = Vec::new();
+    for (_, tiles) in layers.iter() {
+        inner.extend(compile_tiles(pos, tiles, tile_specs));
+    }
+    ChunkRender { inner }
+}
+
+/// Group the tiles of a single layer by texture. Empty tiles are skipped. Each tile is drawn
+/// centered on the middle of its tile position.
+fn compile_tiles(pos: ChunkPos, tiles: &TileLayer, tile_specs: &[TileAsset]) -> Vec<TextureUsage> {
     let mut texture_map: HashMap<usize, TextureUsage> = HashMap::new();
-    let (chunk_x, chunk_y) = chunk.pos;
+    let mut expected = 0;
 
     for x in 0..16 {
         for y in 0..16 {
-            let texture_id = chunk.tiles[x][y].0 as usize;
+            if tiles[x][y] == TileId::EMPTY {
+                continue;
+            }
+            expected += 1;
+
+            let texture_id = tiles[x][y].0 as usize;
             let asset = &tile_specs[texture_id];
+            let center = TilePos::from_local(pos, x, y).center();
             let slice = [
-                (x as f32 + TILE_SIZE * chunk_x as f32) * TILE_SIZE,
-                (y as f32 + TILE_SIZE * chunk_y as f32) * TILE_SIZE,
+                center.x,
+                center.y,
                 asset.sprite.tex_coords.left,
                 asset.sprite.tex_coords.right,
                 asset.sprite.tex_coords.bottom,
@@ -69,11 +101,11 @@ pub fn compile_chunk(chunk: &Chunk, tile_specs: &[TileAsset]) -> ChunkRender {
         collected.push(texture_usage);
     }
 
-    if num != 256 {
+    if num != expected {
         log::error!(
             "Did not get correct number of total tiles rendered: {:?}",
             num
         );
     }
-    ChunkRender { inner: collected }
+    collected
 }
@@ -18,12 +18,15 @@ use shred_derive::SystemData;
 
 use super::specs::{SpriteInstance, TextureOffsetPod, ViewArgs, FRAG_SRC, VERT_SRC};
 use super::WriteChunkRender;
+use crate::components::{Player, ZLevel};
 
 type GraphicsSlice = gfx::Slice<Resources>;
 
 #[derive(SystemData)]
 pub struct RenderData<'a> {
     chunks: WriteChunkRender<'a>,
+    players: ReadStorage<'a, Player>,
+    levels: ReadStorage<'a, ZLevel>,
     camera: ReadStorage<'a, Camera>,
     global: ReadStorage<'a, GlobalTransform>,
     sprite_assets: Read<'a, AssetStorage<Texture>>,
@@ -73,7 +76,9 @@ impl Pass for TileRenderPass {
 
         use gfx::Factory;
 
-        for chunk in data.chunks.values_mut() {
+        // Only the level the player is on is drawn
+        let level = ZLevel::of_player(&data.players, &data.levels);
+        for (_, chunk) in data.chunks.iter_mut().filter(|(pos, _)| pos.z == level) {
             for usage in chunk.inner.iter() {
                 // Get texture
                 match data.sprite_assets.get(&usage.texture) {
@@ -0,0 +1,443 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use amethyst::error::{format_err, Error};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+#[cfg(not(feature = "pretty-save"))]
+use ron::ser::to_string;
+#[cfg(feature = "pretty-save")]
+use ron::ser::{to_string_pretty, PrettyConfig};
+use ron::value::{Number, Value};
+
+use crate::save::envelope::{self, Format, CHUNK_VERSION};
+use crate::systems::chunk::Chunk;
+use crate::systems::chunk_layers::{Layer, TileLayer};
+use crate::tiles::TileId;
+
+/// Format new chunk saves are written in.
+#[cfg(feature = "compressed-save")]
+pub const SAVE_FORMAT: Format = Format::CompressedBinary;
+#[cfg(all(feature = "binary-save", not(feature = "compressed-save")))]
+pub const SAVE_FORMAT: Format = Format::Binary;
+#[cfg(not(feature = "binary-save"))]
+pub const SAVE_FORMAT: Format = Format::Ron;
+
+/// Encode a chunk into a complete save in the given format.
+pub fn encode(chunk: &Chunk, format: Format) -> Result<Vec<u8>, Error> {
+    let payload = match format {
+        Format::Ron => encode_ron(chunk)?.into_bytes(),
+        Format::Binary => encode_binary(chunk)?,
+        Format::CompressedBinary => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&encode_binary(chunk)?)?;
+            encoder.finish()?
+        }
+    };
+
+    Ok(envelope::wrap(format, &payload))
+}
+
+/// Decode a save written in any format by any version of the game.
+pub fn decode(data: &[u8]) -> Result<Chunk, Error> {
+    let (header, payload) = envelope::unwrap(data);
+
+    match header.format {
+        Format::Ron => envelope::decode_ron(header.version, payload),
+        Format::Binary => {
+            check_binary_version(header.version)?;
+            decode_binary(header.version, payload)
+        }
+        Format::CompressedBinary => {
+            check_binary_version(header.version)?;
+            let mut inflated = Vec::new();
+            DeflateDecoder::new(payload).read_to_end(&mut inflated)?;
+            decode_binary(header.version, &inflated)
+        }
+    }
+}
+
+fn encode_ron(chunk: &Chunk) -> Result<String, Error> {
+    #[cfg(feature = "pretty-save")]
+    let save = to_string_pretty(
+        chunk,
+        PrettyConfig {
+            depth_limit: 99,
+            enumerate_arrays: true,
+            ..PrettyConfig::default()
+        },
+    );
+
+    #[cfg(not(feature = "pretty-save"))]
+    let save = to_string(chunk);
+
+    save.map_err(|e| format_err!("Unable to serialize chunk: {}", e))
+}
+
+/// First version binary saves were written with.
+const FIRST_BINARY_VERSION: u32 = 1;
+
+// Older binary saves are read into the form their RON saves had and migrated like those.
+fn check_binary_version(version: u32) -> Result<(), Error> {
+    if version < FIRST_BINARY_VERSION || version > CHUNK_VERSION {
+        return Err(format_err!(
+            "Unable to read version {} binary chunk save",
+            version
+        ));
+    }
+    Ok(())
+}
+
+/// First version with tile layers. Older binary saves only hold the ground.
+const LAYERED_BINARY_VERSION: u32 = 3;
+
+/// The binary payload is the tile palette, the tiles of every layer in drawing order as runs of
+/// palette indices in `tiles[x][y]` order, the entities as RON and finally the turn the chunk was
+/// unloaded at plus one, or zero if it has never been unloaded. All integers are LEB128 varints.
+fn encode_binary(chunk: &Chunk) -> Result<Vec<u8>, Error> {
+    let layers = chunk
+        .layers
+        .iter()
+        .map(|(_, tiles)| tiles)
+        .collect::<Vec<_>>();
+    let mut data = Vec::new();
+    write_tiles(&mut data, &layers);
+
+    let entities = ron::ser::to_string(&chunk.entities)
+        .map_err(|e| format_err!("Unable to serialize chunk entities: {}", e))?;
+    write_varint(&mut data, entities.len() as u64);
+    data.extend_from_slice(entities.as_bytes());
+
+    write_varint(&mut data, chunk.unloaded_at.map_or(0, |turn| turn + 1));
+
+    Ok(data)
+}
+
+/// Write the palette and tile runs shared by the given layers.
+fn write_tiles(data: &mut Vec<u8>, layers: &[&TileLayer]) {
+    let mut palette: Vec<TileId> = Vec::new();
+    let mut runs: Vec<(u64, u64)> = Vec::new();
+
+    let tiles = layers
+        .iter()
+        .flat_map(|layer| layer.iter())
+        .flat_map(|column| column.iter());
+    for tile in tiles {
+        let index = match palette.iter().position(|id| id == tile) {
+            Some(v) => v,
+            None => {
+                palette.push(*tile);
+                palette.len() - 1
+            }
+        } as u64;
+
+        match runs.last_mut() {
+            Some((len, last)) if *last == index => *len += 1,
+            _ => runs.push((1, index)),
+        }
+    }
+
+    write_varint(data, palette.len() as u64);
+    for id in &palette {
+        write_varint(data, u64::from(id.0));
+    }
+
+    write_varint(data, runs.len() as u64);
+    for (len, index) in &runs {
+        write_varint(data, *len);
+        write_varint(data, *index);
+    }
+}
+
+fn decode_binary(version: u32, mut data: &[u8]) -> Result<Chunk, Error> {
+    let data = &mut data;
+    let layers: &[Layer] = if version >= LAYERED_BINARY_VERSION {
+        &Layer::ALL
+    } else {
+        &[Layer::Ground]
+    };
+    let count = layers.len() * 256;
+
+    let palette_len = read_varint(data)? as usize;
+    if palette_len > count {
+        return Err(format_err!(
+            "Chunk save has a palette of {} tiles",
+            palette_len
+        ));
+    }
+    let mut palette = Vec::with_capacity(palette_len);
+    for _ in 0..palette_len {
+        palette.push(TileId(read_varint(data)? as u32));
+    }
+
+    let mut tiles = Vec::with_capacity(count);
+    for _ in 0..read_varint(data)? {
+        let len = read_varint(data)? as usize;
+        let id = *palette
+            .get(read_varint(data)? as usize)
+            .ok_or_else(|| format_err!("Tile run references a missing palette entry"))?;
+
+        match tiles.len().checked_add(len) {
+            Some(total) if total <= count => {}
+            _ => return Err(format_err!("Chunk save contains more than {} tiles", count)),
+        }
+        tiles.extend(std::iter::repeat(id).take(len));
+    }
+
+    if tiles.len() != count {
+        return Err(format_err!(
+            "Chunk save contains {} tiles instead of {}",
+            tiles.len(),
+            count
+        ));
+    }
+
+    let entities_len = read_varint(data)? as usize;
+    if data.len() < entities_len {
+        return Err(format_err!("Chunk save ended inside of its entities"));
+    }
+    let entities = &data[..entities_len];
+    *data = &data[entities_len..];
+
+    // Version 1 saves end after the entities
+    let unloaded_at = if version >= 2 {
+        Some(read_varint(data)?.checked_sub(1))
+    } else {
+        None
+    };
+
+    if version < CHUNK_VERSION {
+        let value = older_chunk(layers, &tiles, entities, unloaded_at)?;
+        return envelope::migrate(version, value);
+    }
+
+    let mut chunk = Chunk::default();
+    chunk.entities = ron::de::from_bytes(entities)
+        .map_err(|e| format_err!("Unable to parse chunk entities: {}", e))?;
+    chunk.unloaded_at = unloaded_at.unwrap_or(None);
+
+    for (i, id) in tiles.into_iter().enumerate() {
+        chunk.layers.get_mut(layers[i / 256])[i % 256 / 16][i % 16] = id;
+    }
+
+    Ok(chunk)
+}
+
+/// A chunk read from an older binary save in the form its RON save had at the same version. The
+/// unloaded turn is only present in versions which have it.
+fn older_chunk(
+    layers: &[Layer],
+    tiles: &[TileId],
+    entities: &[u8],
+    unloaded_at: Option<Option<u64>>,
+) -> Result<Value, Error> {
+    let key = |name: &str| Value::String(name.to_string());
+    let number = |value: u64| Value::Number(Number::new(value as f64));
+    let layer = |tiles: &[TileId]| {
+        Value::Seq(
+            tiles
+                .chunks(16)
+                .map(|column| Value::Seq(column.iter().map(|id| number(u64::from(id.0))).collect()))
+                .collect(),
+        )
+    };
+
+    let mut chunk = BTreeMap::new();
+    if layers.len() == 1 {
+        chunk.insert(key("tiles"), layer(tiles));
+    } else {
+        let names = ["ground", "floor", "object", "overlay"];
+        let layers = names
+            .iter()
+            .zip(tiles.chunks(256))
+            .map(|(name, tiles)| (key(name), layer(tiles)))
+            .collect();
+        chunk.insert(key("layers"), Value::Map(layers));
+    }
+
+    let entities = ron::de::from_bytes(entities)
+        .map_err(|e| format_err!("Unable to parse chunk entities: {}", e))?;
+    chunk.insert(key("entities"), entities);
+    if let Some(turn) = unloaded_at {
+        let turn = turn.map(|turn| Box::new(number(turn)));
+        chunk.insert(key("unloaded_at"), Value::Option(turn));
+    }
+
+    Ok(Value::Map(chunk))
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            data.push(byte);
+            return;
+        }
+        data.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = data
+            .split_first()
+            .ok_or_else(|| format_err!("Chunk save ended inside of a number"))?;
+        *data = rest;
+
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(format_err!(
+        "Chunk save contains a number which is too large"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coords::ChunkPos;
+    use crate::systems::chunk::TestChunk;
+    use crate::systems::chunk_layers::{ChunkLayers, EMPTY_LAYER};
+
+    fn sample_chunk() -> TestChunk {
+        let mut chunk = TestChunk::new(ChunkPos::new(3, -2, 0));
+        chunk.layers.object[4][7] = TileId(35);
+        chunk.layers.overlay[0][15] = TileId(250);
+        chunk
+    }
+
+    fn assert_same(a: &Chunk, b: &Chunk) {
+        assert_eq!(a.layers, b.layers);
+        assert_eq!(a.entities.len(), b.entities.len());
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let mut data = Vec::new();
+        for value in &[
+            0,
+            1,
+            127,
+            128,
+            300,
+            u64::from(u32::max_value()),
+            u64::max_value(),
+        ] {
+            data.clear();
+            write_varint(&mut data, *value);
+            assert_eq!(read_varint(&mut &data[..]).unwrap(), *value);
+        }
+        assert!(read_varint(&mut &[0x80][..]).is_err());
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let chunk = sample_chunk();
+        let ron = decode(&encode(&chunk, Format::Ron).unwrap()).unwrap();
+        let binary = decode(&encode(&chunk, Format::Binary).unwrap()).unwrap();
+        let compressed = decode(&encode(&chunk, Format::CompressedBinary).unwrap()).unwrap();
+
+        assert_same(&chunk, &ron);
+        assert_same(&ron, &binary);
+        assert_same(&binary, &compressed);
+    }
+
+    #[test]
+    fn binary_is_smaller() {
+        let mut chunk = sample_chunk();
+        let ron = encode(&chunk, Format::Ron).unwrap();
+        let binary = encode(&chunk, Format::Binary).unwrap();
+        assert!(binary.len() < ron.len());
+
+        // A single tile type collapses into a single run per layer
+        chunk.layers = ChunkLayers::with_ground([[TileId(129); 16]; 16]);
+        assert!(encode(&chunk, Format::Binary).unwrap().len() < 32);
+    }
+
+    #[test]
+    fn unloaded_turn_round_trip() {
+        let mut chunk = sample_chunk();
+        chunk.unloaded_at = Some(1234);
+        for format in &[Format::Ron, Format::Binary, Format::CompressedBinary] {
+            let decoded = decode(&encode(&chunk, *format).unwrap()).unwrap();
+            assert_eq!(decoded.unloaded_at, Some(1234));
+        }
+    }
+
+    #[test]
+    fn corrupt_run_lengths_are_rejected() {
+        let mut payload = Vec::new();
+        write_varint(&mut payload, 1);
+        write_varint(&mut payload, 129);
+        write_varint(&mut payload, 2);
+        write_varint(&mut payload, 1);
+        write_varint(&mut payload, 0);
+        // Overflows the tile count when added to the first run
+        write_varint(&mut payload, u64::max_value());
+        write_varint(&mut payload, 0);
+        assert!(decode_binary(CHUNK_VERSION, &payload).is_err());
+
+        let mut payload = Vec::new();
+        write_varint(&mut payload, u64::max_value());
+        assert!(decode_binary(CHUNK_VERSION, &payload).is_err());
+    }
+
+    /// Binary save with only the ground tiles, as written before tile layers.
+    fn unlayered_binary(chunk: &Chunk, version: u32) -> Vec<u8> {
+        let mut payload = Vec::new();
+        write_tiles(&mut payload, &[&chunk.layers.ground]);
+        write_varint(&mut payload, 2);
+        payload.extend_from_slice(b"[]");
+        if version >= 2 {
+            write_varint(&mut payload, chunk.unloaded_at.map_or(0, |turn| turn + 1));
+        }
+
+        let mut data = envelope::wrap(Format::Binary, &payload);
+        data[4..8].copy_from_slice(&version.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn reads_version_1_binary() {
+        let chunk = sample_chunk();
+
+        // Version 1 has no layers and no trailing unloaded turn
+        let decoded = decode(&unlayered_binary(&chunk, 1)).unwrap();
+        assert_eq!(decoded.layers.ground, chunk.layers.ground);
+        assert_eq!(decoded.layers.object, EMPTY_LAYER);
+        assert_eq!(decoded.unloaded_at, None);
+    }
+
+    #[test]
+    fn reads_version_2_binary() {
+        let mut chunk = sample_chunk();
+        chunk.unloaded_at = Some(12);
+
+        let decoded = decode(&unlayered_binary(&chunk, 2)).unwrap();
+        assert_eq!(
+            decoded.layers,
+            ChunkLayers::with_ground(chunk.layers.ground)
+        );
+        assert_eq!(decoded.unloaded_at, Some(12));
+    }
+
+    #[test]
+    fn newer_binary_is_rejected() {
+        let chunk = sample_chunk();
+        let mut data = encode(&chunk, Format::Binary).unwrap();
+        data[4..8].copy_from_slice(&(CHUNK_VERSION + 1).to_le_bytes());
+        assert!(decode(&data).is_err());
+    }
+
+    #[test]
+    fn truncated_binary_is_rejected() {
+        let chunk = sample_chunk();
+        let data = encode(&chunk, Format::Binary).unwrap();
+        assert!(decode(&data[..data.len() / 2]).is_err());
+    }
+}
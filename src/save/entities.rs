@@ -0,0 +1,623 @@
+use amethyst::{
+    core::{components::Parent, math::Vector2, transform::Transform, ParentHierarchy},
+    ecs::{
+        error::NoError,
+        saveload::{
+            ConvertSaveload, DeserializeComponents, MarkerAllocator, SerializeComponents,
+            SimpleMarker, SimpleMarkerAllocator,
+        },
+        Entities, Entity, Join, ReadExpect, Write, WriteStorage,
+    },
+};
+use hashbrown::HashMap;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use shred_derive::SystemData;
+
+use crate::assets::item::Property;
+use crate::assets::ItemStorage;
+use crate::components::{
+    Container, Interactable, Item, ObstructionType, PawnTraits, Player, TimeAvailable, Tree, ZLevel,
+};
+use crate::coords::{ChunkPos, WorldPos};
+use crate::entity::WorldEntity;
+use crate::systems::chunk::{Chunk, WorldChunks};
+
+#[cfg(feature = "mapgen")]
+use crate::components::InteractionType;
+#[cfg(feature = "mapgen")]
+use crate::mapgen::ScatterKind;
+
+/// Tag for the markers of entities which are saved with the chunk they stand in.
+pub struct WorldSave;
+
+pub type SaveMarker = SimpleMarker<WorldSave>;
+pub type SaveMarkerAllocator = SimpleMarkerAllocator<WorldSave>;
+
+/// Saved form of every component which is persisted through saveload.
+pub type SavedComponents = (
+    Option<<Transform as ConvertSaveload<SaveMarker>>::Data>,
+    Option<<Parent as ConvertSaveload<SaveMarker>>::Data>,
+    Option<Container>,
+    Option<Interactable>,
+    Option<ObstructionType>,
+    Option<Tree>,
+    Option<Player>,
+    Option<PawnTraits>,
+    Option<TimeAvailable>,
+);
+
+/// Items reference their details through an asset handle so they are saved by name instead.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedItem {
+    pub name: String,
+    pub properties: Vec<Property>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedEntity {
+    pub marker: SaveMarker,
+    pub components: SavedComponents,
+    pub item: Option<SavedItem>,
+}
+
+/// Storages of every component which is saved with entities.
+#[derive(SystemData)]
+pub struct EntityStorages<'a> {
+    entities: Entities<'a>,
+    hierarchy: ReadExpect<'a, ParentHierarchy>,
+    item_storage: ReadExpect<'a, ItemStorage>,
+    markers: WriteStorage<'a, SaveMarker>,
+    allocator: Write<'a, SaveMarkerAllocator>,
+    transforms: WriteStorage<'a, Transform>,
+    parents: WriteStorage<'a, Parent>,
+    containers: WriteStorage<'a, Container>,
+    interactables: WriteStorage<'a, Interactable>,
+    obstructions: WriteStorage<'a, ObstructionType>,
+    trees: WriteStorage<'a, Tree>,
+    items: WriteStorage<'a, Item>,
+    players: WriteStorage<'a, Player>,
+    pawns: WriteStorage<'a, PawnTraits>,
+    time_available: WriteStorage<'a, TimeAvailable>,
+    /// Levels are kept on the saved `WorldEntity` so they are not part of `SavedComponents`.
+    levels: WriteStorage<'a, ZLevel>,
+}
+
+impl<'a> EntityStorages<'a> {
+    /// Level the player is on.
+    pub fn player_level(&self) -> i32 {
+        ZLevel::of_player(&self.players, &self.levels)
+    }
+
+    /// Put an entity on a level.
+    pub fn set_level(&mut self, entity: Entity, level: i32) {
+        if let Err(e) = self.levels.insert(entity, ZLevel(level)) {
+            error!("Unable to put {:?} on level {}: {}", entity, level, e);
+        }
+    }
+
+    pub fn tree_mut(&mut self, entity: Entity) -> Option<&mut Tree> {
+        self.trees.get_mut(entity)
+    }
+
+    /// Mark an entity so it is saved with the chunk it stands in.
+    pub fn mark(&mut self, entity: Entity) {
+        self.allocator.mark(entity, &mut self.markers);
+    }
+
+    /// Serialize every marked entity standing in the chunk, together with everything it carries,
+    /// into the chunk and remove them from the world.
+    pub fn store(&mut self, chunk: &mut Chunk) {
+        let pos = chunk.pos;
+        let taken = self.take(|at| at == pos);
+        if !taken.is_empty() {
+            chunk
+                .entities
+                .extend(taken.into_iter().map(|(_, entity)| entity));
+            chunk.mark_changed();
+        }
+    }
+
+    /// Take marked entities which wandered outside of the loaded chunks out of the world. They are
+    /// handed to their chunk once it loads.
+    pub fn store_strays(&mut self, chunks: &mut WorldChunks) {
+        for (pos, entity) in self.take(|at| !chunks.inner.contains_key(&at)) {
+            chunks.offload(pos, entity);
+        }
+    }
+
+    /// Serialize and remove every marked entity standing in a chunk accepted by `filter`. Each
+    /// entity is saved together with everything it carries so their parent links stay intact.
+    fn take<F>(&mut self, filter: F) -> Vec<(ChunkPos, WorldEntity)>
+    where
+        F: Fn(ChunkPos) -> bool,
+    {
+        let mut groups = Vec::new();
+        for (entity, transform, _, _) in (
+            &*self.entities,
+            &self.transforms,
+            &self.markers,
+            !&self.parents,
+        )
+            .join()
+        {
+            let pos = WorldPos::from_translation(transform.translation());
+            let level = self.levels.get(entity).cloned().unwrap_or_default().0;
+            if self.players.get(entity).is_some() || !filter(pos.chunk(level)) {
+                continue;
+            }
+
+            let mut group = vec![entity];
+            for (child, _) in (&*self.entities, &self.hierarchy.all_children(entity)).join() {
+                group.push(child);
+            }
+            groups.push((pos, level, group));
+        }
+
+        let mut taken = Vec::with_capacity(groups.len());
+        for (pos, level, group) in groups {
+            // Children have to be marked for their parent links to be saved
+            for entity in &group {
+                self.allocator.mark(*entity, &mut self.markers);
+            }
+
+            let saved = group
+                .iter()
+                .filter_map(|entity| self.save_entity(*entity))
+                .collect::<Vec<_>>();
+            if saved.len() != group.len() {
+                error!("Unable to save all entities carried by {:?}", group[0]);
+                continue;
+            }
+
+            for entity in group {
+                if let Err(e) = self.entities.delete(entity) {
+                    error!("Unable to remove saved entity from the world: {}", e);
+                }
+            }
+
+            let tiles = pos.tiles();
+            let world_entity = WorldEntity::new(Vector2::new(tiles.x as f64, tiles.y as f64))
+                .on_level(level)
+                .with_saved(saved);
+            taken.push((pos.chunk(level), world_entity));
+        }
+        taken
+    }
+
+    /// Recreate all entities which were saved with the chunk. Returns the recreated entities.
+    pub fn restore(&mut self, chunk: &mut Chunk) -> Vec<Entity> {
+        let saved = chunk
+            .entities
+            .drain_filter(|e| !e.saved.is_empty())
+            .collect::<Vec<_>>();
+
+        if saved.is_empty() {
+            return Vec::new();
+        }
+
+        // The entities live in the world now and have to be saved from there again
+        chunk.mark_changed();
+        let mut restored = Vec::new();
+        for entity in saved {
+            let batch = self.restore_all(entity.saved);
+            // The entity comes first, followed by everything it carries
+            if let Some(root) = batch.first() {
+                self.levels.insert(*root, ZLevel(entity.level)).unwrap();
+            }
+            restored.extend(batch);
+        }
+        restored
+    }
+
+    /// Turn the objects scattered over a freshly generated chunk into entities. They are marked
+    /// so they are saved with the chunk from then on. Returns the spawned entities.
+    #[cfg(feature = "mapgen")]
+    pub fn spawn_scattered(&mut self, chunk: &mut Chunk) -> Vec<Entity> {
+        if chunk.scattered.is_empty() {
+            return Vec::new();
+        }
+
+        chunk.mark_changed();
+        let mut spawned = Vec::with_capacity(chunk.scattered.len());
+        for object in chunk.scattered.drain(..) {
+            let entity = self.entities.create();
+
+            let pos = WorldPos::from_tiles(object.pos).vector();
+            let mut transform = Transform::default();
+            transform.set_translation_xyz(pos.x, pos.y, 0.0);
+            self.transforms.insert(entity, transform).unwrap();
+            self.levels.insert(entity, ZLevel(chunk.pos.z)).unwrap();
+
+            let interactions = match object.kind {
+                ScatterKind::Tree(kind) => {
+                    self.trees
+                        .insert(entity, Tree::new(kind, object.size, object.size))
+                        .unwrap();
+                    InteractionType::CHOP | InteractionType::CUT
+                }
+                ScatterKind::Bush => InteractionType::CUT | InteractionType::PICKUP,
+                ScatterKind::Rock => InteractionType::DIG | InteractionType::HAMMER,
+            };
+            self.interactables
+                .insert(entity, Interactable::new(interactions))
+                .unwrap();
+            self.obstructions
+                .insert(entity, object.obstruction)
+                .unwrap();
+
+            self.allocator.mark(entity, &mut self.markers);
+            spawned.push(entity);
+        }
+        spawned
+    }
+
+    /// Serialize the player and everything in their inventory, starting with the player. The
+    /// player stays in the world.
+    pub fn save_player(&mut self) -> Vec<SavedEntity> {
+        let player = match (&*self.entities, &self.players).join().next() {
+            Some((entity, _)) => entity,
+            None => return Vec::new(),
+        };
+
+        let mut saved = vec![player];
+        for (child, _) in (&*self.entities, &self.hierarchy.all_children(player)).join() {
+            saved.push(child);
+        }
+
+        for entity in &saved {
+            self.allocator.mark(*entity, &mut self.markers);
+        }
+        saved
+            .into_iter()
+            .filter_map(|entity| self.save_entity(entity))
+            .collect()
+    }
+
+    /// Recreate the player and their inventory. Returns the player entity.
+    pub fn restore_player(&mut self, saved: Vec<SavedEntity>) -> Option<Entity> {
+        let restored = self.restore_all(saved);
+        restored
+            .into_iter()
+            .find(|entity| self.players.get(*entity).is_some())
+    }
+
+    /// Recreate a batch of entities which were saved together.
+    ///
+    /// Saved markers only link the entities of a single batch, because the marker allocator starts
+    /// counting from zero again every session. Restored entities are given fresh markers. A batch
+    /// is an entity and everything it carries, or the player and their inventory.
+    fn restore_all(&mut self, saved: Vec<SavedEntity>) -> Vec<Entity> {
+        let created = saved
+            .iter()
+            .map(|saved| (saved.marker, self.entities.create()))
+            .collect::<HashMap<_, _>>();
+
+        let mut restored = Vec::with_capacity(saved.len());
+        for saved in saved {
+            let entity = created[&saved.marker];
+
+            let result = DeserializeComponents::<NoError, SaveMarker>::deserialize_entity(
+                &mut (
+                    &mut self.transforms,
+                    &mut self.parents,
+                    &mut self.containers,
+                    &mut self.interactables,
+                    &mut self.obstructions,
+                    &mut self.trees,
+                    &mut self.players,
+                    &mut self.pawns,
+                    &mut self.time_available,
+                ),
+                entity,
+                saved.components,
+                |marker| created.get(&marker).cloned(),
+            );
+            if let Err(e) = result {
+                error!("Unable to restore saved entity: {}", e);
+            }
+
+            if let Some(item) = saved.item {
+                let handle = self.item_storage.read().handles.get(&item.name).cloned();
+                match handle {
+                    Some(handle) => {
+                        self.items
+                            .insert(
+                                entity,
+                                Item {
+                                    handle,
+                                    properties: item.properties,
+                                },
+                            )
+                            .unwrap();
+                    }
+                    None => warn!("Saved item {} no longer exists", item.name),
+                }
+            }
+
+            self.allocator.mark(entity, &mut self.markers);
+            restored.push(entity);
+        }
+        restored
+    }
+
+    fn save_entity(&self, entity: Entity) -> Option<SavedEntity> {
+        let marker = self.markers.get(entity).cloned()?;
+
+        let markers = &self.markers;
+        let components = SerializeComponents::<NoError, SaveMarker>::serialize_entity(
+            &(
+                &self.transforms,
+                &self.parents,
+                &self.containers,
+                &self.interactables,
+                &self.obstructions,
+                &self.trees,
+                &self.players,
+                &self.pawns,
+                &self.time_available,
+            ),
+            entity,
+            |e| markers.get(e).cloned(),
+        );
+
+        let components = match components {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to save entity {:?}: {}", entity, e);
+                return None;
+            }
+        };
+
+        let item = self.items.get(entity).and_then(|item| {
+            let storage = self.item_storage.read();
+            let name = storage
+                .handles
+                .iter()
+                .find(|(_, handle)| **handle == item.handle)
+                .map(|(name, _)| name.clone());
+
+            if name.is_none() {
+                warn!("Unable to find the name of a saved item");
+            }
+
+            name.map(|name| SavedItem {
+                name,
+                properties: item.properties.clone(),
+            })
+        });
+
+        Some(SavedEntity {
+            marker,
+            components,
+            item,
+        })
+    }
+}
+
+/// World with every resource the entity storages need.
+#[cfg(test)]
+pub(crate) fn test_world() -> amethyst::ecs::World {
+    use amethyst::ecs::SystemData;
+
+    let mut world = amethyst::ecs::World::new();
+    <EntityStorages as SystemData>::setup(&mut world.res);
+    let reader = world.write_storage::<Parent>().register_reader();
+    world.add_resource(ParentHierarchy::new(reader));
+    world.add_resource(ItemStorage::default());
+    world
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::ecs::{saveload::MarkedBuilder, Builder};
+
+    use super::*;
+    use crate::coords::{CHUNK_SIZE, TILE_SIZE};
+    use crate::save::codec::{decode, encode};
+    use crate::save::envelope::Format;
+    use crate::save::pending::PendingEntities;
+    use crate::systems::catch_up::default_hooks;
+    use crate::systems::chunk::TestChunk;
+
+    /// Transform at a position given in chunks.
+    fn ground_at(x: f32, y: f32) -> Transform {
+        let size = CHUNK_SIZE as f32 * TILE_SIZE;
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(x * size, y * size, 0.0);
+        transform
+    }
+
+    #[test]
+    fn entities_round_trip_through_chunk() {
+        let mut world = test_world();
+
+        let transform = ground_at(0.5, 0.25);
+        let saved = world
+            .create_entity()
+            .with(transform.clone())
+            .with(Container)
+            .marked::<SaveMarker>()
+            .build();
+        let unmarked = world.create_entity().with(transform.clone()).build();
+        let elsewhere = ground_at(5.5, 0.5);
+        let far = world
+            .create_entity()
+            .with(elsewhere)
+            .marked::<SaveMarker>()
+            .build();
+
+        let mut chunk = TestChunk::new(ChunkPos::new(0, 0, 0));
+        world.exec(|mut storages: EntityStorages| storages.store(&mut chunk));
+        world.maintain();
+
+        assert_eq!(chunk.entities.len(), 1);
+        assert!(!world.is_alive(saved));
+        assert!(world.is_alive(unmarked));
+        assert!(world.is_alive(far));
+
+        let mut loaded = TestChunk::from(decode(&encode(&chunk, Format::Ron).unwrap()).unwrap());
+        world.exec(|mut storages: EntityStorages| storages.restore(&mut loaded));
+        assert!(loaded.entities.is_empty());
+
+        let transforms = world.read_storage::<Transform>();
+        let containers = world.read_storage::<Container>();
+        let restored = (&transforms, &containers).join().collect::<Vec<_>>();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].0.translation(), transform.translation());
+    }
+
+    #[test]
+    fn strays_wait_for_their_chunk() {
+        let mut world = test_world();
+        let transform = ground_at(3.5, 0.5);
+        let stray = world
+            .create_entity()
+            .with(transform.clone())
+            .with(Container)
+            .marked::<SaveMarker>()
+            .build();
+
+        let mut chunks = WorldChunks::new(PendingEntities::default());
+        world.exec(|mut storages: EntityStorages| storages.store_strays(&mut chunks));
+        world.maintain();
+
+        let pos = ChunkPos::new(3, 0, 0);
+        assert!(!world.is_alive(stray));
+        assert_eq!(chunks.pending().waiting(pos), 1);
+
+        let folder = std::env::temp_dir().join("survival_strays");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        chunks
+            .inner
+            .insert(pos, TestChunk::saved_in(&folder, pos).into_inner());
+        let hooks = default_hooks();
+        world.exec(|mut storages: EntityStorages| chunks.activate(pos, 0, &hooks, &mut storages));
+        assert!(chunks.pending().is_empty());
+
+        let transforms = world.read_storage::<Transform>();
+        let containers = world.read_storage::<Container>();
+        let restored = (&transforms, &containers).join().collect::<Vec<_>>();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].0.translation(), transform.translation());
+        drop(TestChunk::from(chunks.inner.remove(&pos).unwrap()));
+    }
+
+    #[test]
+    fn entities_keep_their_level() {
+        let mut world = test_world();
+        world
+            .create_entity()
+            .with(ground_at(0.5, 0.5))
+            .with(ZLevel(-2))
+            .marked::<SaveMarker>()
+            .build();
+
+        let mut surface = TestChunk::new(ChunkPos::new(0, 0, 0));
+        let mut below = TestChunk::new(ChunkPos::new(0, 0, -2));
+        world.exec(|mut storages: EntityStorages| {
+            storages.store(&mut surface);
+            storages.store(&mut below);
+        });
+        world.maintain();
+        assert!(surface.entities.is_empty());
+        assert_eq!(below.entities[0].level, -2);
+
+        let restored = world.exec(|mut storages: EntityStorages| storages.restore(&mut below));
+        assert_eq!(
+            world.read_storage::<ZLevel>().get(restored[0]),
+            Some(&ZLevel(-2))
+        );
+    }
+
+    #[cfg(feature = "mapgen")]
+    #[test]
+    fn scattered_objects_are_saved_with_their_chunk() {
+        use crate::components::TreeKind;
+        use crate::mapgen::Scattered;
+
+        let mut world = test_world();
+        let mut chunk = TestChunk::new(ChunkPos::new(0, 0, 0));
+        chunk.scattered = vec![
+            Scattered {
+                pos: Vector2::new(3.5, 4.5),
+                kind: ScatterKind::Tree(TreeKind::Pine),
+                size: 4.,
+                obstruction: ObstructionType::Vegetation(0.8),
+            },
+            Scattered {
+                pos: Vector2::new(10.5, 2.5),
+                kind: ScatterKind::Rock,
+                size: 1.,
+                obstruction: ObstructionType::Blocking { height: 0.5 },
+            },
+        ];
+
+        let spawned =
+            world.exec(|mut storages: EntityStorages| storages.spawn_scattered(&mut chunk));
+        assert_eq!(spawned.len(), 2);
+        assert!(chunk.scattered.is_empty());
+        {
+            let trees = world.read_storage::<Tree>();
+            assert_eq!(trees.get(spawned[0]).map(Tree::size), Some(4.));
+            assert!(trees.get(spawned[1]).is_none());
+            let interactables = world.read_storage::<Interactable>();
+            assert!(spawned.iter().all(|e| interactables.get(*e).is_some()));
+        }
+
+        world.exec(|mut storages: EntityStorages| storages.store(&mut chunk));
+        world.maintain();
+        assert_eq!(chunk.entities.len(), 2);
+        assert!(spawned.iter().all(|e| !world.is_alive(*e)));
+    }
+
+    #[test]
+    fn player_restores_with_inventory() {
+        let mut world = test_world();
+        let player = world
+            .create_entity()
+            .with(Player)
+            .with(TimeAvailable(5))
+            .marked::<SaveMarker>()
+            .build();
+        let bag = world
+            .create_entity()
+            .with(Container)
+            .with(Parent { entity: player })
+            .marked::<SaveMarker>()
+            .build();
+
+        let saved = world.exec(|storages: EntityStorages| {
+            vec![
+                storages.save_entity(player).unwrap(),
+                storages.save_entity(bag).unwrap(),
+            ]
+        });
+        world.delete_entities(&[player, bag]).unwrap();
+
+        // Restoring the same save twice must not link the second bag to the first player
+        let first = world
+            .exec(|mut storages: EntityStorages| storages.restore_player(saved.clone()))
+            .unwrap();
+        let second = world
+            .exec(|mut storages: EntityStorages| storages.restore_player(saved))
+            .unwrap();
+        assert_ne!(first, second);
+
+        let parents = world.read_storage::<Parent>();
+        let containers = world.read_storage::<Container>();
+        let mut owners = (&parents, &containers)
+            .join()
+            .map(|(parent, _)| parent.entity)
+            .collect::<Vec<_>>();
+        owners.sort();
+        let mut expected = vec![first, second];
+        expected.sort();
+        assert_eq!(owners, expected);
+        assert_eq!(
+            world.read_storage::<TimeAvailable>().get(first),
+            Some(&TimeAvailable(5))
+        );
+    }
+}
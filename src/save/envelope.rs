@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+
+use amethyst::error::{format_err, Error};
+use ron::value::Value;
+use serde::de::DeserializeOwned;
+
+/// Version of the chunk save format written by this build.
+pub const CHUNK_VERSION: u32 = 3;
+
+const HEADER_SIZE: usize = 8;
+
+/// Encoding of the payload following the save header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Ron,
+    Binary,
+    CompressedBinary,
+}
+
+impl Format {
+    fn magic(self) -> [u8; 4] {
+        match self {
+            Format::Ron => *b"SCHK",
+            Format::Binary => *b"SCHB",
+            Format::CompressedBinary => *b"SCHZ",
+        }
+    }
+
+    fn from_magic(magic: &[u8]) -> Option<Self> {
+        [Format::Ron, Format::Binary, Format::CompressedBinary]
+            .iter()
+            .cloned()
+            .find(|format| format.magic() == magic)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub format: Format,
+}
+
+/// Upgrades chunk data saved by one version to the next version.
+pub type Migration = fn(Value) -> Result<Value, Error>;
+
+/// Registered migrations indexed by the version they upgrade from. The length is tied to
+/// `CHUNK_VERSION` so bumping the version without adding a migration fails to compile.
+const CHUNK_MIGRATIONS: [Migration; CHUNK_VERSION as usize] = [legacy_to_v1, v1_to_v2, v2_to_v3];
+
+/// Prefix an encoded chunk with the save header for `CHUNK_VERSION`.
+pub fn wrap(format: Format, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+    data.extend_from_slice(&format.magic());
+    data.extend_from_slice(&CHUNK_VERSION.to_le_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+/// Split a save into its header and payload. Saves written before the header was introduced are
+/// treated as version 0 RON.
+pub fn unwrap(data: &[u8]) -> (Header, &[u8]) {
+    let format = if data.len() < HEADER_SIZE {
+        None
+    } else {
+        Format::from_magic(&data[..4])
+    };
+
+    match format {
+        Some(format) => {
+            let mut version = [0u8; 4];
+            version.copy_from_slice(&data[4..HEADER_SIZE]);
+            let header = Header {
+                version: u32::from_le_bytes(version),
+                format,
+            };
+            (header, &data[HEADER_SIZE..])
+        }
+        None => {
+            let header = Header {
+                version: 0,
+                format: Format::Ron,
+            };
+            (header, data)
+        }
+    }
+}
+
+/// Check that a save can be read by this build.
+pub fn check_version(version: u32) -> Result<(), Error> {
+    if version > CHUNK_VERSION {
+        Err(format_err!(
+            "Chunk was saved with version {} but only versions up to {} are supported",
+            version,
+            CHUNK_VERSION
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Decode a RON payload, running every migration between `version` and `CHUNK_VERSION` first.
+pub fn decode_ron<T: DeserializeOwned>(version: u32, payload: &[u8]) -> Result<T, Error> {
+    check_version(version)?;
+
+    if version == CHUNK_VERSION {
+        return ron::de::from_bytes(payload)
+            .map_err(|e| format_err!("Unable to parse chunk save: {}", e));
+    }
+
+    let value: Value = ron::de::from_bytes(payload)
+        .map_err(|e| format_err!("Unable to parse version {} chunk save: {}", version, e))?;
+    migrate(version, value)
+}
+
+/// Run every migration between `version` and `CHUNK_VERSION` over a chunk in the form it had at
+/// `version`.
+pub fn migrate<T: DeserializeOwned>(version: u32, mut value: Value) -> Result<T, Error> {
+    check_version(version)?;
+
+    for (from, migration) in CHUNK_MIGRATIONS.iter().enumerate().skip(version as usize) {
+        value = migration(value)
+            .map_err(|e| format_err!("Unable to migrate chunk from version {}: {}", from, e))?;
+    }
+
+    value
+        .into_rust()
+        .map_err(|e| format_err!("Unable to read migrated chunk save: {}", e))
+}
+
+/// Version 0 saves are bare chunks without a header. The data itself did not change.
+fn legacy_to_v1(value: Value) -> Result<Value, Error> {
+    Ok(value)
+}
+
+/// Version 2 records when the chunk was unloaded. Older chunks have no record, which the missing
+/// field already decodes to.
+fn v1_to_v2(value: Value) -> Result<Value, Error> {
+    Ok(value)
+}
+
+/// Version 3 splits the tiles into layers. The old tiles become the ground and the other layers
+/// start out empty.
+fn v2_to_v3(value: Value) -> Result<Value, Error> {
+    let mut chunk = match value {
+        Value::Map(v) => v,
+        _ => return Err(format_err!("Chunk save is not a struct")),
+    };
+
+    let tiles = chunk
+        .remove(&Value::String("tiles".to_string()))
+        .ok_or_else(|| format_err!("Chunk save has no tiles"))?;
+    let mut layers = BTreeMap::new();
+    layers.insert(Value::String("ground".to_string()), tiles);
+    chunk.insert(Value::String("layers".to_string()), Value::Map(layers));
+
+    Ok(Value::Map(chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::chunk::Chunk;
+    use crate::systems::chunk_layers::EMPTY_LAYER;
+    use crate::tiles::TileId;
+
+    const CHUNK_V0: &str = include_str!("../../tests/fixtures/saves/chunk_v0.ron");
+    const CHUNK_V1: &[u8] = include_bytes!("../../tests/fixtures/saves/chunk_v1.ron");
+    const CHUNK_V2: &[u8] = include_bytes!("../../tests/fixtures/saves/chunk_v2.ron");
+
+    #[test]
+    fn wrap_round_trip() {
+        let data = wrap(Format::Binary, b"(entities: [])");
+        let header = Header {
+            version: CHUNK_VERSION,
+            format: Format::Binary,
+        };
+        assert_eq!(unwrap(&data), (header, &b"(entities: [])"[..]));
+    }
+
+    #[test]
+    fn unversioned_is_legacy() {
+        let header = Header {
+            version: 0,
+            format: Format::Ron,
+        };
+        assert_eq!(unwrap(b"(entities: [])"), (header, &b"(entities: [])"[..]));
+        assert_eq!(unwrap(b""), (header, &b""[..]));
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        assert!(decode_ron::<Chunk>(CHUNK_VERSION + 1, b"(entities: [])").is_err());
+    }
+
+    #[test]
+    fn migrate_v0_fixture() {
+        let (header, payload) = unwrap(CHUNK_V0.as_bytes());
+        let chunk: Chunk = decode_ron(header.version, payload).unwrap();
+        assert_eq!(chunk.layers.ground[0][0], TileId(129));
+        assert_eq!(chunk.layers.ground[0][1], TileId(130));
+        assert_eq!(chunk.layers.ground[15][0], TileId(130));
+        assert_eq!(chunk.layers.object, EMPTY_LAYER);
+        assert!(chunk.entities.is_empty());
+    }
+
+    #[test]
+    fn migrate_v1_fixture() {
+        let (header, payload) = unwrap(CHUNK_V1);
+        assert_eq!(header.version, 1);
+        assert_eq!(header.format, Format::Ron);
+        let chunk: Chunk = decode_ron(header.version, payload).unwrap();
+        assert_eq!(chunk.layers.ground[0][0], TileId(129));
+        assert_eq!(chunk.layers.ground[15][15], TileId(129));
+        assert_eq!(chunk.unloaded_at, None);
+        assert!(chunk.entities.is_empty());
+    }
+
+    #[test]
+    fn migrate_v2_fixture() {
+        let (header, payload) = unwrap(CHUNK_V2);
+        assert_eq!(header.version, 2);
+        let chunk: Chunk = decode_ron(header.version, payload).unwrap();
+        assert_eq!(chunk.layers.ground[0][0], TileId(129));
+        assert_eq!(chunk.layers.ground[0][1], TileId(130));
+        assert_eq!(chunk.layers.ground[15][0], TileId(130));
+        assert_eq!(chunk.layers.object, EMPTY_LAYER);
+        assert_eq!(chunk.unloaded_at, Some(42));
+    }
+}
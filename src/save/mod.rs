@@ -0,0 +1,152 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashMap;
+
+use crate::coords::{ChunkPos, SURFACE_LEVEL};
+
+pub use region::{Region, RegionFiles, REGION_SIZE};
+pub use world::{ActiveWorld, WorldManifest, Worlds};
+
+pub mod codec;
+pub mod entities;
+pub mod envelope;
+pub mod pending;
+pub mod player;
+pub mod region;
+pub mod world;
+
+/// Report of a chunk which could not be decoded and was moved out of its region.
+#[derive(Clone, Debug)]
+pub struct CorruptChunk {
+    pub pos: ChunkPos,
+    pub reason: String,
+    /// File the damaged chunk data was moved to.
+    pub quarantined: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum ChunkError {
+    /// The chunk was damaged and has been quarantined. It is safe to replace it.
+    Corrupt(CorruptChunk),
+    /// The save could not be accessed. The chunk must not be replaced or its data may be lost.
+    Io(io::Error),
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChunkError::Corrupt(report) => write!(
+                f,
+                "Chunk {:?} is corrupt ({}) and was moved to {:?}",
+                report.pos, report.reason, report.quarantined
+            ),
+            ChunkError::Io(e) => write!(f, "Unable to access chunk save: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChunkError {}
+
+impl From<io::Error> for ChunkError {
+    fn from(e: io::Error) -> Self {
+        ChunkError::Io(e)
+    }
+}
+
+/// Move every legacy `chunk_{x}x{y}.save` file in `folder` into its region file. The legacy files
+/// are removed once their region has been written. Returns the number of converted chunks.
+pub fn convert_chunk_saves<P: AsRef<Path>>(folder: &P) -> io::Result<usize> {
+    let mut regions = HashMap::new();
+    for entry in fs::read_dir(folder.as_ref())? {
+        let path = entry?.path();
+        if let Some(pos) = legacy_chunk_pos(&path) {
+            regions
+                .entry(Region::region_pos(pos))
+                .or_insert_with(Vec::new)
+                .push((pos, path));
+        }
+    }
+
+    let mut converted = 0;
+    for (region_pos, chunks) in regions {
+        let mut data = Vec::with_capacity(chunks.len());
+        for (pos, path) in &chunks {
+            data.push((*pos, fs::read(path)?));
+        }
+
+        let mut region = Region::open(folder, region_pos)?;
+        let writes = data
+            .iter()
+            .map(|(pos, bytes)| (*pos, bytes.as_slice()))
+            .collect::<Vec<_>>();
+        region.write_chunks(&writes)?;
+
+        for (_, path) in &chunks {
+            fs::remove_file(path)?;
+        }
+        converted += chunks.len();
+    }
+
+    Ok(converted)
+}
+
+/// Parse the chunk position out of a legacy `chunk_{x}x{y}.save` file name. Legacy saves predate
+/// levels so they are all on the surface.
+fn legacy_chunk_pos(path: &Path) -> Option<ChunkPos> {
+    if !path.is_file() || path.extension()? != "save" {
+        return None;
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+    if !stem.starts_with("chunk_") {
+        return None;
+    }
+    let mut coords = stem["chunk_".len()..].splitn(2, 'x');
+
+    let x = coords.next()?.parse().ok()?;
+    let y = coords.next()?.parse().ok()?;
+    Some(ChunkPos::new(x, y, SURFACE_LEVEL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_legacy_saves() {
+        let folder = std::env::temp_dir().join("survival_convert_legacy");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+
+        fs::write(folder.join("chunk_0x0.save"), b"origin").unwrap();
+        fs::write(folder.join("chunk_-3x-7.save"), b"negative").unwrap();
+        fs::write(folder.join("chunk_40x2.save"), b"second region").unwrap();
+        fs::write(folder.join("notes.txt"), b"not a chunk").unwrap();
+
+        assert_eq!(convert_chunk_saves(&folder).unwrap(), 3);
+        assert!(!folder.join("chunk_0x0.save").exists());
+        assert!(folder.join("notes.txt").exists());
+
+        let mut region = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(0, 0, 0)).unwrap(),
+            Some(b"origin".to_vec())
+        );
+        let mut region = Region::open(&folder, ChunkPos::new(-1, -1, 0)).unwrap();
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(-3, -7, 0)).unwrap(),
+            Some(b"negative".to_vec())
+        );
+        let mut region = Region::open(&folder, ChunkPos::new(1, 0, 0)).unwrap();
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(40, 2, 0)).unwrap(),
+            Some(b"second region".to_vec())
+        );
+
+        // Nothing left to convert
+        assert_eq!(convert_chunk_saves(&folder).unwrap(), 0);
+    }
+}
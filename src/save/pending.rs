@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use amethyst::error::{format_err, Error, ResultExt};
+use ron::ser::to_string;
+use serde::{Deserialize, Serialize};
+
+use crate::coords::ChunkPos;
+use crate::entity::WorldEntity;
+use crate::save::region::replace_file;
+use crate::systems::tile_edit::TileEdit;
+
+/// File inside of every world folder holding the pending entities.
+pub const PENDING_FILE: &str = "pending.ron";
+
+/// Entities which moved into chunks that were not loaded at the time, and tile edits made to such
+/// chunks. They are handed to their chunk once it loads.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PendingEntities {
+    chunks: HashMap<ChunkPos, Vec<WorldEntity>>,
+    /// Tile edits in the order they were made.
+    #[serde(default)]
+    edits: HashMap<ChunkPos, Vec<TileEdit>>,
+    /// World folder the entities were read from and are flushed back into.
+    #[serde(skip)]
+    folder: Option<PathBuf>,
+}
+
+impl PendingEntities {
+    pub fn push(&mut self, pos: ChunkPos, entity: WorldEntity) {
+        self.chunks.entry(pos).or_insert_with(Vec::new).push(entity);
+    }
+
+    /// Remove all entities waiting for the chunk at `pos`.
+    pub fn take(&mut self, pos: ChunkPos) -> Vec<WorldEntity> {
+        self.chunks.remove(&pos).unwrap_or_default()
+    }
+
+    /// Number of entities waiting for the chunk at `pos`.
+    pub fn waiting(&self, pos: ChunkPos) -> usize {
+        self.chunks.get(&pos).map_or(0, Vec::len)
+    }
+
+    pub fn push_edit(&mut self, edit: TileEdit) {
+        self.edits
+            .entry(edit.pos.chunk())
+            .or_insert_with(Vec::new)
+            .push(edit);
+    }
+
+    /// Remove all tile edits waiting for the chunk at `pos`.
+    pub fn take_edits(&mut self, pos: ChunkPos) -> Vec<TileEdit> {
+        self.edits.remove(&pos).unwrap_or_default()
+    }
+
+    /// Number of tile edits waiting for the chunk at `pos`.
+    pub fn edits_waiting(&self, pos: ChunkPos) -> usize {
+        self.edits.get(&pos).map_or(0, Vec::len)
+    }
+
+    /// Number of waiting entities.
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.edits.is_empty()
+    }
+
+    /// Read the pending entities of the world stored in `folder`.
+    pub fn read<P: AsRef<Path>>(folder: &P) -> Result<Self, Error> {
+        let path = folder.as_ref().join(PENDING_FILE);
+        let mut pending = if path.is_file() {
+            let file = fs::File::open(&path)
+                .with_context(|_| format_err!("Failed to open pending entities {:?}", path))?;
+            ron::de::from_reader(file)
+                .map_err(|e| format_err!("Unable to parse pending entities {:?}: {}", path, e))?
+        } else {
+            Self::default()
+        };
+
+        pending.folder = Some(folder.as_ref().to_path_buf());
+        Ok(pending)
+    }
+
+    /// Write the entities back into the world folder they were read from, if any.
+    pub fn flush(&self) -> Result<(), Error> {
+        match &self.folder {
+            Some(folder) => self.write(folder),
+            None => Ok(()),
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, folder: &P) -> Result<(), Error> {
+        let path = folder.as_ref().join(PENDING_FILE);
+        if self.is_empty() {
+            if path.is_file() {
+                fs::remove_file(&path)
+                    .with_context(|_| format_err!("Failed to remove {:?}", path))?;
+            }
+            return Ok(());
+        }
+
+        let ron = to_string(self)
+            .map_err(|e| format_err!("Unable to serialize pending entities: {}", e))?;
+        replace_file(&path, ron.as_bytes())
+            .with_context(|_| format_err!("Failed to write pending entities {:?}", path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::core::math::Vector2;
+
+    use super::*;
+    use crate::coords::TilePos;
+    use crate::systems::chunk_layers::Layer;
+    use crate::tiles::TileId;
+
+    #[test]
+    fn pending_round_trip() {
+        let folder = std::env::temp_dir().join("survival_pending");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+
+        let mut pending = PendingEntities::default();
+        pending.push(
+            ChunkPos::new(-4, 2, 0),
+            WorldEntity::new(Vector2::new(-60.0, 40.0)),
+        );
+        pending.push(
+            ChunkPos::new(-4, 2, 0),
+            WorldEntity::new(Vector2::new(-50.0, 35.0)),
+        );
+        pending.push_edit(TileEdit {
+            pos: TilePos::new(-60, 40, 0),
+            layer: Layer::Floor,
+            tile: TileId(12),
+        });
+        pending.write(&folder).unwrap();
+
+        let mut read = PendingEntities::read(&folder).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(
+            read.take(ChunkPos::new(-4, 2, 0))[1].pos,
+            Vector2::new(-50.0, 35.0)
+        );
+        assert_eq!(read.take_edits(ChunkPos::new(-4, 2, 0))[0].tile, TileId(12));
+
+        // Flushing an empty store cleans up the file
+        read.flush().unwrap();
+        assert!(!folder.join(PENDING_FILE).exists());
+    }
+}
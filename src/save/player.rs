@@ -0,0 +1,51 @@
+use std::fs;
+use std::path::Path;
+
+use amethyst::error::{format_err, Error, ResultExt};
+use ron::ser::to_string;
+use serde::{Deserialize, Serialize};
+
+use crate::save::entities::SavedEntity;
+use crate::save::region::replace_file;
+use crate::systems::time::TimeState;
+
+/// File inside of every world folder holding the player.
+pub const PLAYER_FILE: &str = "player.ron";
+
+/// The player, everything they carry and the world time, saved when the level is left.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PlayerSave {
+    pub time: TimeState,
+    /// Level the player was on.
+    #[serde(default)]
+    pub level: i32,
+    /// The player followed by every entity in their inventory.
+    pub entities: Vec<SavedEntity>,
+}
+
+impl PlayerSave {
+    /// Read the player saved in the world folder. Returns `Ok(None)` for worlds which have never
+    /// been played.
+    pub fn read<P: AsRef<Path>>(folder: &P) -> Result<Option<Self>, Error> {
+        let path = folder.as_ref().join(PLAYER_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let file = fs::File::open(&path)
+            .with_context(|_| format_err!("Failed to open player save {:?}", path))?;
+        ron::de::from_reader(file)
+            .map(Some)
+            .map_err(|e| format_err!("Unable to parse player save {:?}: {}", path, e))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, folder: &P) -> Result<(), Error> {
+        let ron =
+            to_string(self).map_err(|e| format_err!("Unable to serialize player save: {}", e))?;
+
+        let path = folder.as_ref().join(PLAYER_FILE);
+        replace_file(&path, ron.as_bytes())
+            .with_context(|_| format_err!("Failed to write player save {:?}", path))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,714 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use flate2::Crc;
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::coords::{floor_div, ChunkPos, SURFACE_LEVEL};
+
+/// Number of chunks along each side of a region.
+pub const REGION_SIZE: i32 = 32;
+
+const MAGIC: [u8; 4] = *b"SRG2";
+const SLOTS: usize = (REGION_SIZE * REGION_SIZE) as usize;
+const ENTRY_SIZE: usize = 16;
+/// Generation and checksum in front of the entries of a table.
+const TABLE_HEADER_SIZE: usize = 16;
+const TABLE_SIZE: usize = TABLE_HEADER_SIZE + ENTRY_SIZE * SLOTS;
+/// Chunk data is allocated in multiples of this.
+const SECTOR_SIZE: u64 = 512;
+/// Regions kept open at once by `RegionFiles`.
+const MAX_OPEN_REGIONS: usize = 16;
+/// Chunk data starts at the first sector after both tables.
+const DATA_START: u64 =
+    ((MAGIC.len() + 2 * TABLE_SIZE) as u64 + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE;
+
+/// Location of a single chunk inside of the region file. A zeroed entry is an empty slot.
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+struct Entry {
+    offset: u64,
+    len: u32,
+    checksum: u32,
+}
+
+impl Entry {
+    fn read(bytes: &[u8]) -> Self {
+        let mut offset = [0u8; 8];
+        let mut len = [0u8; 4];
+        let mut checksum = [0u8; 4];
+        offset.copy_from_slice(&bytes[0..8]);
+        len.copy_from_slice(&bytes[8..12]);
+        checksum.copy_from_slice(&bytes[12..16]);
+
+        Self {
+            offset: u64::from_le_bytes(offset),
+            len: u32::from_le_bytes(len),
+            checksum: u32::from_le_bytes(checksum),
+        }
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        bytes[0..8].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.len.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.checksum.to_le_bytes());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset == 0
+    }
+
+    /// Sectors taken up by the data of the chunk.
+    fn sectors(&self) -> std::ops::Range<usize> {
+        if self.is_empty() {
+            return 0..0;
+        }
+        let first = ((self.offset - DATA_START) / SECTOR_SIZE) as usize;
+        first..first + sector_count(self.len as usize)
+    }
+}
+
+/// A single file holding a `REGION_SIZE` x `REGION_SIZE` block of chunks on one level. The file
+/// starts with two tables of offsets and checksums for every chunk slot followed by the chunk data
+/// in sectors.
+///
+/// Chunks are updated in place, but never over data the current table points at. New data goes
+/// into free sectors and only then is the table written out, alternating between both copies with
+/// a growing generation. The table with the highest generation whose checksum matches wins when
+/// the region is opened, so a crash in the middle of an update leaves the previous version of the
+/// chunk behind.
+pub struct Region {
+    pos: ChunkPos,
+    file: File,
+    table: Vec<Entry>,
+    generation: u64,
+    /// Sectors holding the data of a chunk of the current table.
+    used: Vec<bool>,
+}
+
+impl Region {
+    /// Open the region at `pos` inside of `folder`, creating an empty region if it is missing.
+    pub fn open<P: AsRef<Path>>(folder: &P, pos: ChunkPos) -> io::Result<Self> {
+        let path = Region::file_name(folder, pos);
+        if !path.exists() {
+            replace_file(&path, &empty_region())?;
+        }
+
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is not a region file", path),
+            ));
+        }
+
+        let mut tables = vec![0u8; 2 * TABLE_SIZE];
+        file.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        file.read_exact(&mut tables)?;
+        let (generation, table) = tables
+            .chunks(TABLE_SIZE)
+            .filter_map(decode_table)
+            .max_by_key(|(generation, _)| *generation)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?} has no intact chunk table", path),
+                )
+            })?;
+
+        Ok(Self {
+            pos,
+            file,
+            used: used_sectors(&table),
+            table,
+            generation,
+        })
+    }
+
+    /// Position of this region in region coordinates. The level is the level of its chunks.
+    pub fn pos(&self) -> ChunkPos {
+        self.pos
+    }
+
+    /// Find the region a chunk belongs to.
+    pub fn region_pos(chunk: ChunkPos) -> ChunkPos {
+        ChunkPos::new(
+            floor_div(chunk.x, REGION_SIZE),
+            floor_div(chunk.y, REGION_SIZE),
+            chunk.z,
+        )
+    }
+
+    /// Regions of the surface level keep the file names they had before there were levels.
+    pub fn file_name<P: AsRef<Path>>(folder: &P, pos: ChunkPos) -> PathBuf {
+        let name = if pos.z == SURFACE_LEVEL {
+            format!("region_{}x{}.region", pos.x, pos.y)
+        } else {
+            format!("region_{}x{}x{}.region", pos.x, pos.y, pos.z)
+        };
+        folder.as_ref().join(name)
+    }
+
+    /// Check if a chunk has been written to this region.
+    pub fn contains(&self, chunk: ChunkPos) -> bool {
+        self.slot(chunk)
+            .map(|slot| !self.table[slot].is_empty())
+            .unwrap_or(false)
+    }
+
+    /// Read the data of a single chunk. Returns `None` if the chunk was never written and an
+    /// `InvalidData` error if the data does not match its checksum.
+    pub fn read_chunk(&mut self, chunk: ChunkPos) -> io::Result<Option<Vec<u8>>> {
+        let slot = self.slot_or_err(chunk)?;
+        let data = match self.read_slot(slot)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        if checksum(&data) != self.table[slot].checksum {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Chunk {:?} does not match its checksum", chunk),
+            ));
+        }
+        Ok(Some(data))
+    }
+
+    /// Write the data of a single chunk, replacing any previous data for it.
+    pub fn write_chunk(&mut self, chunk: ChunkPos, data: &[u8]) -> io::Result<()> {
+        self.write_chunks(&[(chunk, data)])
+    }
+
+    /// Write several chunks with a single update of the chunk table.
+    pub fn write_chunks(&mut self, chunks: &[(ChunkPos, &[u8])]) -> io::Result<()> {
+        let mut table = self.table.clone();
+        let mut used = self.used.clone();
+
+        for (chunk, data) in chunks {
+            let slot = self.slot_or_err(*chunk)?;
+            let count = sector_count(data.len());
+            let first = allocate(&mut used, count);
+
+            // Always write out whole sectors so the file never ends inside of a chunk
+            let offset = DATA_START + first as u64 * SECTOR_SIZE;
+            let mut padded = data.to_vec();
+            padded.resize(count * SECTOR_SIZE as usize, 0);
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&padded)?;
+
+            table[slot] = Entry {
+                offset,
+                len: data.len() as u32,
+                checksum: checksum(data),
+            };
+        }
+
+        self.commit(table)
+    }
+
+    /// Forget a chunk so it is treated as never written.
+    pub fn remove_chunk(&mut self, chunk: ChunkPos) -> io::Result<()> {
+        let slot = self.slot_or_err(chunk)?;
+        let mut table = self.table.clone();
+        table[slot] = Entry::default();
+        self.commit(table)
+    }
+
+    /// Move the raw data of a chunk out of the region into `folder` so it is not lost when the
+    /// chunk is saved again. Returns the file the data was moved to.
+    pub fn quarantine<P: AsRef<Path>>(
+        &mut self,
+        chunk: ChunkPos,
+        folder: &P,
+    ) -> io::Result<PathBuf> {
+        let slot = self.slot_or_err(chunk)?;
+        let data = self.read_slot(slot)?.unwrap_or_default();
+
+        fs::create_dir_all(folder.as_ref())?;
+        let name = format!("chunk_{}x{}x{}", chunk.x, chunk.y, chunk.z);
+        let mut path = folder.as_ref().join(format!("{}.corrupt", name));
+        let mut attempt = 1;
+        while path.exists() {
+            path = folder
+                .as_ref()
+                .join(format!("{}.{}.corrupt", name, attempt));
+            attempt += 1;
+        }
+
+        replace_file(&path, &data)?;
+        self.remove_chunk(chunk)?;
+        Ok(path)
+    }
+
+    fn read_slot(&mut self, slot: usize) -> io::Result<Option<Vec<u8>>> {
+        let entry = self.table[slot];
+        if entry.is_empty() {
+            return Ok(None);
+        }
+
+        let mut data = vec![0u8; entry.len as usize];
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Make a new table current once the data it points at is on disk. The table goes into the
+    /// copy which is not current, so the current one stays intact if the write is cut short.
+    fn commit(&mut self, table: Vec<Entry>) -> io::Result<()> {
+        self.file.sync_data()?;
+
+        let generation = self.generation + 1;
+        self.file.seek(SeekFrom::Start(table_offset(generation)))?;
+        self.file.write_all(&encode_table(generation, &table))?;
+        self.file.sync_data()?;
+
+        self.used = used_sectors(&table);
+        self.table = table;
+        self.generation = generation;
+        Ok(())
+    }
+
+    fn slot(&self, chunk: ChunkPos) -> Option<usize> {
+        if Region::region_pos(chunk) != self.pos {
+            return None;
+        }
+
+        let x = chunk.x - self.pos.x * REGION_SIZE;
+        let y = chunk.y - self.pos.y * REGION_SIZE;
+        Some((y * REGION_SIZE + x) as usize)
+    }
+
+    fn slot_or_err(&self, chunk: ChunkPos) -> io::Result<usize> {
+        self.slot(chunk).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Chunk {:?} is not part of region {:?}", chunk, self.pos),
+            )
+        })
+    }
+}
+
+/// Regions of a save folder which stay open between chunk reads and writes. Chunks share region
+/// files, so every clone of the handle goes through the same lock.
+#[derive(Clone, Default)]
+pub struct RegionFiles {
+    folder: Arc<PathBuf>,
+    open: Arc<Mutex<HashMap<ChunkPos, Region>>>,
+}
+
+impl RegionFiles {
+    pub fn new<P: AsRef<Path>>(folder: P) -> Self {
+        Self {
+            folder: Arc::new(folder.as_ref().to_path_buf()),
+            open: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+
+    /// Wait until no other thread is accessing the regions of the folder.
+    pub fn lock(&self) -> OpenRegions {
+        OpenRegions {
+            folder: &self.folder,
+            open: self.open.lock(),
+        }
+    }
+}
+
+/// Exclusive access to the regions of a save folder.
+pub struct OpenRegions<'a> {
+    folder: &'a Path,
+    open: MutexGuard<'a, HashMap<ChunkPos, Region>>,
+}
+
+impl<'a> OpenRegions<'a> {
+    /// The region at `pos`, created if it is missing.
+    pub fn get(&mut self, pos: ChunkPos) -> io::Result<&mut Region> {
+        if !self.open.contains_key(&pos) {
+            if self.open.len() >= MAX_OPEN_REGIONS {
+                self.open.clear();
+            }
+            let region = Region::open(&self.folder, pos)?;
+            self.open.insert(pos, region);
+        }
+        Ok(self.open.get_mut(&pos).unwrap())
+    }
+
+    /// The region at `pos`, or `None` if nothing has been written to it yet.
+    pub fn existing(&mut self, pos: ChunkPos) -> io::Result<Option<&mut Region>> {
+        if !self.open.contains_key(&pos) && !Region::file_name(&self.folder, pos).is_file() {
+            return Ok(None);
+        }
+        self.get(pos).map(Some)
+    }
+}
+
+fn sector_count(len: usize) -> usize {
+    (len + SECTOR_SIZE as usize - 1) / SECTOR_SIZE as usize
+}
+
+/// Offset of the table copy a generation is written to.
+fn table_offset(generation: u64) -> u64 {
+    MAGIC.len() as u64 + (generation % 2) * TABLE_SIZE as u64
+}
+
+fn table_checksum(bytes: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(&bytes[0..8]);
+    crc.update(&bytes[TABLE_HEADER_SIZE..]);
+    crc.sum()
+}
+
+fn encode_table(generation: u64, table: &[Entry]) -> Vec<u8> {
+    let mut bytes = vec![0u8; TABLE_SIZE];
+    for (entry, slot) in table
+        .iter()
+        .zip(bytes[TABLE_HEADER_SIZE..].chunks_mut(ENTRY_SIZE))
+    {
+        entry.write(slot);
+    }
+    bytes[0..8].copy_from_slice(&generation.to_le_bytes());
+    let sum = table_checksum(&bytes);
+    bytes[8..12].copy_from_slice(&sum.to_le_bytes());
+    bytes
+}
+
+/// Read a table copy. Returns `None` if its write was cut short.
+fn decode_table(bytes: &[u8]) -> Option<(u64, Vec<Entry>)> {
+    let mut generation = [0u8; 8];
+    let mut sum = [0u8; 4];
+    generation.copy_from_slice(&bytes[0..8]);
+    sum.copy_from_slice(&bytes[8..12]);
+    if u32::from_le_bytes(sum) != table_checksum(bytes) {
+        return None;
+    }
+
+    let table = bytes[TABLE_HEADER_SIZE..]
+        .chunks(ENTRY_SIZE)
+        .map(Entry::read)
+        .collect();
+    Some((u64::from_le_bytes(generation), table))
+}
+
+fn used_sectors(table: &[Entry]) -> Vec<bool> {
+    let mut used = Vec::new();
+    for entry in table {
+        let sectors = entry.sectors();
+        if used.len() < sectors.end {
+            used.resize(sectors.end, false);
+        }
+        for sector in sectors {
+            used[sector] = true;
+        }
+    }
+    used
+}
+
+/// Find the first run of `count` free sectors, growing the file if there is none, and mark it as
+/// used. Returns the first sector of the run.
+fn allocate(used: &mut Vec<bool>, count: usize) -> usize {
+    let mut first = 0;
+    while first < used.len() {
+        match used[first..].iter().take(count).position(|used| *used) {
+            Some(taken) => first += taken + 1,
+            None => break,
+        }
+    }
+
+    if used.len() < first + count {
+        used.resize(first + count, false);
+    }
+    for sector in &mut used[first..first + count] {
+        *sector = true;
+    }
+    first
+}
+
+/// Contents of a new region file without any chunks.
+fn empty_region() -> Vec<u8> {
+    let mut contents = vec![0u8; DATA_START as usize];
+    contents[..MAGIC.len()].copy_from_slice(&MAGIC);
+    let encoded = encode_table(0, &vec![Entry::default(); SLOTS]);
+    for copy in 0..2 {
+        let start = table_offset(copy) as usize;
+        contents[start..start + TABLE_SIZE].copy_from_slice(&encoded);
+    }
+    contents
+}
+
+/// Atomically replace the contents of `path` by writing a temporary file and renaming it.
+pub(crate) fn replace_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("survival_region_{}", name));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn region_pos_negative() {
+        assert_eq!(
+            Region::region_pos(ChunkPos::new(0, 0, 0)),
+            ChunkPos::new(0, 0, 0)
+        );
+        assert_eq!(
+            Region::region_pos(ChunkPos::new(31, 31, 0)),
+            ChunkPos::new(0, 0, 0)
+        );
+        assert_eq!(
+            Region::region_pos(ChunkPos::new(32, -1, 0)),
+            ChunkPos::new(1, -1, 0)
+        );
+        assert_eq!(
+            Region::region_pos(ChunkPos::new(-32, -33, 0)),
+            ChunkPos::new(-1, -2, 0)
+        );
+    }
+
+    #[test]
+    fn levels_have_their_own_regions() {
+        let folder = test_folder("levels");
+        assert_eq!(
+            Region::region_pos(ChunkPos::new(33, -1, -2)),
+            ChunkPos::new(1, -1, -2)
+        );
+        assert!(Region::file_name(&folder, ChunkPos::new(0, 0, 0)).ends_with("region_0x0.region"));
+        assert!(
+            Region::file_name(&folder, ChunkPos::new(0, 0, -1)).ends_with("region_0x0x-1.region")
+        );
+
+        let mut surface = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+        surface
+            .write_chunk(ChunkPos::new(1, 1, 0), b"grass")
+            .unwrap();
+        assert!(surface
+            .write_chunk(ChunkPos::new(1, 1, -1), b"rock")
+            .is_err());
+
+        let mut below = Region::open(&folder, ChunkPos::new(0, 0, -1)).unwrap();
+        below.write_chunk(ChunkPos::new(1, 1, -1), b"rock").unwrap();
+        assert_eq!(
+            surface.read_chunk(ChunkPos::new(1, 1, 0)).unwrap(),
+            Some(b"grass".to_vec())
+        );
+        assert_eq!(
+            below.read_chunk(ChunkPos::new(1, 1, -1)).unwrap(),
+            Some(b"rock".to_vec())
+        );
+    }
+
+    #[test]
+    fn region_round_trip() {
+        let folder = test_folder("round_trip");
+
+        {
+            let mut region = Region::open(&folder, ChunkPos::new(-1, 0, 0)).unwrap();
+            assert!(!region.contains(ChunkPos::new(-1, 0, 0)));
+            region
+                .write_chunk(ChunkPos::new(-1, 0, 0), b"first")
+                .unwrap();
+            region
+                .write_chunk(ChunkPos::new(-32, 31, 0), b"second")
+                .unwrap();
+            assert!(region
+                .write_chunk(ChunkPos::new(0, 0, 0), b"wrong region")
+                .is_err());
+        }
+
+        let mut region = Region::open(&folder, ChunkPos::new(-1, 0, 0)).unwrap();
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(-1, 0, 0)).unwrap(),
+            Some(b"first".to_vec())
+        );
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(-32, 31, 0)).unwrap(),
+            Some(b"second".to_vec())
+        );
+        assert_eq!(region.read_chunk(ChunkPos::new(-2, 0, 0)).unwrap(), None);
+        assert!(!Region::file_name(&folder, ChunkPos::new(-1, 0, 0))
+            .with_extension("tmp")
+            .exists());
+    }
+
+    #[test]
+    fn region_update_keeps_neighbors() {
+        let folder = test_folder("update");
+        let mut region = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+
+        region
+            .write_chunks(&[
+                (ChunkPos::new(1, 1, 0), b"a chunk"),
+                (ChunkPos::new(2, 1, 0), b"its neighbor"),
+            ])
+            .unwrap();
+
+        let large = vec![7u8; 4096];
+        region.write_chunk(ChunkPos::new(1, 1, 0), &large).unwrap();
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(1, 1, 0)).unwrap(),
+            Some(large)
+        );
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(2, 1, 0)).unwrap(),
+            Some(b"its neighbor".to_vec())
+        );
+
+        region.remove_chunk(ChunkPos::new(2, 1, 0)).unwrap();
+        assert!(!region.contains(ChunkPos::new(2, 1, 0)));
+        assert!(region.contains(ChunkPos::new(1, 1, 0)));
+    }
+
+    #[test]
+    fn region_detects_corruption() {
+        let folder = test_folder("corruption");
+        {
+            let mut region = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+            region
+                .write_chunks(&[
+                    (ChunkPos::new(0, 0, 0), b"will be damaged"),
+                    (ChunkPos::new(1, 0, 0), b"intact"),
+                ])
+                .unwrap();
+        }
+
+        // Flip a byte inside of the first chunk's data
+        let path = Region::file_name(&folder, ChunkPos::new(0, 0, 0));
+        let mut contents = std::fs::read(&path).unwrap();
+        contents[DATA_START as usize + 2] ^= 0xFF;
+        std::fs::write(&path, &contents).unwrap();
+
+        let mut region = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+        let err = region.read_chunk(ChunkPos::new(0, 0, 0)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(1, 0, 0)).unwrap(),
+            Some(b"intact".to_vec())
+        );
+
+        // Quarantining keeps the damaged data and frees the slot
+        let quarantine = folder.join("quarantine");
+        let moved = region
+            .quarantine(ChunkPos::new(0, 0, 0), &quarantine)
+            .unwrap();
+        assert_eq!(
+            std::fs::read(&moved).unwrap().len(),
+            b"will be damaged".len()
+        );
+        assert!(!region.contains(ChunkPos::new(0, 0, 0)));
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(1, 0, 0)).unwrap(),
+            Some(b"intact".to_vec())
+        );
+
+        let again = region
+            .quarantine(ChunkPos::new(1, 0, 0), &quarantine)
+            .unwrap();
+        assert_ne!(moved, again);
+    }
+
+    #[test]
+    fn updates_reuse_free_sectors() {
+        let folder = test_folder("reuse");
+        let mut region = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+        let path = Region::file_name(&folder, ChunkPos::new(0, 0, 0));
+
+        let data = vec![3u8; 1500];
+        for _ in 0..10 {
+            region.write_chunk(ChunkPos::new(5, 5, 0), &data).unwrap();
+            region
+                .write_chunk(ChunkPos::new(6, 5, 0), b"small")
+                .unwrap();
+        }
+
+        // The old copy of a chunk is only overwritten by the update after the next one
+        let limit = DATA_START + 2 * (3 + 1) * SECTOR_SIZE;
+        assert!(std::fs::metadata(&path).unwrap().len() <= limit);
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(5, 5, 0)).unwrap(),
+            Some(data)
+        );
+    }
+
+    #[test]
+    fn torn_table_keeps_previous_version() {
+        let folder = test_folder("torn");
+        {
+            let mut region = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+            region.write_chunk(ChunkPos::new(0, 0, 0), b"old").unwrap();
+            region.write_chunk(ChunkPos::new(0, 0, 0), b"new").unwrap();
+        }
+
+        // Damage the table of the last update as if the game crashed while writing it
+        let path = Region::file_name(&folder, ChunkPos::new(0, 0, 0));
+        let mut contents = std::fs::read(&path).unwrap();
+        contents[table_offset(2) as usize + TABLE_HEADER_SIZE + 3] ^= 0xFF;
+        std::fs::write(&path, &contents).unwrap();
+
+        let mut region = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(0, 0, 0)).unwrap(),
+            Some(b"old".to_vec())
+        );
+
+        // The damaged copy is the next one to be written
+        region
+            .write_chunk(ChunkPos::new(0, 0, 0), b"newer")
+            .unwrap();
+        let mut region = Region::open(&folder, ChunkPos::new(0, 0, 0)).unwrap();
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(0, 0, 0)).unwrap(),
+            Some(b"newer".to_vec())
+        );
+    }
+
+    #[test]
+    fn region_files_share_open_regions() {
+        let folder = test_folder("shared");
+        let regions = RegionFiles::new(&folder);
+        let other = regions.clone();
+
+        assert!(regions
+            .lock()
+            .existing(ChunkPos::new(0, 0, 0))
+            .unwrap()
+            .is_none());
+        regions
+            .lock()
+            .get(ChunkPos::new(0, 0, 0))
+            .unwrap()
+            .write_chunk(ChunkPos::new(3, 4, 0), b"shared")
+            .unwrap();
+
+        let mut open = other.lock();
+        let region = open.existing(ChunkPos::new(0, 0, 0)).unwrap().unwrap();
+        assert_eq!(
+            region.read_chunk(ChunkPos::new(3, 4, 0)).unwrap(),
+            Some(b"shared".to_vec())
+        );
+    }
+}
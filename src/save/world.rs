@@ -0,0 +1,339 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use amethyst::core::math::Vector2;
+use amethyst::error::{format_err, Error, ResultExt};
+use log::{info, warn};
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+
+use crate::save::region::replace_file;
+use crate::settings::TerrainStrategy;
+
+/// File inside of every world folder describing the world.
+pub const MANIFEST_FILE: &str = "world.ron";
+
+/// World the saves from before there were named worlds are moved into.
+pub const LEGACY_WORLD: &str = "default";
+
+/// Everything known about a world without loading any of its chunks.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WorldManifest {
+    pub name: String,
+    pub seed: u64,
+    /// How the land of the world is shaped. Worlds from before it was stored have an island.
+    #[serde(default)]
+    pub terrain: TerrainStrategy,
+    /// Seconds since the unix epoch at which the world was created.
+    pub created: u64,
+    pub play_time: Duration,
+    /// Version of the game which last saved the world.
+    pub game_version: String,
+    /// Position of the player in tiles when the world was last closed.
+    pub player_position: Option<Vector2<f32>>,
+}
+
+impl WorldManifest {
+    pub fn new(name: &str, seed: u64, terrain: &TerrainStrategy) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            name: name.to_string(),
+            seed,
+            terrain: terrain.clone(),
+            created,
+            play_time: Duration::from_secs(0),
+            game_version: env!("CARGO_PKG_VERSION").to_string(),
+            player_position: None,
+        }
+    }
+
+    /// Read the manifest of the world stored in `folder`.
+    pub fn read<P: AsRef<Path>>(folder: &P) -> Result<Self, Error> {
+        let path = folder.as_ref().join(MANIFEST_FILE);
+        let file = fs::File::open(&path)
+            .with_context(|_| format_err!("Failed to open world manifest {:?}", path))?;
+
+        ron::de::from_reader(file)
+            .map_err(|e| format_err!("Unable to parse world manifest {:?}: {}", path, e))
+    }
+
+    /// Write the manifest into the world stored in `folder`.
+    pub fn write<P: AsRef<Path>>(&self, folder: &P) -> Result<(), Error> {
+        let ron = to_string_pretty(self, PrettyConfig::default())
+            .map_err(|e| format_err!("Unable to serialize world manifest: {}", e))?;
+
+        let path = folder.as_ref().join(MANIFEST_FILE);
+        replace_file(&path, ron.as_bytes())
+            .with_context(|_| format_err!("Failed to write world manifest {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// The world which is currently being played.
+#[derive(Clone, Debug)]
+pub struct ActiveWorld {
+    /// Folder holding the manifest and region files of the world.
+    pub path: PathBuf,
+    pub manifest: WorldManifest,
+}
+
+impl ActiveWorld {
+    /// Write the manifest back into the world folder, marking it as saved by this version.
+    pub fn save_manifest(&mut self) -> Result<(), Error> {
+        self.manifest.game_version = env!("CARGO_PKG_VERSION").to_string();
+        self.manifest.write(&self.path)
+    }
+}
+
+/// Manages the worlds stored as `<root>/<name>/` folders.
+#[derive(Clone, Debug)]
+pub struct Worlds {
+    root: PathBuf,
+}
+
+impl Worlds {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Folder the world with this name is stored in.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.path(name).join(MANIFEST_FILE).is_file()
+    }
+
+    /// Manifests of all worlds sorted by name. Folders with unreadable manifests are skipped.
+    pub fn list(&self) -> Result<Vec<WorldManifest>, Error> {
+        if !self.root.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut worlds = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if !path.join(MANIFEST_FILE).is_file() {
+                continue;
+            }
+
+            match WorldManifest::read(&path) {
+                Ok(manifest) => worlds.push(manifest),
+                Err(e) => warn!("Skipping world {:?}: {}", path, e),
+            }
+        }
+
+        worlds.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(worlds)
+    }
+
+    /// Create a new empty world.
+    pub fn create(
+        &self,
+        name: &str,
+        seed: u64,
+        terrain: &TerrainStrategy,
+    ) -> Result<ActiveWorld, Error> {
+        let path = self.vacant_path(name)?;
+        fs::create_dir_all(&path)
+            .with_context(|_| format_err!("Failed to create world folder {:?}", path))?;
+
+        let manifest = WorldManifest::new(name, seed, terrain);
+        manifest.write(&path)?;
+        Ok(ActiveWorld { path, manifest })
+    }
+
+    pub fn open(&self, name: &str) -> Result<ActiveWorld, Error> {
+        check_name(name)?;
+        let path = self.path(name);
+        let manifest = WorldManifest::read(&path)?;
+        Ok(ActiveWorld { path, manifest })
+    }
+
+    /// Open the world, creating it with `seed` and `terrain` if it does not exist yet.
+    pub fn open_or_create(
+        &self,
+        name: &str,
+        seed: u64,
+        terrain: &TerrainStrategy,
+    ) -> Result<ActiveWorld, Error> {
+        if self.exists(name) {
+            self.open(name)
+        } else {
+            self.create(name, seed, terrain)
+        }
+    }
+
+    /// Move the single save folder used before there were named worlds into `LEGACY_WORLD`, created
+    /// with `seed` and `terrain`. Its chunk files are converted into region files. Returns false
+    /// if there is no such folder or the world already exists.
+    pub fn migrate_legacy<P: AsRef<Path>>(
+        &self,
+        legacy: &P,
+        seed: u64,
+        terrain: &TerrainStrategy,
+    ) -> Result<bool, Error> {
+        let legacy = legacy.as_ref();
+        if !legacy.is_dir() || self.exists(LEGACY_WORLD) {
+            return Ok(false);
+        }
+
+        let path = self.vacant_path(LEGACY_WORLD)?;
+        fs::create_dir_all(&self.root)
+            .with_context(|_| format_err!("Failed to create worlds folder {:?}", self.root))?;
+        fs::rename(legacy, &path)
+            .with_context(|_| format_err!("Failed to move {:?} into {:?}", legacy, path))?;
+
+        let converted = crate::save::convert_chunk_saves(&path)
+            .with_context(|_| format_err!("Failed to convert the chunk saves in {:?}", path))?;
+        WorldManifest::new(LEGACY_WORLD, seed, terrain).write(&path)?;
+        info!(
+            "Moved {:?} into world {} and converted {} chunk saves",
+            legacy, LEGACY_WORLD, converted
+        );
+        Ok(true)
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> Result<WorldManifest, Error> {
+        let mut world = self.open(from)?;
+        let path = self.vacant_path(to)?;
+        fs::rename(&world.path, &path)
+            .with_context(|_| format_err!("Failed to rename world {} to {}", from, to))?;
+
+        world.manifest.name = to.to_string();
+        world.manifest.write(&path)?;
+        Ok(world.manifest)
+    }
+
+    /// Copy a world and everything in it into a new world.
+    pub fn copy(&self, from: &str, to: &str) -> Result<WorldManifest, Error> {
+        let mut world = self.open(from)?;
+        let path = self.vacant_path(to)?;
+        copy_folder(&world.path, &path)
+            .with_context(|_| format_err!("Failed to copy world {} to {}", from, to))?;
+
+        world.manifest.name = to.to_string();
+        world.manifest.write(&path)?;
+        Ok(world.manifest)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), Error> {
+        check_name(name)?;
+        if !self.exists(name) {
+            return Err(format_err!("World {} does not exist", name));
+        }
+
+        fs::remove_dir_all(self.path(name))
+            .with_context(|_| format_err!("Failed to delete world {}", name))?;
+        Ok(())
+    }
+
+    /// Folder for a new world which must not exist yet.
+    fn vacant_path(&self, name: &str) -> Result<PathBuf, Error> {
+        check_name(name)?;
+        let path = self.path(name);
+        if path.exists() {
+            return Err(format_err!("World {} already exists", name));
+        }
+        Ok(path)
+    }
+}
+
+/// World names are used as folder names so they must not be able to escape the worlds folder.
+fn check_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(|c: char| c == '/' || c == '\\' || c == ':' || c.is_control());
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format_err!("{:?} is not a valid world name", name))
+    }
+}
+
+fn copy_folder(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_folder(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manage_worlds() {
+        let root = std::env::temp_dir().join("survival_worlds");
+        let _ = fs::remove_dir_all(&root);
+        let worlds = Worlds::new(&root);
+
+        assert!(worlds.list().unwrap().is_empty());
+        let flat = TerrainStrategy::Flat(Default::default());
+        let created = worlds.create("first", 42, &flat).unwrap();
+        assert_eq!(created.manifest.seed, 42);
+        assert!(worlds.create("first", 1, &flat).is_err());
+        assert!(worlds.create("../escape", 1, &flat).is_err());
+
+        fs::write(created.path.join("region_0x0.region"), b"chunks").unwrap();
+        worlds.copy("first", "second").unwrap();
+        worlds.rename("first", "third").unwrap();
+
+        let names = worlds
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|manifest| manifest.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["second", "third"]);
+        assert_eq!(worlds.open("second").unwrap().manifest.seed, 42);
+        assert_eq!(worlds.open("second").unwrap().manifest.terrain, flat);
+        assert!(worlds.path("second").join("region_0x0.region").is_file());
+
+        worlds.delete("second").unwrap();
+        assert!(!worlds.exists("second"));
+        assert!(worlds.open("first").is_err());
+        assert_eq!(worlds.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn legacy_saves_become_the_default_world() {
+        let root = std::env::temp_dir().join("survival_legacy_world");
+        let _ = fs::remove_dir_all(&root);
+        let legacy = root.join("saves");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("chunk_0x0.save"), b"old chunk").unwrap();
+
+        let worlds = Worlds::new(root.join("worlds"));
+        let island = TerrainStrategy::default();
+        assert!(worlds.migrate_legacy(&legacy, 7, &island).unwrap());
+        assert!(!legacy.exists());
+
+        let world = worlds.open(LEGACY_WORLD).unwrap();
+        assert_eq!(world.manifest.seed, 7);
+        assert!(world.path.join("region_0x0.region").is_file());
+        assert!(!world.path.join("chunk_0x0.save").exists());
+
+        // Nothing is left to move the next time the game starts
+        fs::create_dir_all(&legacy).unwrap();
+        assert!(!worlds.migrate_legacy(&legacy, 7, &island).unwrap());
+        assert!(legacy.is_dir());
+    }
+}
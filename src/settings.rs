@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 #[serde(default)]
 pub struct Config {
     pub graphics: Graphics,
+    pub world: World,
+    pub streaming: Streaming,
 }
 
 #[derive(Clone, Default, Debug, Deserialize, PartialEq, Serialize)]
@@ -12,4 +14,155 @@ pub struct Graphics {
     pub scale: f32,
 }
 
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct World {
+    /// Name of the world which is opened when the game starts.
+    pub name: String,
+    /// Seed new worlds are created with. Existing worlds keep the seed stored in their manifest.
+    pub seed: u64,
+    /// Terrain new worlds are created with. Existing worlds keep the terrain of their manifest.
+    pub terrain: TerrainStrategy,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        World {
+            name: "default".to_string(),
+            seed: 0,
+            terrain: TerrainStrategy::default(),
+        }
+    }
+}
+
+/// How the land of a world is shaped, with the settings of the strategy.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TerrainStrategy {
+    /// A single island rising from the middle of the map.
+    Island(IslandGeneratorSettings),
+    /// Land from fractal noise, sinking into the sea toward the edges of the map.
+    Continent(ContinentSettings),
+    /// Many small islands scattered over the map.
+    Archipelago(ArchipelagoSettings),
+    /// The same height everywhere, for tests.
+    Flat(FlatSettings),
+}
+
+impl Default for TerrainStrategy {
+    fn default() -> Self {
+        TerrainStrategy::Island(IslandGeneratorSettings::default())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct IslandGeneratorSettings {
+    pub height: f64,
+    pub radius: f64,
+    pub sharpness: f64,
+}
+
+impl Default for IslandGeneratorSettings {
+    fn default() -> Self {
+        Self {
+            height: 1.0,
+            radius: 0.95,
+            sharpness: 0.2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ContinentSettings {
+    /// Height of the land in the middle of the map, before the noise.
+    pub height: f64,
+    /// Number of layers of noise, each with finer detail.
+    pub octaves: usize,
+    /// Number of features of the first layer across the map.
+    pub frequency: f64,
+    /// Amplitude of every layer compared to the one before it.
+    pub persistence: f64,
+    /// Frequency of every layer compared to the one before it.
+    pub lacunarity: f64,
+    /// Height lost from the middle to the edges of the map.
+    pub falloff: f64,
+}
+
+impl Default for ContinentSettings {
+    fn default() -> Self {
+        Self {
+            height: 0.75,
+            octaves: 6,
+            frequency: 3.0,
+            persistence: 0.5,
+            lacunarity: 2.0,
+            falloff: 0.6,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ArchipelagoSettings {
+    pub islands: usize,
+    /// Radius of the smallest island, as a share of the map.
+    pub min_radius: f64,
+    /// Radius of the largest island, as a share of the map.
+    pub max_radius: f64,
+    /// Height of the peaks of the islands.
+    pub height: f64,
+    /// Amplitude of the noise roughening the coasts.
+    pub roughness: f64,
+}
+
+impl Default for ArchipelagoSettings {
+    fn default() -> Self {
+        Self {
+            islands: 7,
+            min_radius: 0.08,
+            max_radius: 0.2,
+            height: 0.9,
+            roughness: 0.15,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct FlatSettings {
+    pub height: f64,
+}
+
+impl Default for FlatSettings {
+    fn default() -> Self {
+        Self { height: 0.6 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Streaming {
+    /// Chunks within this many chunks of the player are loaded.
+    pub load_radius: i32,
+    /// Loaded chunks stay resident until they are further than this from the player. Keeping it
+    /// above `load_radius` stops chunks from reloading while walking along a chunk border.
+    pub unload_radius: i32,
+    /// Levels within this many levels above and below the player are loaded.
+    pub level_radius: i32,
+    /// Most chunk loads started in a single frame.
+    pub loads_per_frame: usize,
+}
+
+impl Default for Streaming {
+    fn default() -> Self {
+        Streaming {
+            load_radius: 4,
+            unload_radius: 6,
+            level_radius: 1,
+            loads_per_frame: 4,
+        }
+    }
+}
+
 pub type Context = Option<amethyst::renderer::SpriteSheetHandle>;
@@ -0,0 +1,126 @@
+use amethyst::ecs::Entity;
+
+use crate::save::entities::EntityStorages;
+use crate::systems::chunk::Chunk;
+
+/// Coarse simulation of a subsystem for chunks which were unloaded while time passed.
+pub trait CatchUp: Send + Sync {
+    /// Apply `elapsed` turns to a chunk which just loaded and the entities restored with it.
+    fn catch_up(
+        &self,
+        elapsed: u64,
+        chunk: &mut Chunk,
+        restored: &[Entity],
+        storages: &mut EntityStorages,
+    );
+}
+
+/// Catch-up hooks of every subsystem, run in the order they were registered.
+#[derive(Default)]
+pub struct CatchUpHooks {
+    hooks: Vec<Box<dyn CatchUp>>,
+}
+
+impl CatchUpHooks {
+    pub fn register<H: CatchUp + 'static>(&mut self, hook: H) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    pub fn with<H: CatchUp + 'static>(mut self, hook: H) -> Self {
+        self.register(hook);
+        self
+    }
+
+    pub fn run(
+        &self,
+        elapsed: u64,
+        chunk: &mut Chunk,
+        restored: &[Entity],
+        storages: &mut EntityStorages,
+    ) {
+        for hook in &self.hooks {
+            hook.catch_up(elapsed, chunk, restored, storages);
+        }
+    }
+}
+
+/// Size trees gain every turn.
+const TREE_GROWTH_PER_TURN: f32 = 0.001;
+const MAX_TREE_SIZE: f32 = 10.0;
+
+/// Trees keep growing while nobody is around.
+pub struct TreeGrowth;
+
+impl CatchUp for TreeGrowth {
+    fn catch_up(
+        &self,
+        elapsed: u64,
+        _chunk: &mut Chunk,
+        restored: &[Entity],
+        storages: &mut EntityStorages,
+    ) {
+        for entity in restored {
+            if let Some(tree) = storages.tree_mut(*entity) {
+                tree.grow(elapsed as f32 * TREE_GROWTH_PER_TURN, MAX_TREE_SIZE);
+            }
+        }
+    }
+}
+
+/// Hooks of every subsystem which can catch up.
+pub fn default_hooks() -> CatchUpHooks {
+    CatchUpHooks::default().with(TreeGrowth)
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::core::transform::Transform;
+    use amethyst::ecs::{saveload::MarkedBuilder, Builder, Join};
+
+    use super::*;
+    use crate::components::{Tree, TreeKind};
+    use crate::coords::ChunkPos;
+    use crate::save::entities::{test_world, SaveMarker};
+    use crate::save::pending::PendingEntities;
+    use crate::systems::chunk::{TestChunk, WorldChunks};
+
+    #[test]
+    fn trees_grow_while_unloaded() {
+        let mut world = test_world();
+
+        world
+            .create_entity()
+            .with(Transform::default())
+            .with(Tree::new(TreeKind::Pine, 2.0, 1.0))
+            .marked::<SaveMarker>()
+            .build();
+
+        let mut chunk = TestChunk::new(ChunkPos::new(0, 0, 0));
+        world.exec(|mut storages: EntityStorages| storages.store(&mut chunk));
+        world.maintain();
+        chunk.unloaded_at = Some(100);
+
+        let mut chunks = WorldChunks::new(PendingEntities::default());
+        chunks
+            .inner
+            .insert(ChunkPos::new(0, 0, 0), chunk.into_inner());
+        world.exec(|mut storages: EntityStorages| {
+            chunks.activate(
+                ChunkPos::new(0, 0, 0),
+                1100,
+                &default_hooks(),
+                &mut storages,
+            )
+        });
+
+        let trees = world.read_storage::<Tree>();
+        let sizes = trees.join().map(Tree::size).collect::<Vec<_>>();
+        assert_eq!(sizes.len(), 1);
+        assert!((sizes[0] - 3.0).abs() < 1e-4);
+        assert_eq!(chunks.inner[&ChunkPos::new(0, 0, 0)].unloaded_at, None);
+
+        drop(TestChunk::from(
+            chunks.inner.remove(&ChunkPos::new(0, 0, 0)).unwrap(),
+        ));
+    }
+}
@@ -1,218 +1,628 @@
-use std::fs::{DirBuilder, File};
-#[cfg(not(feature = "no-save"))]
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::fs::DirBuilder;
+use std::io;
+use std::path::Path;
 use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 
 use amethyst::assets::ProgressCounter;
-use amethyst::core::math::Vector2;
+use amethyst::core::ArcThreadPool;
 use amethyst::ecs::prelude::*;
-use amethyst::ecs::{ReadExpect, System, WriteExpect};
+use amethyst::ecs::{Read, ReadExpect, System, WriteExpect};
 use derivative::Derivative;
 use hashbrown::HashMap;
 use log::{error, info, warn};
-use ron::de::from_reader;
-#[cfg(not(feature = "no-save"))]
-use ron::ser::to_string;
-#[cfg(all(feature = "pretty-save", not(feature = "no-save")))]
-use ron::ser::{to_string_pretty, PrettyConfig};
 use serde::{Deserialize, Serialize};
 use shred::DynamicSystemData;
 use shred_derive::SystemData;
 use specs_derive::Component;
 
-use crate::components::PlayerPosition;
+use crate::components::{ObstructionType, PlayerPosition};
+use crate::coords::{ChunkPos, WorldPos, SURFACE_LEVEL};
 use crate::entity::WorldEntity;
 use crate::events::SHEET_INIT;
-use crate::render::tile_pass::{compile_chunk, WriteChunkRender};
+use crate::render::tile_pass::{compile_chunk, compile_placeholder, WriteChunkRender};
+use crate::save::entities::EntityStorages;
+use crate::save::pending::PendingEntities;
+use crate::save::{codec, ActiveWorld, ChunkError, CorruptChunk, Region, RegionFiles};
+use crate::settings::{Config, Streaming, TerrainStrategy};
+use crate::systems::catch_up::{default_hooks, CatchUpHooks};
+use crate::systems::chunk_layers::{ChunkLayers, Layer, TileLayer, EMPTY_LAYER};
+use crate::systems::chunk_scheduler::ChunkScheduler;
+use crate::systems::chunk_worker::{ChunkEvent, ChunkWorker};
+use crate::systems::tile_edit::TileEdit;
+use crate::systems::time::TimeState;
+use crate::systems::world_gen::{LiquidLayer, WorldGenerator};
 use crate::tiles::TileId;
 use crate::tiles::{TileAsset, TileAssets};
 use array_init::array_init;
-use rand::Rng;
-use crate::utils::TILE_SIZE;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[cfg(feature = "mapgen")]
+use crate::mapgen::Scattered;
+
+/// Solid rock filling the levels below the surface.
+const ROCK_TILE: TileId = TileId(177);
 
-#[derive(Serialize, Deserialize, Derivative)]
+/// Grass tiles picked at random for the surface.
+pub(crate) const GRASS_TILES: [TileId; 4] = [TileId(153), TileId(154), TileId(129), TileId(130)];
+
+#[derive(Default, Serialize, Deserialize, Derivative)]
 #[derivative(Debug)]
 pub struct Chunk {
     #[serde(skip)]
-    pub pos: (i32, i32),
+    pub pos: ChunkPos,
     #[derivative(Debug = "ignore")]
-    #[serde(default = [[TileId(0); 16]; 16])]
-    pub tiles: [[TileId; 16]; 16],
+    pub layers: ChunkLayers,
     #[serde(skip)]
-    path: PathBuf,
+    regions: RegionFiles,
     #[serde(skip)]
     requires_save: bool,
     pub entities: Vec<WorldEntity>,
+    /// Turn at which the chunk was last unloaded, used to catch up on the time it was away.
+    #[serde(default)]
+    pub unloaded_at: Option<u64>,
+    /// Objects scattered over a freshly generated chunk, spawned as entities when it activates.
+    #[cfg(feature = "mapgen")]
+    #[serde(skip)]
+    pub scattered: Vec<Scattered>,
+    /// Water of the tiles, taken from the world generator whenever the chunk is loaded.
+    #[serde(skip)]
+    #[derivative(Debug = "ignore")]
+    liquids: LiquidLayer,
 }
 
 impl Chunk {
-    /// Save this chunk to the previous file
+    /// Save this chunk into its region file
     #[cfg(not(feature = "no-save"))]
     fn save(&self) {
         #[cfg(not(feature = "silent-load"))]
         info!("Saving and unloading {:?}", self);
 
-        if self.path.exists() && !self.path.is_file() {
-            error!("Unable to overwrite directory to save chunk!");
-            return;
-        }
-
-        let mut file = match File::create(self.path.clone()) {
+        let serial = match codec::encode(self, codec::SAVE_FORMAT) {
             Ok(v) => v,
             Err(e) => {
-                error!("Unable to open {:?} to save chunk: {}", self.path, e);
+                error!("Unable to encode chunk: {}", e);
                 return;
             }
         };
 
-        #[cfg(feature = "pretty-save")]
-        let save = to_string_pretty(
-            &self,
-            PrettyConfig {
-                depth_limit: 99,
-                enumerate_arrays: true,
-                ..PrettyConfig::default()
-            },
-        );
-
-        #[cfg(not(feature = "pretty-save"))]
-        let save = to_string(&self);
-
-        let serial = match save {
+        let region_pos = Region::region_pos(self.pos);
+        let mut regions = self.regions.lock();
+        let region = match regions.get(region_pos) {
             Ok(v) => v,
             Err(e) => {
-                error!("Unable to serialize chunk: {}", e);
+                error!(
+                    "Unable to open region {:?} to save chunk: {}",
+                    region_pos, e
+                );
                 return;
             }
         };
 
-        if let Err(e) = file.write(serial.as_bytes()) {
-            error!("Unable to write to file {:?} due to {}", self.path, e);
+        if let Err(e) = region.write_chunk(self.pos, &serial) {
+            error!("Unable to write chunk {:?} due to {}", self.pos, e);
         }
     }
 
-    fn read<P: AsRef<Path>>(path: &P, pos: (i32, i32)) -> Option<Self> {
-        if !path.as_ref().exists() {
-            return None;
-        }
-
-        if !path.as_ref().is_file() {
-            warn!("Could not read chunk {:?} due to non file at path", pos);
-            return None;
-        }
+    /// Read a previously saved chunk. Returns `Ok(None)` if the chunk has not been saved before.
+    /// Chunks which fail their checksum or can not be decoded are moved to the quarantine folder.
+    pub fn read(regions: &RegionFiles, pos: ChunkPos) -> Result<Option<Self>, ChunkError> {
+        let mut open = regions.lock();
+        let region = match open.existing(Region::region_pos(pos))? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let decoded = match region.read_chunk(pos) {
+            Ok(Some(data)) => codec::decode(&data).map_err(|e| e.to_string()),
+            Ok(None) => return Ok(None),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => Err(e.to_string()),
+            Err(e) => return Err(e.into()),
+        };
 
-        let file = match File::open(path.as_ref()) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Unable to open file to read chunk: {}", e);
-                return None;
+        let reason = match decoded {
+            Ok(mut found) => {
+                found.pos = pos;
+                found.regions = regions.clone();
+                return Ok(Some(found));
             }
+            Err(reason) => reason,
         };
 
-        from_reader(file).ok()
+        let quarantined = region.quarantine(pos, &regions.folder().join("quarantine"))?;
+        Err(ChunkError::Corrupt(CorruptChunk {
+            pos,
+            reason,
+            quarantined,
+        }))
     }
 
-    /// Generate a new chunk from its coords and possibly more information in future.
-    fn generate(pos: (i32, i32)) -> [[TileId; 16]; 16] {
+    /// Create the rng used to generate a chunk. The stream only depends on the world seed and the
+    /// chunk position so a chunk always regenerates the same way, no matter the load order.
+    pub fn rng(seed: u64, pos: ChunkPos) -> StdRng {
+        // Level zero mixes in nothing so surface chunks keep the streams they had before levels
+        let mut state = seed.wrapping_mul(0x2545_F491_4F6C_DD1D)
+            ^ ((u64::from(pos.x as u32) << 32) | u64::from(pos.y as u32))
+            ^ u64::from(pos.z as u32).wrapping_mul(0xD6E8_FEB8_6659_FD93);
+
+        // Expand the state into a full rng seed with splitmix64
+        let mut bytes = [0u8; 32];
+        for chunk in bytes.chunks_mut(8) {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_le_bytes());
+        }
+
+        StdRng::from_seed(bytes)
+    }
+
+    /// Generate a new chunk from the world seed and its coords. Levels below the surface are solid
+    /// rock and levels above it are empty.
+    pub(crate) fn generate(seed: u64, pos: ChunkPos) -> ChunkLayers {
         #[cfg(not(feature = "silent-load"))]
         info!("Generating new chunk at {:?}", pos);
 
-        let mut rng = rand::thread_rng();
-        let mut tiles: [[TileId; 16]; 16] = array_init(|i| {
-            array_init(|j| match rng.gen_range(0, 4) {
-                0 => TileId(153),
-                1 => TileId(154),
-                2 => TileId(129),
-                _ => TileId(130),
-            })
-        });
-        tiles
-    }
-
-    pub fn load<P: AsRef<Path>>(path: &P, pos: (i32, i32)) -> Self {
-        let path = Chunk::file_name(path, pos);
-        if let Some(mut found) = Chunk::read(&path, pos) {
-            found.pos = pos;
-            found.path = path;
-            found
-        } else {
-            Chunk {
-                pos,
-                tiles: Chunk::generate(pos),
-                path,
-                requires_save: true,
-                entities: Vec::new(),
+        if pos.z < SURFACE_LEVEL {
+            return ChunkLayers::with_ground([[ROCK_TILE; 16]; 16]);
+        }
+        if pos.z > SURFACE_LEVEL {
+            return ChunkLayers::with_ground(EMPTY_LAYER);
+        }
+
+        let mut rng = Chunk::rng(seed, pos);
+        let ground: TileLayer =
+            array_init(|_| array_init(|_| GRASS_TILES[rng.gen_range(0, 4) as usize]));
+        ChunkLayers::with_ground(ground)
+    }
+
+    /// Create a fresh chunk which has never been saved.
+    pub fn generated(regions: &RegionFiles, pos: ChunkPos, generator: &WorldGenerator) -> Self {
+        let layers = generator.generate(pos);
+        Chunk {
+            pos,
+            liquids: generator.liquids(pos, &layers),
+            layers,
+            regions: regions.clone(),
+            requires_save: true,
+            entities: Vec::new(),
+            unloaded_at: None,
+            #[cfg(feature = "mapgen")]
+            scattered: generator.scatter(pos),
+        }
+    }
+
+    /// Read the chunk or generate it if it has never been saved.
+    pub fn load(
+        regions: &RegionFiles,
+        pos: ChunkPos,
+        generator: &WorldGenerator,
+    ) -> Result<Self, ChunkError> {
+        match Chunk::read(regions, pos)? {
+            Some(mut chunk) => {
+                chunk.fill_liquids(generator);
+                Ok(chunk)
+            }
+            None => Ok(Chunk::generated(regions, pos, generator)),
+        }
+    }
+
+    /// Look up the water of the tiles, which is not part of the save.
+    pub fn fill_liquids(&mut self, generator: &WorldGenerator) {
+        self.liquids = generator.liquids(self.pos, &self.layers);
+    }
+
+    /// Water covering a tile inside of the chunk, as the obstruction it forms.
+    pub fn liquid(&self, x: usize, y: usize) -> Option<ObstructionType> {
+        self.liquids[x][y]
+    }
+
+    /// Save the chunk if it has changed since it was loaded.
+    pub fn save_if_required(&mut self) {
+        #[cfg(not(feature = "no-save"))]
+        {
+            if self.requires_save {
+                self.save();
             }
         }
+        self.requires_save = false;
+    }
+
+    /// Tile of a layer at a position inside of the chunk.
+    pub fn tile(&self, layer: Layer, x: usize, y: usize) -> TileId {
+        self.layers.get(layer)[x][y]
     }
 
-    fn file_name<P: AsRef<Path>>(path: &P, pos: (i32, i32)) -> PathBuf {
-        path.as_ref()
-            .join(format!("chunk_{}x{}.save", pos.0, pos.1))
+    /// Replace a tile of a layer. Returns true and flags the chunk to be saved if the tile changed.
+    pub fn set_tile(&mut self, layer: Layer, x: usize, y: usize, tile: TileId) -> bool {
+        let current = &mut self.layers.get_mut(layer)[x][y];
+        if *current == tile {
+            return false;
+        }
+
+        *current = tile;
+        // Changed ground no longer has the water it was generated with
+        if layer == Layer::Ground {
+            self.liquids[x][y] = None;
+        }
+        self.mark_changed();
+        true
+    }
+
+    /// Flag the chunk to be saved when it unloads.
+    pub fn mark_changed(&mut self) {
+        self.requires_save = true;
+    }
+
+    /// Drop the chunk without saving it. Used for duplicate loads of a chunk which is already
+    /// present in the world.
+    pub fn discard(mut self) {
+        self.requires_save = false;
     }
 }
 
+// Saves under the lock of the region files, so dropping a chunk on the game thread never races a
+// worker writing to the same region.
 #[cfg(not(feature = "no-save"))]
 impl Drop for Chunk {
     fn drop(&mut self) {
-        if self.requires_save {
-            self.save()
+        self.save_if_required();
+    }
+}
+
+/// Chunk of a meadow world for tests. It is discarded instead of saved once it goes out of scope,
+/// so tests leave nothing behind in their save folder.
+#[cfg(test)]
+pub(crate) struct TestChunk(Option<Chunk>);
+
+#[cfg(test)]
+impl TestChunk {
+    pub fn new(pos: ChunkPos) -> Self {
+        Self::saved_in(std::env::temp_dir(), pos)
+    }
+
+    pub fn saved_in<P: AsRef<Path>>(folder: P, pos: ChunkPos) -> Self {
+        let regions = RegionFiles::new(folder);
+        TestChunk(Some(Chunk::generated(
+            &regions,
+            pos,
+            &WorldGenerator::meadow(0),
+        )))
+    }
+
+    /// Hand the chunk to code which takes ownership of it, like `WorldChunks`. Wrap it again with
+    /// `TestChunk::from` once the test is done with it.
+    pub fn into_inner(mut self) -> Chunk {
+        self.0.take().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl From<Chunk> for TestChunk {
+    fn from(chunk: Chunk) -> Self {
+        TestChunk(Some(chunk))
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestChunk {
+    type Target = Chunk;
+
+    fn deref(&self) -> &Chunk {
+        self.0.as_ref().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl std::ops::DerefMut for TestChunk {
+    fn deref_mut(&mut self) -> &mut Chunk {
+        self.0.as_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestChunk {
+    fn drop(&mut self) {
+        if let Some(chunk) = self.0.take() {
+            chunk.discard();
         }
     }
 }
 
+/// Where a chunk is in the loading pipeline.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChunkState {
+    /// The chunk is wanted but has not been handed to a worker yet.
+    Requested,
+    /// A worker is reading or generating the chunk. A placeholder is drawn in the meantime.
+    Loading,
+    /// The chunk is present in `WorldChunks::inner`.
+    Ready,
+    /// A worker is saving the chunk. It can not be loaded again until the save has finished.
+    Unloading,
+}
+
 #[derive(Component)]
 pub struct WorldChunks {
-    pub inner: HashMap<(i32, i32), Chunk>,
+    pub inner: HashMap<ChunkPos, Chunk>,
+    states: HashMap<ChunkPos, ChunkState>,
+    corrupted: Vec<CorruptChunk>,
+    pending: PendingEntities,
 }
 
 impl WorldChunks {
-    pub fn new() -> Self {
+    pub fn new(pending: PendingEntities) -> Self {
         WorldChunks {
             inner: HashMap::new(),
+            states: HashMap::new(),
+            corrupted: Vec::new(),
+            pending,
+        }
+    }
+
+    /// Entities waiting for their chunk to load.
+    pub fn pending(&self) -> &PendingEntities {
+        &self.pending
+    }
+
+    /// Hand an entity to the chunk at `pos`, or keep it until the chunk loads.
+    pub fn offload(&mut self, pos: ChunkPos, entity: WorldEntity) {
+        match self.inner.get_mut(&pos) {
+            Some(chunk) => {
+                chunk.entities.push(entity);
+                chunk.mark_changed();
+            }
+            None => self.pending.push(pos, entity),
+        }
+    }
+
+    /// Keep tile edits of chunks which are not loaded until they load.
+    pub fn hold_edits(&mut self, edits: Vec<TileEdit>) {
+        for edit in edits {
+            self.pending.push_edit(edit);
+        }
+        #[cfg(not(feature = "no-save"))]
+        {
+            if let Err(e) = self.pending.flush() {
+                error!("Unable to save tile edits waiting for their chunks: {}", e);
+            }
+        }
+    }
+
+    /// Move the entities and tile edits waiting for a chunk into it. The chunk is saved with them
+    /// before they are dropped from the pending file, so a crash can neither lose nor duplicate
+    /// them. Returns true if the edits changed the tiles of the chunk.
+    pub fn merge_pending(&mut self, chunk: &mut Chunk) -> bool {
+        let pending = self.pending.take(chunk.pos);
+        let edits = self.pending.take_edits(chunk.pos);
+        if pending.is_empty() && edits.is_empty() {
+            return false;
+        }
+
+        let mut changed = false;
+        for edit in edits {
+            let (x, y) = edit.pos.local();
+            changed |= chunk.set_tile(edit.layer, x, y, edit.tile);
+        }
+        chunk.entities.extend(pending);
+        chunk.mark_changed();
+        #[cfg(not(feature = "no-save"))]
+        {
+            chunk.save_if_required();
+            if let Err(e) = self.pending.flush() {
+                error!("Unable to save entities waiting for their chunks: {}", e);
+            }
         }
+        changed
     }
 
-    fn reload_chunks<P: AsRef<Path>>(
+    /// Get the loading state of a chunk. Returns `None` if the chunk is not wanted.
+    pub fn state(&self, pos: ChunkPos) -> Option<ChunkState> {
+        self.states.get(&pos).cloned()
+    }
+
+    /// Chunks which were found corrupted and quarantined since the world was loaded.
+    pub fn corrupted(&self) -> &[CorruptChunk] {
+        &self.corrupted
+    }
+
+    /// Every chunk which is wanted or still being saved, with its loading state.
+    pub fn states(&self) -> impl Iterator<Item = (ChunkPos, ChunkState)> + '_ {
+        self.states.iter().map(|(pos, state)| (*pos, *state))
+    }
+
+    /// Chunks which are loaded and present in `WorldChunks::inner`.
+    pub fn resident(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        self.inner.keys().cloned()
+    }
+
+    /// Number of chunks which are waiting for or in the middle of loading.
+    pub fn loading(&self) -> usize {
+        self.states
+            .values()
+            .filter(|state| **state == ChunkState::Requested || **state == ChunkState::Loading)
+            .count()
+    }
+
+    /// Update which chunks should be loaded after the player entered another chunk. Chunks outside
+    /// of the unload radius take the entities standing in them out of the world and are handed to
+    /// the worker to be saved.
+    fn request(
         &mut self,
-        player: Vector2<f32>,
-        save_path: &P,
-        assets: &[TileAsset],
+        scheduler: &ChunkScheduler,
+        worker: &ChunkWorker,
         renders: &mut WriteChunkRender,
+        storages: &mut EntityStorages,
+        now: u64,
     ) {
         #[cfg(not(feature = "silent-load"))]
-        info!("Performing chunk refresh");
-        // TODO add to config
-        const CHUNK_RADIUS: i32 = 4;
-
-        let player_chunk_x = (player.x / TILE_SIZE).floor() as i32;
-        let player_chunk_y = (player.y / TILE_SIZE).floor() as i32;
-
-        use log::debug;
-        self.inner.retain(|&(x, y), _| {
-            if (player_chunk_x - x).abs() <= CHUNK_RADIUS
-                && (player_chunk_y - y).abs() <= CHUNK_RADIUS
-            {
-                true
-            } else {
-                renders.remove(&(x, y));
-                false
+        info!("Performing chunk refresh around {:?}", scheduler.center());
+
+        let inner = &mut self.inner;
+        self.states.retain(|pos, state| {
+            if scheduler.retains(*pos, *state) {
+                return true;
+            }
+
+            match state {
+                ChunkState::Ready => {
+                    renders.remove(pos);
+                    if let Some(mut chunk) = inner.remove(pos) {
+                        storages.store(&mut chunk);
+                        chunk.unloaded_at = Some(now);
+                        chunk.mark_changed();
+                        worker.unload(chunk);
+                    }
+                    *state = ChunkState::Unloading;
+                    true
+                }
+                // Chunks which are still loading get unloaded once they come back
+                ChunkState::Requested | ChunkState::Loading => {
+                    renders.remove(pos);
+                    false
+                }
+                ChunkState::Unloading => true,
             }
         });
 
-        for x in player_chunk_x - CHUNK_RADIUS..player_chunk_x + CHUNK_RADIUS {
-            for y in player_chunk_y - CHUNK_RADIUS..player_chunk_y + CHUNK_RADIUS {
-                let chunk_pos = (x, y);
-                if !self.inner.contains_key(&chunk_pos) {
-                    #[cfg(not(feature = "silent-load"))]
-                    log::debug!("Creating and adding chunk!");
-                    let chunk = Chunk::load(save_path, chunk_pos);
-                    renders.insert(chunk_pos, compile_chunk(&chunk, assets));
-                    self.inner.insert(chunk_pos, chunk);
+        for pos in scheduler.wanted() {
+            if !self.states.contains_key(&pos) {
+                self.states.insert(pos, ChunkState::Requested);
+            }
+        }
+    }
+
+    /// Start loading the most important requested chunks, up to the per frame budget, and draw
+    /// placeholders until they are ready.
+    fn dispatch(
+        &mut self,
+        generator: &Arc<WorldGenerator>,
+        scheduler: &ChunkScheduler,
+        assets: &Arc<Vec<TileAsset>>,
+        worker: &ChunkWorker,
+        renders: &mut WriteChunkRender,
+    ) {
+        let mut requested = self
+            .states
+            .iter()
+            .filter(|(_, state)| **state == ChunkState::Requested)
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        scheduler.sort(&mut requested);
+
+        for pos in requested.into_iter().take(scheduler.loads_per_frame()) {
+            #[cfg(not(feature = "silent-load"))]
+            log::debug!("Requesting chunk {:?}", pos);
+
+            renders.insert(pos, compile_placeholder(pos, assets));
+            worker.load(pos, generator.clone(), assets.clone());
+            self.states.insert(pos, ChunkState::Loading);
+        }
+    }
+
+    /// Take the entities standing in every loaded chunk out of the world so they are saved with
+    /// their chunks, and record the turn the chunks were unloaded at. Used when the world is
+    /// closed.
+    pub fn store_all(&mut self, storages: &mut EntityStorages, now: u64) {
+        for chunk in self.inner.values_mut() {
+            storages.store(chunk);
+            chunk.unloaded_at = Some(now);
+            chunk.mark_changed();
+        }
+    }
+
+    /// Bring a chunk which just became resident up to date. It is handed the entities and tile
+    /// edits waiting for it, its saved entities are recreated and the time it was unloaded for is
+    /// caught up on. Returns true if its tiles changed since its render was compiled.
+    pub fn activate(
+        &mut self,
+        pos: ChunkPos,
+        now: u64,
+        hooks: &CatchUpHooks,
+        storages: &mut EntityStorages,
+    ) -> bool {
+        let mut chunk = match self.inner.remove(&pos) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        let edited = self.merge_pending(&mut chunk);
+        let restored = storages.restore(&mut chunk);
+        #[cfg(feature = "mapgen")]
+        storages.spawn_scattered(&mut chunk);
+
+        if let Some(unloaded_at) = chunk.unloaded_at.take() {
+            let elapsed = now.saturating_sub(unloaded_at);
+            if elapsed > 0 {
+                hooks.run(elapsed, &mut chunk, &restored, storages);
+            }
+            chunk.mark_changed();
+        }
+
+        self.inner.insert(pos, chunk);
+        edited
+    }
+
+    /// Hand work finished by the chunk workers back to the world. Returns the position of a chunk
+    /// which became resident and has to be activated.
+    fn finish(
+        &mut self,
+        event: ChunkEvent,
+        scheduler: &ChunkScheduler,
+        worker: &ChunkWorker,
+        renders: &mut WriteChunkRender,
+    ) -> Option<ChunkPos> {
+        match event {
+            ChunkEvent::Loaded(chunk, render) => {
+                let pos = chunk.pos;
+                match self.states.get(&pos) {
+                    Some(ChunkState::Loading) => {
+                        renders.insert(pos, render);
+                        self.inner.insert(pos, chunk);
+                        self.states.insert(pos, ChunkState::Ready);
+                        return Some(pos);
+                    }
+                    // A fresh chunk is generated again the next time instead of being saved without
+                    // its scattered objects
+                    #[cfg(feature = "mapgen")]
+                    None if !chunk.scattered.is_empty() => chunk.discard(),
+                    // The chunk stopped being wanted while it was loading
+                    None => {
+                        worker.unload(chunk);
+                        self.states.insert(pos, ChunkState::Unloading);
+                    }
+                    // A newer load of this chunk already exists
+                    Some(_) => chunk.discard(),
+                }
+            }
+            ChunkEvent::Failed(pos, ChunkError::Corrupt(report)) => {
+                error!(
+                    "Chunk {:?} is corrupt and was moved to {:?}: {}",
+                    pos, report.quarantined, report.reason
+                );
+                self.corrupted.push(report);
+            }
+            // Forget the chunk so it gets requested again on the next refresh
+            ChunkEvent::Failed(pos, e) => {
+                error!("Unable to load chunk {:?}: {}", pos, e);
+                if self.states.get(&pos) == Some(&ChunkState::Loading) {
+                    self.states.remove(&pos);
+                }
+            }
+            ChunkEvent::Saved(pos) => {
+                if self.states.get(&pos) == Some(&ChunkState::Unloading) {
+                    if scheduler.wants(pos) {
+                        self.states.insert(pos, ChunkState::Requested);
+                    } else {
+                        self.states.remove(&pos);
+                    }
                 }
             }
         }
+        None
     }
 }
 
@@ -220,26 +630,42 @@ impl WorldChunks {
 pub struct ChunkSystemData<'a> {
     chunks: WriteExpect<'a, WorldChunks>,
     player: ReadExpect<'a, PlayerPosition>,
+    world: ReadExpect<'a, ActiveWorld>,
     tile_assets: ReadExpect<'a, TileAssets>,
     chunk_renders: WriteChunkRender<'a>,
+    storages: EntityStorages<'a>,
+    time: Read<'a, TimeState>,
+    catch_up: ReadExpect<'a, CatchUpHooks>,
     // TODO Use progress counter instead of atomic bool
     #[allow(dead_code)]
     asset_progress: ReadExpect<'a, ProgressCounter>,
 }
 
-#[derive(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct ChunkLoadSystem<P: AsRef<Path>> {
-    player_previous: Vector2<f32>,
-    player_offset: Vector2<f32>,
     save_path: P,
+    scheduler: ChunkScheduler,
+    #[derivative(Debug = "ignore")]
+    assets: Option<Arc<Vec<TileAsset>>>,
+    #[derivative(Debug = "ignore")]
+    worker: Option<ChunkWorker>,
+    #[derivative(Debug = "ignore")]
+    generator: Option<Arc<WorldGenerator>>,
+    /// Seed and terrain of the world whose generator is being built, and where it arrives.
+    #[derivative(Debug = "ignore")]
+    building: Option<(u64, TerrainStrategy, Receiver<WorldGenerator>)>,
 }
 
 impl<P: AsRef<Path>> ChunkLoadSystem<P> {
     pub fn new(path: P) -> Self {
         ChunkLoadSystem {
-            player_previous: Vector2::new(0.0, 0.0),
-            player_offset: Vector2::new(2000.0, 2000.0),
             save_path: path,
+            scheduler: ChunkScheduler::new(Streaming::default()),
+            assets: None,
+            worker: None,
+            generator: None,
+            building: None,
         }
     }
 }
@@ -248,25 +674,90 @@ impl<'a, P: AsRef<Path>> System<'a> for ChunkLoadSystem<P> {
     type SystemData = ChunkSystemData<'a>;
 
     fn run(&mut self, mut data: ChunkSystemData) {
-        self.player_offset = self.player_offset + data.player.0 - self.player_previous;
-        self.player_previous = data.player.0;
+        let worker = match &self.worker {
+            Some(v) => v,
+            None => return,
+        };
 
-        // Don't attempt to reload if there hasn't been a notable change in chunks
-        if self.player_offset.x.abs() < 16.0 && self.player_offset.y.abs() < 16.0
-            || !unsafe { SHEET_INIT.load(Ordering::SeqCst) }
-        {
+        for event in worker.poll() {
+            let resident =
+                data.chunks
+                    .finish(event, &self.scheduler, worker, &mut data.chunk_renders);
+
+            if let Some(pos) = resident {
+                let edited = data.chunks.activate(
+                    pos,
+                    data.time.current_time,
+                    &data.catch_up,
+                    &mut data.storages,
+                );
+                if edited {
+                    let render = compile_chunk(&data.chunks.inner[&pos], &data.tile_assets.0);
+                    data.chunk_renders.insert(pos, render);
+                }
+            }
+        }
+
+        if !unsafe { SHEET_INIT.load(Ordering::SeqCst) } {
             return;
         }
 
         //        use log::debug;
         //        debug!("Complete: {:?} {}/{}", data.asset_progress.complete(), data.asset_progress.num_assets(), data.asset_progress.num_finished());
 
-        // Reset offset
-        self.player_offset = Vector2::new(0.0, 0.0);
-        data.chunks.reload_chunks(
-            data.player.0,
-            &self.save_path,
-            &data.tile_assets.0,
+        let tile_assets = &data.tile_assets;
+        let assets = self
+            .assets
+            .get_or_insert_with(|| Arc::new(tile_assets.0.clone()));
+
+        // Only refresh the wanted chunks once the player enters another chunk
+        let level = data.storages.player_level();
+        if self.scheduler.update(data.player.0, level) {
+            data.chunks.request(
+                &self.scheduler,
+                worker,
+                &mut data.chunk_renders,
+                &mut data.storages,
+                data.time.current_time,
+            );
+        }
+        data.storages.store_strays(&mut data.chunks);
+
+        // The island is only generated again when another world is opened. Until it is ready the
+        // requested chunks keep showing their placeholders.
+        let manifest = &data.world.manifest;
+        let outdated = |seed: u64, terrain: &TerrainStrategy| {
+            seed != manifest.seed || *terrain != manifest.terrain
+        };
+        if let Some(generator) = &self.generator {
+            if outdated(generator.seed(), generator.terrain()) {
+                self.generator = None;
+            }
+        }
+        if self.generator.is_none() {
+            if let Some((seed, terrain, receiver)) = &self.building {
+                if outdated(*seed, terrain) {
+                    self.building = None;
+                } else if let Ok(generator) = receiver.try_recv() {
+                    self.generator = Some(Arc::new(generator));
+                    self.building = None;
+                }
+            }
+            if self.generator.is_none() && self.building.is_none() {
+                let receiver = worker.build_generator(manifest.seed, &manifest.terrain);
+                self.building = Some((manifest.seed, manifest.terrain.clone(), receiver));
+            }
+        }
+        let generator = match &self.generator {
+            Some(v) => v,
+            None => return,
+        };
+
+        data.chunks.dispatch(
+            generator,
+            &self.scheduler,
+            assets,
+            worker,
             &mut data.chunk_renders,
         );
     }
@@ -281,8 +772,19 @@ impl<'a, P: AsRef<Path>> System<'a> for ChunkLoadSystem<P> {
         info!("Using no save mode");
 
         <Self::SystemData as DynamicSystemData>::setup(&self.accessor(), res);
-        res.insert(PlayerPosition(Vector2::new(0.0, 0.0)));
-        res.insert(WorldChunks::new());
+        self.scheduler = ChunkScheduler::new(res.fetch::<Config>().streaming.clone());
+        if !res.has_value::<CatchUpHooks>() {
+            res.insert(default_hooks());
+        }
+        res.insert(PlayerPosition(WorldPos::default()));
+        let pending = PendingEntities::read(&self.save_path).unwrap_or_else(|e| {
+            error!("Unable to read entities waiting for their chunks: {}", e);
+            PendingEntities::default()
+        });
+        res.insert(WorldChunks::new(pending));
+
+        let pool = res.fetch::<ArcThreadPool>().clone();
+        self.worker = Some(ChunkWorker::new(pool, RegionFiles::new(&self.save_path)));
 
         if cfg!(not(feature = "no-save")) && !self.save_path.as_ref().exists() {
             info!("Creating save folder");
@@ -291,5 +793,152 @@ impl<'a, P: AsRef<Path>> System<'a> for ChunkLoadSystem<P> {
                 error!("Could not create save folder: {}", e);
             }
         }
+
+        if self.save_path.as_ref().is_dir() {
+            match crate::save::convert_chunk_saves(&self.save_path) {
+                Ok(0) => {}
+                Ok(n) => info!("Converted {} chunk saves into region files", n),
+                Err(e) => error!("Unable to convert chunk saves into region files: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expand a layout of tile indices into the tile ids `Chunk::generate` picks from.
+    fn layout(rows: [&str; 16]) -> TileLayer {
+        array_init(|x| {
+            let row = rows[x].as_bytes();
+            array_init(|y| GRASS_TILES[(row[y] - b'0') as usize])
+        })
+    }
+
+    #[test]
+    fn generate_is_deterministic() {
+        assert_eq!(
+            Chunk::generate(42, ChunkPos::new(5, -9, 0)),
+            Chunk::generate(42, ChunkPos::new(5, -9, 0))
+        );
+        assert_ne!(
+            Chunk::generate(42, ChunkPos::new(5, -9, 0)),
+            Chunk::generate(43, ChunkPos::new(5, -9, 0))
+        );
+        assert_ne!(
+            Chunk::generate(42, ChunkPos::new(5, -9, 0)),
+            Chunk::generate(42, ChunkPos::new(-9, 5, 0))
+        );
+    }
+
+    #[test]
+    fn generate_levels() {
+        let below = Chunk::generate(42, ChunkPos::new(5, -9, -1));
+        assert_eq!(below.ground, [[ROCK_TILE; 16]; 16]);
+        assert_eq!(below.object, EMPTY_LAYER);
+        assert_eq!(
+            Chunk::generate(42, ChunkPos::new(5, -9, 1)),
+            ChunkLayers::default()
+        );
+    }
+
+    #[test]
+    fn corrupt_chunk_is_quarantined() {
+        let folder = std::env::temp_dir().join("survival_chunk_quarantine");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+
+        Region::open(&folder, ChunkPos::new(0, 0, 0))
+            .unwrap()
+            .write_chunk(ChunkPos::new(2, 3, 0), b"SCHB\x01\x00\x00\x00garbage")
+            .unwrap();
+
+        let regions = RegionFiles::new(&folder);
+        match Chunk::read(&regions, ChunkPos::new(2, 3, 0)) {
+            Err(ChunkError::Corrupt(report)) => {
+                assert_eq!(report.pos, ChunkPos::new(2, 3, 0));
+                assert!(report.quarantined.starts_with(folder.join("quarantine")));
+                assert!(report.quarantined.is_file());
+            }
+            _ => panic!("Corrupt chunk was not reported"),
+        }
+
+        // The damaged data is out of the region so the chunk can be generated again
+        assert!(Chunk::read(&regions, ChunkPos::new(2, 3, 0))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn generate_origin_chunk() {
+        let expected = layout([
+            "2330131323201032",
+            "0322013212003130",
+            "0233312331223103",
+            "3212333232322133",
+            "1132100023333030",
+            "1132232302232301",
+            "0310022230030122",
+            "0020211021032311",
+            "2200313101012300",
+            "3030013333330011",
+            "3121333121132310",
+            "3101320300312000",
+            "3131123030111001",
+            "3301322333320120",
+            "0312221220333131",
+            "2230303020132322",
+        ]);
+        assert_eq!(Chunk::generate(0, ChunkPos::new(0, 0, 0)).ground, expected);
+    }
+
+    #[test]
+    fn generate_neighbor_chunk() {
+        let expected = layout([
+            "2201221233022000",
+            "2321013203123030",
+            "2113310003023301",
+            "0300101301130211",
+            "3322200121122120",
+            "2003132102201333",
+            "2132212020221231",
+            "0033113021310212",
+            "3333300333130332",
+            "0103201101001230",
+            "0122003113233100",
+            "0202312103013002",
+            "3003121222013222",
+            "1123131013032032",
+            "3110132031320322",
+            "1320030223221020",
+        ]);
+        assert_eq!(Chunk::generate(0, ChunkPos::new(1, 0, 0)).ground, expected);
+    }
+
+    #[test]
+    fn generate_negative_chunk() {
+        let expected = layout([
+            "2201111202303122",
+            "0202020210302130",
+            "3330102333000132",
+            "3300300001132100",
+            "2022321121100032",
+            "2002233030122322",
+            "1012320233300111",
+            "0331130110000301",
+            "3000301211100000",
+            "1121211222313001",
+            "0000313022011131",
+            "3021322132313012",
+            "1022321330211102",
+            "3200211333202330",
+            "0320010200032110",
+            "3021100211233210",
+        ]);
+        assert_eq!(
+            Chunk::generate(12345, ChunkPos::new(-3, 7, 0)).ground,
+            expected
+        );
     }
 }
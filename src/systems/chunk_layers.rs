@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tiles::TileId;
+
+/// Tiles of a single layer in `tiles[x][y]` order.
+pub type TileLayer = [[TileId; 16]; 16];
+
+/// A layer which has nothing on it.
+pub const EMPTY_LAYER: TileLayer = [[TileId::EMPTY; 16]; 16];
+
+/// Named tile layers of a chunk. Each one is drawn over the ones before it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Layer {
+    /// Terrain such as grass, sand or rock. Every spot of a level with solid ground has one.
+    Ground,
+    /// Built floors laid over the ground.
+    Floor,
+    /// Walls, bushes and other things standing on the floor.
+    Object,
+    /// Decals drawn over everything else.
+    Overlay,
+}
+
+impl Layer {
+    /// Every layer in the order it is drawn in.
+    pub const ALL: [Layer; 4] = [Layer::Ground, Layer::Floor, Layer::Object, Layer::Overlay];
+}
+
+fn empty_layer() -> TileLayer {
+    EMPTY_LAYER
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChunkLayers {
+    pub ground: TileLayer,
+    #[serde(default = "empty_layer")]
+    pub floor: TileLayer,
+    #[serde(default = "empty_layer")]
+    pub object: TileLayer,
+    #[serde(default = "empty_layer")]
+    pub overlay: TileLayer,
+}
+
+impl ChunkLayers {
+    /// Layers with the given ground and nothing on top of it.
+    pub fn with_ground(ground: TileLayer) -> Self {
+        ChunkLayers {
+            ground,
+            ..ChunkLayers::default()
+        }
+    }
+
+    pub fn get(&self, layer: Layer) -> &TileLayer {
+        match layer {
+            Layer::Ground => &self.ground,
+            Layer::Floor => &self.floor,
+            Layer::Object => &self.object,
+            Layer::Overlay => &self.overlay,
+        }
+    }
+
+    pub fn get_mut(&mut self, layer: Layer) -> &mut TileLayer {
+        match layer {
+            Layer::Ground => &mut self.ground,
+            Layer::Floor => &mut self.floor,
+            Layer::Object => &mut self.object,
+            Layer::Overlay => &mut self.overlay,
+        }
+    }
+
+    /// Every layer in drawing order.
+    pub fn iter(&self) -> impl Iterator<Item = (Layer, &TileLayer)> {
+        Layer::ALL
+            .iter()
+            .map(move |layer| (*layer, self.get(*layer)))
+    }
+}
+
+impl Default for ChunkLayers {
+    fn default() -> Self {
+        ChunkLayers {
+            ground: EMPTY_LAYER,
+            floor: EMPTY_LAYER,
+            object: EMPTY_LAYER,
+            overlay: EMPTY_LAYER,
+        }
+    }
+}
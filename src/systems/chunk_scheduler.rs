@@ -0,0 +1,268 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use amethyst::core::math::Vector2;
+use log::warn;
+
+use crate::coords::{ChunkPos, WorldPos};
+use crate::settings::Streaming;
+use crate::systems::chunk::ChunkState;
+
+/// How many chunks of distance heading straight toward a chunk is worth when ordering loads.
+const DIRECTION_BIAS: f32 = 1.5;
+
+/// Decides which chunks should be resident around the player and in which order they load. Chunks
+/// are wanted within a circle around the player on their level and the levels close to it.
+#[derive(Clone, Debug)]
+pub struct ChunkScheduler {
+    settings: Streaming,
+    center: Option<ChunkPos>,
+    previous: Option<Vector2<f32>>,
+    /// Direction the player last moved in, or zero if they have not moved yet.
+    heading: Vector2<f32>,
+}
+
+impl ChunkScheduler {
+    pub fn new(settings: Streaming) -> Self {
+        if settings.unload_radius < settings.load_radius {
+            warn!("Chunk unload radius is smaller than the load radius");
+        }
+
+        Self {
+            settings,
+            center: None,
+            previous: None,
+            heading: Vector2::new(0.0, 0.0),
+        }
+    }
+
+    /// Track the player position and the level they are on. Returns true if the player entered
+    /// another chunk, so the wanted chunks have to be refreshed.
+    pub fn update(&mut self, player: WorldPos, level: i32) -> bool {
+        let center = player.chunk(level);
+        let player = player.vector();
+        if let Some(previous) = self.previous {
+            let moved = player - previous;
+            if moved.norm() > std::f32::EPSILON {
+                self.heading = moved.normalize();
+            }
+        }
+        self.previous = Some(player);
+
+        if self.center == Some(center) {
+            return false;
+        }
+        self.center = Some(center);
+        true
+    }
+
+    /// Chunk the player is standing in.
+    pub fn center(&self) -> ChunkPos {
+        self.center.unwrap_or_default()
+    }
+
+    pub fn loads_per_frame(&self) -> usize {
+        self.settings.loads_per_frame
+    }
+
+    /// Chunks within the load radius of the player.
+    pub fn wanted(&self) -> HashSet<ChunkPos> {
+        let center = self.center();
+        let radius = self.settings.load_radius;
+        let levels = self.settings.level_radius;
+
+        let mut wanted = HashSet::new();
+        for dz in -levels..=levels {
+            for dx in -radius..=radius {
+                for dy in -radius..=radius {
+                    let pos = ChunkPos::new(center.x + dx, center.y + dy, center.z + dz);
+                    if self.wants(pos) {
+                        wanted.insert(pos);
+                    }
+                }
+            }
+        }
+        wanted
+    }
+
+    /// Whether the chunk is close enough to the player to be loaded.
+    pub fn wants(&self, pos: ChunkPos) -> bool {
+        self.levels(pos) <= self.settings.level_radius
+            && self.distance(pos) <= self.settings.load_radius as f32
+    }
+
+    /// Whether a chunk which is already resident is close enough to the player to stay.
+    pub fn keeps(&self, pos: ChunkPos) -> bool {
+        self.levels(pos) <= self.settings.level_radius
+            && self.distance(pos)
+                <= self.settings.unload_radius.max(self.settings.load_radius) as f32
+    }
+
+    /// Whether a chunk in `state` should stay after the player moved. Only resident chunks get the
+    /// unload radius as hysteresis, chunks which have not loaded yet are dropped once they leave
+    /// the load radius.
+    pub fn retains(&self, pos: ChunkPos, state: ChunkState) -> bool {
+        match state {
+            ChunkState::Ready => self.keeps(pos),
+            ChunkState::Requested | ChunkState::Loading => self.wants(pos),
+            ChunkState::Unloading => true,
+        }
+    }
+
+    /// Load priority of a chunk, lower loads first. Chunks are ordered by distance with the ones
+    /// ahead of the player pulled forward. Every level away from the player counts as one chunk of
+    /// distance.
+    pub fn priority(&self, pos: ChunkPos) -> f32 {
+        let levels = self.levels(pos) as f32;
+        let offset = self.offset(pos);
+        let distance = offset.norm();
+        if distance <= std::f32::EPSILON {
+            return levels;
+        }
+
+        levels + distance - DIRECTION_BIAS * offset.dot(&self.heading) / distance
+    }
+
+    /// Order chunks so the most important one comes first.
+    pub fn sort(&self, chunks: &mut [ChunkPos]) {
+        chunks.sort_by(|a, b| {
+            self.priority(*a)
+                .partial_cmp(&self.priority(*b))
+                .unwrap_or(Ordering::Equal)
+                .then(a.cmp(b))
+        });
+    }
+
+    /// Horizontal offset of a chunk from the player.
+    fn offset(&self, pos: ChunkPos) -> Vector2<f32> {
+        let center = self.center();
+        Vector2::new((pos.x - center.x) as f32, (pos.y - center.y) as f32)
+    }
+
+    fn distance(&self, pos: ChunkPos) -> f32 {
+        self.offset(pos).norm()
+    }
+
+    /// Number of levels between a chunk and the player.
+    fn levels(&self, pos: ChunkPos) -> i32 {
+        (pos.z - self.center().z).abs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coords::{CHUNK_SIZE, TILE_SIZE};
+
+    fn scheduler() -> ChunkScheduler {
+        ChunkScheduler::new(Streaming {
+            load_radius: 2,
+            unload_radius: 3,
+            level_radius: 1,
+            loads_per_frame: 1,
+        })
+    }
+
+    /// Position of the middle of a chunk.
+    fn chunk_middle(x: i32, y: i32) -> WorldPos {
+        let size = CHUNK_SIZE as f32 * TILE_SIZE;
+        WorldPos::new((x as f32 + 0.5) * size, (y as f32 + 0.5) * size)
+    }
+
+    #[test]
+    fn wanted_is_symmetric() {
+        let mut scheduler = scheduler();
+        assert!(scheduler.update(chunk_middle(0, 0), 0));
+        assert!(!scheduler.update(chunk_middle(0, 0), 0));
+
+        let wanted = scheduler.wanted();
+        for pos in &wanted {
+            assert!(wanted.contains(&ChunkPos::new(-pos.x, -pos.y, -pos.z)));
+        }
+        assert!(
+            wanted.contains(&ChunkPos::new(2, 0, 0)) && wanted.contains(&ChunkPos::new(-2, 0, 0))
+        );
+        assert!(!wanted.contains(&ChunkPos::new(2, 2, 0)));
+    }
+
+    #[test]
+    fn unloading_has_hysteresis() {
+        let mut scheduler = scheduler();
+        scheduler.update(chunk_middle(0, 0), 0);
+        assert!(scheduler.wants(ChunkPos::new(2, 0, 0)));
+
+        // Stepping one chunk back keeps the chunk without wanting it anew
+        scheduler.update(chunk_middle(-1, 0), 0);
+        assert!(!scheduler.wants(ChunkPos::new(2, 0, 0)));
+        assert!(scheduler.keeps(ChunkPos::new(2, 0, 0)));
+
+        scheduler.update(chunk_middle(-2, 0), 0);
+        assert!(!scheduler.keeps(ChunkPos::new(2, 0, 0)));
+    }
+
+    #[test]
+    fn only_resident_chunks_have_hysteresis() {
+        let mut scheduler = scheduler();
+        scheduler.update(chunk_middle(0, 0), 0);
+        scheduler.update(chunk_middle(-1, 0), 0);
+
+        let pos = ChunkPos::new(2, 0, 0);
+        assert!(scheduler.retains(pos, ChunkState::Ready));
+        assert!(!scheduler.retains(pos, ChunkState::Requested));
+        assert!(!scheduler.retains(pos, ChunkState::Loading));
+        assert!(scheduler.retains(ChunkPos::new(1, 0, 0), ChunkState::Requested));
+    }
+
+    #[test]
+    fn loads_ahead_of_the_player_first() {
+        let mut scheduler = scheduler();
+        scheduler.update(chunk_middle(0, 0), 0);
+        scheduler.update(chunk_middle(1, 0), 0);
+
+        let mut chunks = vec![
+            ChunkPos::new(-1, 0, 0),
+            ChunkPos::new(3, 0, 0),
+            ChunkPos::new(1, 0, 0),
+            ChunkPos::new(1, 2, 0),
+            ChunkPos::new(1, -2, 0),
+        ];
+        scheduler.sort(&mut chunks);
+        assert_eq!(chunks[0], ChunkPos::new(1, 0, 0));
+        assert_eq!(chunks[1], ChunkPos::new(3, 0, 0));
+        assert_eq!(chunks[chunks.len() - 1], ChunkPos::new(-1, 0, 0));
+    }
+
+    #[test]
+    fn streams_nearby_levels() {
+        let mut scheduler = scheduler();
+        scheduler.update(chunk_middle(0, 0), -3);
+
+        let wanted = scheduler.wanted();
+        assert!(
+            wanted.contains(&ChunkPos::new(0, 0, -2)) && wanted.contains(&ChunkPos::new(0, 0, -4))
+        );
+        assert!(
+            !wanted.contains(&ChunkPos::new(0, 0, -5)) && !wanted.contains(&ChunkPos::new(0, 0, 0))
+        );
+
+        // Changing levels refreshes the wanted chunks even without moving
+        assert!(scheduler.update(chunk_middle(0, 0), -4));
+        assert!(!scheduler.keeps(ChunkPos::new(0, 0, -2)));
+
+        // The player's own level loads before the ones above and below
+        let mut chunks = vec![
+            ChunkPos::new(0, 0, -3),
+            ChunkPos::new(2, 0, -4),
+            ChunkPos::new(0, 0, -4),
+        ];
+        scheduler.sort(&mut chunks);
+        assert_eq!(
+            chunks,
+            vec![
+                ChunkPos::new(0, 0, -4),
+                ChunkPos::new(0, 0, -3),
+                ChunkPos::new(2, 0, -4)
+            ]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::mpsc::{channel, Receiver, Sender, TryIter};
+use std::sync::Arc;
+
+use amethyst::core::ArcThreadPool;
+use log::error;
+
+use crate::coords::ChunkPos;
+use crate::render::tile_pass::{compile_chunk, ChunkRender};
+use crate::save::{ChunkError, RegionFiles};
+use crate::settings::TerrainStrategy;
+use crate::systems::chunk::Chunk;
+use crate::systems::world_gen::WorldGenerator;
+use crate::tiles::TileAsset;
+
+/// Finished work handed back from the chunk workers.
+pub enum ChunkEvent {
+    /// The chunk was read or generated and its render compiled.
+    Loaded(Chunk, ChunkRender),
+    /// The chunk at this position has been written out and dropped.
+    Saved(ChunkPos),
+    /// The chunk could not be read. Corrupt chunks are still followed by a freshly generated
+    /// `Loaded` chunk, chunks which could not be accessed are not.
+    Failed(ChunkPos, ChunkError),
+}
+
+/// Runs chunk reading, generation and saving on the thread pool so the game thread never blocks on
+/// disk access. Results are collected with `ChunkWorker::poll`.
+pub struct ChunkWorker {
+    pool: ArcThreadPool,
+    // Serializes the disk access of the workers and of chunks saved when they are dropped.
+    regions: RegionFiles,
+    sender: Sender<ChunkEvent>,
+    receiver: Receiver<ChunkEvent>,
+}
+
+impl ChunkWorker {
+    pub fn new(pool: ArcThreadPool, regions: RegionFiles) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            pool,
+            regions,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Read the chunk at `pos` from disk, or generate it if it has never been saved.
+    pub fn load(&self, pos: ChunkPos, generator: Arc<WorldGenerator>, assets: Arc<Vec<TileAsset>>) {
+        let sender = self.sender.clone();
+        let regions = self.regions.clone();
+
+        self.pool.spawn(move || {
+            let chunk = match Chunk::read(&regions, pos) {
+                Ok(Some(mut chunk)) => {
+                    chunk.fill_liquids(&generator);
+                    chunk
+                }
+                Ok(None) => Chunk::generated(&regions, pos, &generator),
+                Err(ChunkError::Corrupt(report)) => {
+                    let _ = sender.send(ChunkEvent::Failed(pos, ChunkError::Corrupt(report)));
+                    Chunk::generated(&regions, pos, &generator)
+                }
+                Err(e) => {
+                    let _ = sender.send(ChunkEvent::Failed(pos, e));
+                    return;
+                }
+            };
+            let render = compile_chunk(&chunk, &assets);
+
+            if sender.send(ChunkEvent::Loaded(chunk, render)).is_err() {
+                error!(
+                    "Chunk {:?} finished loading after the world was closed",
+                    pos
+                );
+            }
+        });
+    }
+
+    /// Build the generator of a world. Generating an island takes far too long for the game
+    /// thread, so the generator arrives on the returned channel once it is done.
+    pub fn build_generator(
+        &self,
+        seed: u64,
+        terrain: &TerrainStrategy,
+    ) -> Receiver<WorldGenerator> {
+        let (sender, receiver) = channel();
+        let terrain = terrain.clone();
+
+        self.pool.spawn(move || {
+            // Nobody is waiting on the generator when the world was closed meanwhile
+            let _ = sender.send(WorldGenerator::for_world(seed, &terrain));
+        });
+        receiver
+    }
+
+    /// Save the chunk if required and drop it.
+    pub fn unload(&self, mut chunk: Chunk) {
+        let sender = self.sender.clone();
+
+        self.pool.spawn(move || {
+            let pos = chunk.pos;
+            chunk.save_if_required();
+            drop(chunk);
+
+            // Nobody is waiting on the save when the world is closing
+            let _ = sender.send(ChunkEvent::Saved(pos));
+        });
+    }
+
+    /// Collect all work finished since the last poll without blocking.
+    pub fn poll(&self) -> TryIter<ChunkEvent> {
+        self.receiver.try_iter()
+    }
+}
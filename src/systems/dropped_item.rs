@@ -2,12 +2,16 @@ use amethyst::{
     assets::AssetStorage,
     core::components::Parent,
     core::transform::Transform,
-    ecs::{Entities, Join, Read, ReadExpect, ReadStorage, Resources, SystemData, WriteStorage},
+    ecs::{
+        saveload::MarkerAllocator, Entities, Join, Read, ReadExpect, ReadStorage, Resources,
+        SystemData, Write, WriteStorage,
+    },
 };
 use log::error;
 
 use crate::actions::Action;
 use crate::components;
+use crate::save::entities::{SaveMarker, SaveMarkerAllocator};
 use crate::settings::Context;
 use crate::utils::ComponentEventReader;
 
@@ -27,6 +31,8 @@ impl<'s> amethyst::ecs::System<'s> for System {
         WriteStorage<'s, components::Actionable>,
         WriteStorage<'s, Parent>,
         Read<'s, AssetStorage<crate::assets::Item>>,
+        WriteStorage<'s, SaveMarker>,
+        Write<'s, SaveMarkerAllocator>,
     );
 
     fn setup(&mut self, res: &mut Resources) {
@@ -46,6 +52,8 @@ impl<'s> amethyst::ecs::System<'s> for System {
             mut actionables,
             mut parents,
             item_storage,
+            mut markers,
+            mut allocator,
         ): Self::SystemData,
     ) {
         self.action_reader.maintain(&entities, &mut actionables);
@@ -95,6 +103,9 @@ impl<'s> amethyst::ecs::System<'s> for System {
 
                                     // Remove the parent relationship
                                     parents.remove(*dropped_item);
+
+                                    // Items on the ground are saved with the chunk they lie in
+                                    allocator.mark(*dropped_item, &mut markers);
                                 }
                             }
                             None => {
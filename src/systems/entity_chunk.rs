@@ -1,21 +1,20 @@
 use amethyst::ecs::{System, WriteExpect};
 use log::debug;
 
+use crate::coords::{ChunkPos, TilePos};
 use crate::entity::WorldEntity;
 use crate::systems::chunk::WorldChunks;
-use crate::utils::TILE_SIZE;
 
-// TODO offload out of bounds entities
+/// Moves world entities into the chunk they are standing in. Entities which walk into a chunk that
+/// is not loaded wait in the pending store of `WorldChunks` until it loads.
 pub struct EntityChunkSystem {
     transfer: Vec<WorldEntity>,
-    offload: Vec<WorldEntity>,
 }
 
 impl EntityChunkSystem {
     pub fn new() -> Self {
         Self {
             transfer: Vec::with_capacity(16),
-            offload: Vec::with_capacity(16),
         }
     }
 }
@@ -25,26 +24,149 @@ impl<'a> System<'a> for EntityChunkSystem {
 
     fn run(&mut self, mut data: WriteExpect<'a, WorldChunks>) {
         // Remove all entities that are in the wrong chunk
-        for ((chunk_x, chunk_y), ref mut chunk) in data.inner.iter_mut() {
-            self.transfer.extend(chunk.entities.drain_filter(|e| {
-                (e.pos.x / TILE_SIZE as f64).floor() as i32 != *chunk_x
-                    || (e.pos.y / TILE_SIZE as f64).floor() as i32 != *chunk_y
-            }));
+        for (pos, ref mut chunk) in data.inner.iter_mut() {
+            let before = self.transfer.len();
+            self.transfer
+                .extend(chunk.entities.drain_filter(|e| chunk_of(e) != *pos));
+
+            if self.transfer.len() != before {
+                chunk.mark_changed();
+            }
         }
 
-        // Re-add them to either the correct chunk or offload queue
+        // Re-add them to either the correct chunk or the pending store
         for entity in self.transfer.drain(..) {
-            let chunk_pos = (
-                (entity.pos.x / TILE_SIZE as f64).floor() as i32,
-                (entity.pos.y / TILE_SIZE as f64).floor() as i32,
-            );
-            match data.inner.get_mut(&chunk_pos) {
-                Some(v) => v.entities.push(entity),
-                None => {
-                    self.offload.push(entity);
-                    debug!("Entity added to offload queue: {}", self.offload.len());
-                }
+            let chunk_pos = chunk_of(&entity);
+            if !data.inner.contains_key(&chunk_pos) {
+                debug!("Entity waiting for chunk {:?} to load", chunk_pos);
             }
+            data.offload(chunk_pos, entity);
+        }
+    }
+}
+
+/// Chunk a world entity is standing in.
+fn chunk_of(entity: &WorldEntity) -> ChunkPos {
+    TilePos::containing(entity.pos, entity.level).chunk()
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::core::math::Vector2;
+    use amethyst::ecs::{RunNow, World};
+
+    use super::*;
+    use crate::coords::CHUNK_SIZE;
+    use crate::save::pending::PendingEntities;
+    use crate::systems::chunk::TestChunk;
+
+    fn load(world: &World, pos: ChunkPos) {
+        let mut chunk = TestChunk::new(pos);
+        let mut chunks = world.write_resource::<WorldChunks>();
+        chunks.merge_pending(&mut chunk);
+        chunks.inner.insert(pos, chunk.into_inner());
+    }
+
+    fn unload(world: &World, pos: ChunkPos) {
+        let chunk = world
+            .write_resource::<WorldChunks>()
+            .inner
+            .remove(&pos)
+            .unwrap();
+        drop(TestChunk::from(chunk));
+    }
+
+    #[test]
+    fn entity_walks_into_unloaded_chunk() {
+        let mut world = World::new();
+        world.add_resource(WorldChunks::new(PendingEntities::default()));
+        load(&world, ChunkPos::new(0, 0, 0));
+
+        // The entity has walked from the loaded chunk into its unloaded neighbor
+        let walker = WorldEntity::new(Vector2::new(CHUNK_SIZE as f64 * 1.5, 2.0));
+        world
+            .write_resource::<WorldChunks>()
+            .inner
+            .get_mut(&ChunkPos::new(0, 0, 0))
+            .unwrap()
+            .entities
+            .push(walker);
+
+        let mut system = EntityChunkSystem::new();
+        system.run_now(&world.res);
+
+        {
+            let chunks = world.read_resource::<WorldChunks>();
+            assert!(chunks.inner[&ChunkPos::new(0, 0, 0)].entities.is_empty());
+            assert_eq!(chunks.pending().waiting(ChunkPos::new(1, 0, 0)), 1);
         }
+
+        // The entity is handed over once its chunk streams in, and stays there
+        load(&world, ChunkPos::new(1, 0, 0));
+        system.run_now(&world.res);
+        {
+            let chunks = world.read_resource::<WorldChunks>();
+            assert!(chunks.pending().is_empty());
+            assert_eq!(chunks.inner[&ChunkPos::new(1, 0, 0)].entities.len(), 1);
+        }
+
+        unload(&world, ChunkPos::new(0, 0, 0));
+        unload(&world, ChunkPos::new(1, 0, 0));
+    }
+
+    #[test]
+    fn entity_crosses_between_loaded_chunks() {
+        let mut world = World::new();
+        world.add_resource(WorldChunks::new(PendingEntities::default()));
+        load(&world, ChunkPos::new(0, 0, 0));
+        load(&world, ChunkPos::new(-1, 0, 0));
+
+        let walker = WorldEntity::new(Vector2::new(-1.0, 2.0));
+        world
+            .write_resource::<WorldChunks>()
+            .inner
+            .get_mut(&ChunkPos::new(0, 0, 0))
+            .unwrap()
+            .entities
+            .push(walker);
+
+        EntityChunkSystem::new().run_now(&world.res);
+
+        {
+            let chunks = world.read_resource::<WorldChunks>();
+            assert!(chunks.pending().is_empty());
+            assert_eq!(chunks.inner[&ChunkPos::new(-1, 0, 0)].entities.len(), 1);
+        }
+
+        unload(&world, ChunkPos::new(0, 0, 0));
+        unload(&world, ChunkPos::new(-1, 0, 0));
+    }
+
+    #[test]
+    fn entity_stays_on_its_level() {
+        let mut world = World::new();
+        world.add_resource(WorldChunks::new(PendingEntities::default()));
+        load(&world, ChunkPos::new(0, 0, 0));
+        load(&world, ChunkPos::new(0, 0, -1));
+
+        let digger = WorldEntity::new(Vector2::new(2.0, 2.0)).on_level(-1);
+        world
+            .write_resource::<WorldChunks>()
+            .inner
+            .get_mut(&ChunkPos::new(0, 0, 0))
+            .unwrap()
+            .entities
+            .push(digger);
+
+        EntityChunkSystem::new().run_now(&world.res);
+
+        {
+            let chunks = world.read_resource::<WorldChunks>();
+            assert!(chunks.inner[&ChunkPos::new(0, 0, 0)].entities.is_empty());
+            assert_eq!(chunks.inner[&ChunkPos::new(0, 0, -1)].entities.len(), 1);
+        }
+
+        unload(&world, ChunkPos::new(0, 0, 0));
+        unload(&world, ChunkPos::new(0, 0, -1));
     }
 }
@@ -8,6 +8,7 @@ use log::error;
 use crate::actions;
 use crate::actions::Action;
 use crate::components;
+use crate::coords::WorldPos;
 use crate::tiles::{ReadTiles, TileEntities, Tiles};
 use crate::utils::{ComponentEventReader, HasChannel};
 
@@ -19,7 +20,6 @@ pub struct System {
 impl<'s> amethyst::ecs::System<'s> for System {
     #[allow(clippy::type_complexity)]
     type SystemData = (
-        ReadExpect<'s, crate::settings::Config>,
         ReadExpect<'s, Tiles>,
         Entities<'s>,
         ReadStorage<'s, components::Item>,
@@ -27,6 +27,7 @@ impl<'s> amethyst::ecs::System<'s> for System {
         WriteStorage<'s, components::Actionable>,
         ReadStorage<'s, Parent>,
         ReadTiles<'s, TileEntities>,
+        ReadStorage<'s, components::ZLevel>,
     );
 
     fn setup(&mut self, res: &mut Resources) {
@@ -38,7 +39,6 @@ impl<'s> amethyst::ecs::System<'s> for System {
     fn run(
         &mut self,
         (
-            config,
             tiles,
             entities,
             items,
@@ -46,6 +46,7 @@ impl<'s> amethyst::ecs::System<'s> for System {
             mut actionables,
             _,
             tile_entities_map,
+            levels,
         ): Self::SystemData,
     ) {
         self.action_reader.maintain(&entities, &mut actionables);
@@ -56,13 +57,14 @@ impl<'s> amethyst::ecs::System<'s> for System {
                     match target {
                         actions::PickupTarget::Under => {
                             // Target there any other tile entities underneath us?
-                            for entity in &tile_entities_map
-                                .get(tiles.id_from_vector(
-                                    tiles.world_to_tile(transform.translation(), &config),
-                                ))
-                                .unwrap()
-                                .0
-                            {
+                            let level = levels.get(entity).cloned().unwrap_or_default().0;
+                            let tile =
+                                WorldPos::from_translation(transform.translation()).tile(level);
+                            let id = match tiles.id_of(tile) {
+                                Some(id) => id,
+                                None => continue,
+                            };
+                            for entity in &tile_entities_map.get(id).unwrap().0 {
                                 if items.get(*entity).is_some() {
                                     // Its an item! We can get it.
                                     // TODO: allllll sorts of checks
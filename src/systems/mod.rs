@@ -6,12 +6,18 @@ pub use nutrition::System as NutritionSystem;
 pub use time::System as TimeSystem;
 pub use wearing::System as WearingSystem;
 
+pub mod catch_up;
 pub mod chunk;
+pub mod chunk_layers;
+pub mod chunk_scheduler;
+pub mod chunk_worker;
 pub mod dropped_item;
 pub mod entity_chunk;
 pub mod handle_pickup;
 pub mod input;
 pub mod movement;
 pub mod nutrition;
+pub mod tile_edit;
 pub mod time;
 pub mod wearing;
+pub mod world_gen;
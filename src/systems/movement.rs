@@ -1,4 +1,3 @@
-use amethyst::core::math::Vector2;
 use amethyst::{
     core::components::Transform,
     ecs::{
@@ -11,9 +10,10 @@ use log::{error, warn};
 use crate::actions::{Action, Direction};
 use crate::components;
 use crate::components::PlayerPosition;
+use crate::coords::WorldPos;
 use crate::settings::Config;
 use crate::tiles::{ReadTiles, Tiles};
-use crate::utils::{ComponentEventReader, TILE_SIZE};
+use crate::utils::ComponentEventReader;
 
 #[derive(Default)]
 pub struct System {
@@ -86,7 +86,7 @@ impl<'s> amethyst::ecs::System<'s> for System {
                         *transform = target;
 
                         if players.get(entity).is_some() {
-                            player_position.0 = transform.translation().xy() / TILE_SIZE;
+                            player_position.0 = WorldPos::from_translation(transform.translation());
                         }
                     }
                 }
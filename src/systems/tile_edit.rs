@@ -0,0 +1,179 @@
+use amethyst::ecs::{ReadExpect, System, Write, WriteExpect};
+use hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::coords::{ChunkPos, TilePos, WorldPos};
+use crate::render::tile_pass::{compile_chunk, WriteChunkRender};
+use crate::systems::chunk::{ChunkState, WorldChunks};
+use crate::systems::chunk_layers::Layer;
+use crate::tiles::{TileAssets, TileId};
+
+/// A single tile change waiting to be applied.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TileEdit {
+    pub pos: TilePos,
+    pub layer: Layer,
+    pub tile: TileId,
+}
+
+/// Tile changes requested this frame. They are applied by `TileEditSystem`, which only
+/// recompiles the renders of the chunks that actually changed.
+#[derive(Debug, Default)]
+pub struct TileEdits {
+    queued: Vec<TileEdit>,
+}
+
+impl TileEdits {
+    pub fn set(&mut self, pos: TilePos, layer: Layer, tile: TileId) {
+        self.queued.push(TileEdit { pos, layer, tile });
+    }
+
+    /// Replace the tile drawn at a world position.
+    pub fn set_at_world(&mut self, pos: WorldPos, level: i32, layer: Layer, tile: TileId) {
+        self.set(pos.tile(level), layer, tile);
+    }
+
+    /// Remove whatever is on a layer of a tile.
+    pub fn clear(&mut self, pos: TilePos, layer: Layer) {
+        self.set(pos, layer, TileId::EMPTY);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Apply the queued edits to the resident chunks, in the order they were made. Edits of
+    /// chunks which are still loading stay queued until they arrive, edits of chunks which are
+    /// not wanted wait with the pending entities until the chunk loads. Returns the chunks whose
+    /// tiles changed.
+    pub fn apply(&mut self, chunks: &mut WorldChunks) -> HashSet<ChunkPos> {
+        let mut batches: HashMap<ChunkPos, Vec<TileEdit>> = HashMap::new();
+        for edit in self.queued.drain(..) {
+            batches
+                .entry(edit.pos.chunk())
+                .or_insert_with(Vec::new)
+                .push(edit);
+        }
+
+        let mut changed = HashSet::new();
+        for (pos, batch) in batches {
+            let chunk = match chunks.inner.get_mut(&pos) {
+                Some(v) => v,
+                None => {
+                    match chunks.state(pos) {
+                        Some(ChunkState::Requested) | Some(ChunkState::Loading) => {
+                            self.queued.extend(batch)
+                        }
+                        _ => chunks.hold_edits(batch),
+                    }
+                    continue;
+                }
+            };
+
+            for edit in batch {
+                let (x, y) = edit.pos.local();
+                if chunk.set_tile(edit.layer, x, y, edit.tile) {
+                    changed.insert(pos);
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Applies queued tile edits and rebuilds the renders of the edited chunks.
+pub struct TileEditSystem;
+
+impl<'a> System<'a> for TileEditSystem {
+    type SystemData = (
+        Write<'a, TileEdits>,
+        WriteExpect<'a, WorldChunks>,
+        ReadExpect<'a, TileAssets>,
+        WriteChunkRender<'a>,
+    );
+
+    fn run(&mut self, (mut edits, mut chunks, tile_assets, mut renders): Self::SystemData) {
+        if edits.is_empty() {
+            return;
+        }
+
+        for pos in edits.apply(&mut chunks) {
+            renders.insert(pos, compile_chunk(&chunks.inner[&pos], &tile_assets.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coords::TILE_SIZE;
+    use crate::save::pending::PendingEntities;
+    use crate::systems::chunk::TestChunk;
+
+    #[test]
+    fn world_positions_edit_the_tile_drawn_there() {
+        let mut edits = TileEdits::default();
+        edits.set_at_world(
+            WorldPos::new(3.5 * TILE_SIZE, -0.5 * TILE_SIZE),
+            -1,
+            Layer::Floor,
+            TileId(35),
+        );
+        let edit = edits.queued[0];
+        assert_eq!(edit.pos, TilePos::new(3, -1, -1));
+        assert_eq!(edit.pos.chunk(), ChunkPos::new(0, -1, -1));
+        assert_eq!(edit.pos.local(), (3, 15));
+    }
+
+    #[test]
+    fn edits_only_change_resident_chunks() {
+        let mut chunks = WorldChunks::new(PendingEntities::default());
+        for &pos in &[ChunkPos::new(0, 0, 0), ChunkPos::new(-1, 0, 0)] {
+            chunks.inner.insert(pos, TestChunk::new(pos).into_inner());
+        }
+
+        let mut edits = TileEdits::default();
+        edits.set(TilePos::new(2, 3, 0), Layer::Object, TileId(35));
+        edits.set(TilePos::new(2, 3, 0), Layer::Object, TileId(36));
+        edits.clear(TilePos::new(-1, 0, 0), Layer::Object);
+        edits.set(TilePos::new(40, 0, 0), Layer::Ground, TileId(35));
+
+        // Clearing an empty tile changes nothing, the unloaded chunk is not wanted
+        let changed = edits.apply(&mut chunks);
+        assert_eq!(changed.len(), 1);
+        assert!(changed.contains(&ChunkPos::new(0, 0, 0)));
+        assert!(edits.is_empty());
+        assert_eq!(
+            chunks.inner[&ChunkPos::new(0, 0, 0)].tile(Layer::Object, 2, 3),
+            TileId(36)
+        );
+        assert_eq!(chunks.pending().edits_waiting(ChunkPos::new(2, 0, 0)), 1);
+
+        for (_, chunk) in chunks.inner.drain() {
+            drop(TestChunk::from(chunk));
+        }
+    }
+
+    #[test]
+    fn edits_of_unloaded_chunks_apply_once_they_load() {
+        let mut chunks = WorldChunks::new(PendingEntities::default());
+        let mut edits = TileEdits::default();
+        edits.set(TilePos::new(-20, 5, 0), Layer::Object, TileId(35));
+        edits.set(TilePos::new(-20, 5, 0), Layer::Object, TileId(36));
+        edits.set(TilePos::new(-18, 9, 0), Layer::Floor, TileId(12));
+
+        assert!(edits.apply(&mut chunks).is_empty());
+        assert!(edits.is_empty());
+        assert_eq!(chunks.pending().edits_waiting(ChunkPos::new(-2, 0, 0)), 3);
+
+        let mut chunk = TestChunk::new(ChunkPos::new(-2, 0, 0));
+        assert!(chunks.merge_pending(&mut chunk));
+        assert!(chunks.pending().is_empty());
+        assert_eq!(chunk.tile(Layer::Object, 12, 5), TileId(36));
+        assert_eq!(chunk.tile(Layer::Floor, 14, 9), TileId(12));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::components::ObstructionType;
+use crate::coords::ChunkPos;
+use crate::settings::TerrainStrategy;
+use crate::systems::chunk::Chunk;
+use crate::systems::chunk_layers::ChunkLayers;
+
+#[cfg(feature = "mapgen")]
+use crate::coords::{TilePos, SURFACE_LEVEL};
+#[cfg(feature = "mapgen")]
+use crate::mapgen::{IslandMap, ScatterRules, Scattered, Terrain, SCATTER_FILE};
+#[cfg(feature = "mapgen")]
+use crate::systems::{chunk::GRASS_TILES, chunk_layers::TileLayer};
+#[cfg(feature = "mapgen")]
+use crate::tiles::TileId;
+#[cfg(feature = "mapgen")]
+use array_init::array_init;
+#[cfg(feature = "mapgen")]
+use log::{error, info};
+#[cfg(feature = "mapgen")]
+use rand::Rng;
+
+#[cfg(feature = "mapgen")]
+const WATER_TILE: TileId = TileId(27);
+#[cfg(feature = "mapgen")]
+const SAND_TILE: TileId = TileId(38);
+#[cfg(feature = "mapgen")]
+const FOREST_TILE: TileId = TileId(412);
+#[cfg(feature = "mapgen")]
+const STONE_TILE: TileId = TileId(224);
+
+/// Mixed into the seed of the scatter rng so it does not follow the stream of the ground tiles.
+#[cfg(feature = "mapgen")]
+const SCATTER_SALT: u64 = 0x5CA7_7E85;
+
+/// Water covering the tiles of a chunk in `tiles[x][y]` order, as the obstruction it forms.
+pub type LiquidLayer = [[Option<ObstructionType>; 16]; 16];
+
+/// Generates the chunks of a world which have never been saved. With the `mapgen` feature the
+/// surface is cut out of the island of the world seed, which is generated once and kept for the
+/// lifetime of the world. Without it the surface is a meadow of random grass.
+pub struct WorldGenerator {
+    seed: u64,
+    terrain: TerrainStrategy,
+    #[cfg(feature = "mapgen")]
+    island: Option<IslandMap>,
+    #[cfg(feature = "mapgen")]
+    scatter: ScatterRules,
+}
+
+impl WorldGenerator {
+    /// A world of grass, the same as without the `mapgen` feature.
+    pub fn meadow(seed: u64) -> Self {
+        Self {
+            seed,
+            terrain: TerrainStrategy::default(),
+            #[cfg(feature = "mapgen")]
+            island: None,
+            #[cfg(feature = "mapgen")]
+            scatter: ScatterRules::default(),
+        }
+    }
+
+    #[cfg(feature = "mapgen")]
+    pub fn island(seed: u64, terrain: &TerrainStrategy) -> Self {
+        info!("Generating the island of seed {} with {:?}", seed, terrain);
+        let scatter = ScatterRules::load(&SCATTER_FILE).unwrap_or_else(|e| {
+            error!(
+                "Unable to load scatter rules, nothing will be scattered: {}",
+                e
+            );
+            ScatterRules::default()
+        });
+        Self::with_island(seed, IslandMap::generate(seed, terrain)).with_scatter(scatter)
+    }
+
+    #[cfg(feature = "mapgen")]
+    pub fn with_island(seed: u64, island: IslandMap) -> Self {
+        Self {
+            seed,
+            terrain: island.strategy().clone(),
+            island: Some(island),
+            scatter: ScatterRules::default(),
+        }
+    }
+
+    /// Use other rules to scatter objects over the island.
+    #[cfg(feature = "mapgen")]
+    pub fn with_scatter(mut self, scatter: ScatterRules) -> Self {
+        self.scatter = scatter;
+        self
+    }
+
+    /// The best generator available for a world.
+    pub fn for_world(seed: u64, terrain: &TerrainStrategy) -> Self {
+        #[cfg(feature = "mapgen")]
+        {
+            Self::island(seed, terrain)
+        }
+        #[cfg(not(feature = "mapgen"))]
+        {
+            Self {
+                terrain: terrain.clone(),
+                ..Self::meadow(seed)
+            }
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn terrain(&self) -> &TerrainStrategy {
+        &self.terrain
+    }
+
+    /// Generate the layers of a new chunk. Only the surface comes from the island, the levels
+    /// above and below it are the same for every generator.
+    pub fn generate(&self, pos: ChunkPos) -> ChunkLayers {
+        #[cfg(feature = "mapgen")]
+        {
+            if let Some(island) = &self.island {
+                if pos.z == SURFACE_LEVEL {
+                    return self.generate_island(island, pos);
+                }
+            }
+        }
+        Chunk::generate(self.seed, pos)
+    }
+
+    /// Trees, bushes and rocks growing on a new chunk. Only the surface of an island has any.
+    #[cfg(feature = "mapgen")]
+    pub fn scatter(&self, pos: ChunkPos) -> Vec<Scattered> {
+        if let Some(island) = &self.island {
+            if pos.z == SURFACE_LEVEL {
+                let mut rng = Chunk::rng(self.seed ^ SCATTER_SALT, pos);
+                return self.scatter.scatter(&mut rng, island, pos);
+            }
+        }
+        Vec::new()
+    }
+
+    /// Depth and current of the water tiles of a chunk. Only the surface of an island has water,
+    /// which is looked up again whenever a chunk loads instead of being saved with it.
+    #[cfg_attr(not(feature = "mapgen"), allow(unused_variables))]
+    pub fn liquids(&self, pos: ChunkPos, layers: &ChunkLayers) -> LiquidLayer {
+        #[cfg(feature = "mapgen")]
+        {
+            if let Some(island) = &self.island {
+                if pos.z == SURFACE_LEVEL {
+                    return array_init(|x| {
+                        array_init(|y| {
+                            if layers.ground[x][y] == WATER_TILE {
+                                island.liquid_at(TilePos::from_local(pos, x, y))
+                            } else {
+                                None
+                            }
+                        })
+                    });
+                }
+            }
+        }
+        [[None; 16]; 16]
+    }
+
+    #[cfg(feature = "mapgen")]
+    fn generate_island(&self, island: &IslandMap, pos: ChunkPos) -> ChunkLayers {
+        let terrain = island.chunk_terrain(pos);
+        let mut rng = Chunk::rng(self.seed, pos);
+        let ground: TileLayer = array_init(|x| {
+            array_init(|y| match terrain[x][y] {
+                Terrain::Water => WATER_TILE,
+                Terrain::Sand => SAND_TILE,
+                Terrain::Grass => GRASS_TILES[rng.gen_range(0, 4) as usize],
+                Terrain::Forest => FOREST_TILE,
+                Terrain::Rock => STONE_TILE,
+            })
+        });
+        ChunkLayers::with_ground(ground)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coords::SURFACE_LEVEL;
+
+    #[test]
+    fn meadow_is_plain_generation() {
+        let generator = WorldGenerator::meadow(42);
+        for &pos in &[
+            ChunkPos::new(0, 0, SURFACE_LEVEL),
+            ChunkPos::new(-3, 7, SURFACE_LEVEL),
+            ChunkPos::new(1, 1, SURFACE_LEVEL - 1),
+        ] {
+            let layers = generator.generate(pos);
+            assert_eq!(layers, Chunk::generate(42, pos));
+            #[cfg(feature = "mapgen")]
+            assert!(generator.scatter(pos).is_empty());
+            assert!(generator
+                .liquids(pos, &layers)
+                .iter()
+                .flat_map(|column| column.iter())
+                .all(Option::is_none));
+        }
+    }
+
+    #[cfg(feature = "mapgen")]
+    #[test]
+    fn island_surface() {
+        use crate::coords::TilePos;
+        use crate::mapgen::GeneratorConfig;
+
+        let config = GeneratorConfig {
+            num_points: 300,
+            ..Default::default()
+        };
+        let island = IslandMap::with_config(11, &TerrainStrategy::default(), config);
+        let generator = WorldGenerator::with_island(11, island);
+        let island = generator.island.as_ref().unwrap();
+
+        let pos = ChunkPos::new(0, 0, SURFACE_LEVEL);
+        let layers = generator.generate(pos);
+        let liquids = generator.liquids(pos, &layers);
+        for x in 0..16 {
+            for y in 0..16 {
+                let tile = layers.ground[x][y];
+                match liquids[x][y] {
+                    Some(ObstructionType::Liquid { depth, .. }) => {
+                        assert_eq!(tile, WATER_TILE);
+                        assert!(depth >= 0.);
+                    }
+                    Some(other) => panic!("Expected a liquid, got {:?}", other),
+                    None => assert_ne!(tile, WATER_TILE),
+                }
+                match island.terrain(TilePos::from_local(pos, x, y)) {
+                    Terrain::Grass => assert!(GRASS_TILES.contains(&tile)),
+                    Terrain::Water => assert_eq!(tile, WATER_TILE),
+                    Terrain::Sand => assert_eq!(tile, SAND_TILE),
+                    Terrain::Forest => assert_eq!(tile, FOREST_TILE),
+                    Terrain::Rock => assert_eq!(tile, STONE_TILE),
+                }
+            }
+        }
+
+        // Far out at sea and below the surface
+        let sea = ChunkPos::new(500, 500, SURFACE_LEVEL);
+        let layers = generator.generate(sea);
+        assert_eq!(layers.ground, [[WATER_TILE; 16]; 16]);
+        match generator.liquids(sea, &layers)[3][7] {
+            Some(ObstructionType::Liquid { depth, current }) => {
+                assert!(depth > 0. && current == 0.)
+            }
+            other => panic!("Expected open sea, got {:?}", other),
+        }
+        let below = ChunkPos::new(0, 0, SURFACE_LEVEL - 1);
+        assert_eq!(generator.generate(below), Chunk::generate(11, below));
+    }
+}
@@ -5,12 +5,13 @@ use amethyst::renderer::Rgba;
 use amethyst::renderer::Sprite;
 use amethyst::renderer::Texture;
 use amethyst::{
-    core::math::{Vector2, Vector3, Vector4},
+    core::math::{Vector2, Vector4},
     ecs::{Component, DenseVecStorage, Entity, Read, Write},
 };
 use serde::{Deserialize, Serialize};
 use specs_derive::Component;
 
+use crate::coords::TilePos;
 use crate::specs_static::{Id, Storage};
 
 #[derive(Clone, Debug)]
@@ -35,11 +36,14 @@ pub struct TileEntities(pub HashSet<Entity>);
 pub struct TileId(pub u32);
 
 impl TileId {
+    /// Spot of a tile layer which has nothing on it.
+    pub const EMPTY: TileId = TileId(u32::max_value());
+
     #[inline]
     pub fn coords(self, dimensions: Vector2<u32>) -> (f32, f32) {
         (
-            (self.0 % dimensions.y) as f32,
-            (self.0 / dimensions.y) as f32,
+            (self.0 % dimensions.x) as f32,
+            (self.0 / dimensions.x) as f32,
         )
     }
 }
@@ -67,22 +71,23 @@ impl Tiles {
     }
 
     pub fn id(self, x: u32, y: u32) -> TileId {
-        TileId(y * self.dimensions.y + x)
+        TileId(y * self.dimensions.x + x)
     }
 
     pub fn id_from_vector(self, vector: Vector2<u32>) -> TileId {
-        TileId(vector.y * self.dimensions.y + vector.x)
+        TileId(vector.y * self.dimensions.x + vector.x)
     }
 
-    pub fn world_to_tile(
-        self,
-        vector: &Vector3<f32>,
-        game_settings: &crate::settings::Config,
-    ) -> Vector2<u32> {
-        Vector2::new(
-            (vector.x / 20. / game_settings.graphics.scale) as u32,
-            (vector.y / 20. / game_settings.graphics.scale).abs() as u32,
-        )
+    /// Id of a tile in the grid, which is centered on the origin. Every level shares the same
+    /// grid. Returns `None` for tiles outside of it.
+    pub fn id_of(self, tile: TilePos) -> Option<TileId> {
+        let x = tile.x + (self.dimensions.x / 2) as i32;
+        let y = tile.y + (self.dimensions.y / 2) as i32;
+        if x < 0 || y < 0 || x >= self.dimensions.x as i32 || y >= self.dimensions.y as i32 {
+            return None;
+        }
+
+        Some(self.id(x as u32, y as u32))
     }
 
     pub fn dimensions(self) -> Vector2<u32> {
@@ -6,8 +6,6 @@ use amethyst::ecs::{
 use amethyst::shrev;
 use hibitset::BitSet;
 
-pub const TILE_SIZE: f32 = 128.;
-
 pub trait HasChannel<E> {
     /// Event channel tracking modified/inserted/removed components.
     fn channel(&self) -> &shrev::EventChannel<E>;
@@ -0,0 +1,531 @@
+//! Headless mode of the terrain generator, for build servers without a display. Settings come from
+//! an optional RON file, overridden by the arguments, and the chosen exports are written to disk.
+//! With `--sweep` a grid of maps is rendered into a contact sheet instead.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use amethyst::error::{format_err, Error, ResultExt};
+use log::info;
+use rand::rngs::StdRng;
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+
+use survival::mapgen::{
+    ArchipelagoSettings, Cell, CellData, CellGraph, ClimateSettings, ContinentSettings,
+    ErosionSettings, FeatureCollection, FlatSettings, Generator, GeneratorConfig, Heightmap,
+    ImageMode, IndexPoint, IslandGeneratorSettings, MapSeed, TerrainStrategy,
+};
+
+pub const USAGE: &str = "\
+Usage: terrain_generator [OPTIONS]
+
+Without options the interactive generator is opened.
+
+Options:
+    --config FILE       Read the settings from a RON file, other options override it
+    --out DIR           Folder the exports are written to
+    --export LIST       Comma separated exports: map, biomes, eroded, heightmap16, geojson, cells
+    --biomes            Color contact sheets by biome instead of height
+    --sweep NAME=A,B,C  Render a contact sheet of maps with the parameter set to each value.
+                        A second sweep adds rows to the grid.
+    --columns N         Number of maps in a row of the contact sheet
+
+Parameters, which can also be swept:
+    --seed TEXT         --terrain island|continent|archipelago|flat
+    --box-size N        --points N          --lloyd N
+    --height F          --radius F          --sharpness F
+    --octaves N         --islands N         --hydraulic N       --thermal N
+
+The seed of a world given as --seed generates the island of that world.
+";
+
+/// A file written for a generated map.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Export {
+    /// `map.png`, colored by height
+    Map,
+    /// `biomes.png`, colored by biome
+    Biomes,
+    /// `eroded.png`, the heightmap after erosion
+    Eroded,
+    /// `map16.png`, the eroded heightmap as a 16-bit grayscale image
+    Heightmap16,
+    /// `map.geojson`, the cell polygons with their attributes
+    GeoJson,
+    /// `map.cells.ron`, the cell graph which loads back into the generator
+    Cells,
+}
+
+impl Export {
+    fn parse(name: &str) -> Result<Self, Error> {
+        Ok(match name {
+            "map" => Export::Map,
+            "biomes" => Export::Biomes,
+            "eroded" => Export::Eroded,
+            "heightmap16" => Export::Heightmap16,
+            "geojson" => Export::GeoJson,
+            "cells" => Export::Cells,
+            _ => return Err(format_err!("Unknown export {}", name)),
+        })
+    }
+}
+
+/// A parameter set to every one of its values in turn.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sweep {
+    pub parameter: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchSettings {
+    pub seed: String,
+    pub config: GeneratorConfig,
+    pub terrain: TerrainStrategy,
+    pub erosion: ErosionSettings,
+    pub exports: Vec<Export>,
+    pub out: PathBuf,
+    pub sweeps: Vec<Sweep>,
+    /// Maps in a row of the contact sheet. Defaults to the values of the first sweep when there
+    /// are two, or a square grid.
+    pub columns: Option<usize>,
+    pub biomes: bool,
+}
+
+impl Default for BatchSettings {
+    fn default() -> Self {
+        Self {
+            seed: "balls".to_string(),
+            config: GeneratorConfig::default(),
+            terrain: TerrainStrategy::default(),
+            erosion: ErosionSettings::default(),
+            exports: vec![Export::Map, Export::Eroded],
+            out: PathBuf::from("terrain_export"),
+            sweeps: Vec::new(),
+            columns: None,
+            biomes: false,
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| format_err!("Invalid value {:?} for --{}", value, name))
+}
+
+impl BatchSettings {
+    /// Read the settings from the arguments, without the name of the program.
+    pub fn from_args(args: &[String]) -> Result<Self, Error> {
+        let mut settings = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => {
+                let path = args
+                    .get(i + 1)
+                    .ok_or_else(|| format_err!("Missing the file of --config"))?;
+                Self::load(Path::new(path))?
+            }
+            None => Self::default(),
+        };
+
+        // The other parameters of the terrain only apply once the terrain is chosen
+        if let Some(i) = args.iter().position(|arg| arg == "--terrain") {
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| format_err!("Missing the value of --terrain"))?;
+            settings.set("terrain", value)?;
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = match arg.get(..2) {
+                Some("--") => &arg[2..],
+                _ => return Err(format_err!("Unexpected argument {}", arg)),
+            };
+            if name == "biomes" {
+                settings.biomes = true;
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format_err!("Missing the value of --{}", name))?;
+            match name {
+                "config" | "terrain" => {}
+                "out" => settings.out = PathBuf::from(value),
+                "export" => {
+                    settings.exports = value
+                        .split(',')
+                        .map(Export::parse)
+                        .collect::<Result<_, _>>()?
+                }
+                "columns" => settings.columns = Some(number(name, value)?),
+                "sweep" => {
+                    let mut parts = value.splitn(2, '=');
+                    let parameter = parts.next().unwrap_or_default().to_string();
+                    let values = parts
+                        .next()
+                        .ok_or_else(|| format_err!("--sweep needs NAME=VALUES, got {}", value))?
+                        .split(',')
+                        .map(str::to_string)
+                        .collect();
+                    settings.sweeps.push(Sweep { parameter, values });
+                }
+                _ => settings.set(name, value)?,
+            }
+        }
+
+        // Catch misspelled sweeps before generating anything
+        for sweep in &settings.sweeps {
+            for value in &sweep.values {
+                settings.clone().set(&sweep.parameter, value)?;
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = fs::File::open(path)
+            .with_context(|_| format_err!("Failed to open batch settings {:?}", path))?;
+
+        ron::de::from_reader(file)
+            .map_err(|e| format_err!("Unable to parse batch settings {:?}: {}", path, e))
+    }
+
+    /// Change a parameter of the map. Switching to another terrain keeps the height of the
+    /// current one, choosing the current terrain again keeps all of its settings.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        match name {
+            "seed" => self.seed = value.to_string(),
+            "box-size" => self.config.box_size = number(name, value)?,
+            "points" => self.config.num_points = number(name, value)?,
+            "lloyd" => self.config.num_lloyd = number(name, value)?,
+            "hydraulic" => self.erosion.hydraulic_iterations = number(name, value)?,
+            "thermal" => self.erosion.thermal_iterations = number(name, value)?,
+            "terrain" => {
+                let terrain = match value {
+                    "island" => TerrainStrategy::Island(IslandGeneratorSettings::default()),
+                    "continent" => TerrainStrategy::Continent(ContinentSettings::default()),
+                    "archipelago" => TerrainStrategy::Archipelago(ArchipelagoSettings::default()),
+                    "flat" => TerrainStrategy::Flat(FlatSettings::default()),
+                    _ => return Err(format_err!("Unknown terrain {}", value)),
+                };
+                if std::mem::discriminant(&terrain) != std::mem::discriminant(&self.terrain) {
+                    let height = match &self.terrain {
+                        TerrainStrategy::Island(s) => s.height,
+                        TerrainStrategy::Continent(s) => s.height,
+                        TerrainStrategy::Archipelago(s) => s.height,
+                        TerrainStrategy::Flat(s) => s.height,
+                    };
+                    self.terrain = terrain;
+                    self.set("height", &height.to_string())?;
+                }
+            }
+            "height" => {
+                let height = number(name, value)?;
+                match &mut self.terrain {
+                    TerrainStrategy::Island(s) => s.height = height,
+                    TerrainStrategy::Continent(s) => s.height = height,
+                    TerrainStrategy::Archipelago(s) => s.height = height,
+                    TerrainStrategy::Flat(s) => s.height = height,
+                }
+            }
+            "radius" | "sharpness" => {
+                let value = number(name, value)?;
+                match &mut self.terrain {
+                    TerrainStrategy::Island(s) if name == "radius" => s.radius = value,
+                    TerrainStrategy::Island(s) => s.sharpness = value,
+                    _ => return Err(format_err!("--{} only applies to the island terrain", name)),
+                }
+            }
+            "octaves" => match &mut self.terrain {
+                TerrainStrategy::Continent(s) => s.octaves = number(name, value)?,
+                _ => {
+                    return Err(format_err!(
+                        "--octaves only applies to the continent terrain"
+                    ))
+                }
+            },
+            "islands" => match &mut self.terrain {
+                TerrainStrategy::Archipelago(s) => s.islands = number(name, value)?,
+                _ => {
+                    return Err(format_err!(
+                        "--islands only applies to the archipelago terrain"
+                    ))
+                }
+            },
+            _ => return Err(format_err!("Unknown option --{}", name)),
+        }
+        Ok(())
+    }
+
+    /// Every combination of the swept values, with the settings they produce.
+    pub fn variants(&self) -> Result<Vec<(Vec<(String, String)>, BatchSettings)>, Error> {
+        let mut variants = vec![(Vec::new(), self.clone())];
+        for sweep in &self.sweeps {
+            let mut next = Vec::with_capacity(variants.len() * sweep.values.len());
+            for (params, settings) in &variants {
+                for value in &sweep.values {
+                    let mut params = params.clone();
+                    params.push((sweep.parameter.clone(), value.clone()));
+                    let mut settings = settings.clone();
+                    settings.set(&sweep.parameter, value)?;
+                    next.push((params, settings));
+                }
+            }
+            variants = next;
+        }
+
+        // The first sweep runs along the rows, so order the maps row by row
+        if self.sweeps.len() == 2 {
+            let columns = self.sweeps[0].values.len();
+            let rows = self.sweeps[1].values.len();
+            let mut ordered = Vec::with_capacity(variants.len());
+            for row in 0..rows {
+                for column in 0..columns {
+                    ordered.push(variants[column * rows + row].clone());
+                }
+            }
+            variants = ordered;
+        }
+        Ok(variants)
+    }
+
+    fn columns(&self, count: usize) -> usize {
+        let columns = match (self.columns, self.sweeps.len()) {
+            (Some(columns), _) => columns,
+            (None, 2) => self.sweeps[0].values.len(),
+            (None, _) => (count as f64).sqrt().ceil() as usize,
+        };
+        columns.max(1)
+    }
+
+    fn mode(&self) -> ImageMode {
+        if self.biomes {
+            ImageMode::Biome
+        } else {
+            ImageMode::Height
+        }
+    }
+}
+
+/// A generated map and the generator it came from.
+struct Map {
+    generator: Generator<StdRng>,
+    cells: HashMap<IndexPoint, Cell<CellData>>,
+}
+
+fn generate(settings: &BatchSettings) -> Map {
+    let seed = MapSeed::new(&settings.seed);
+    info!(
+        "Generating seed {:?} ({}) with {:?} and {:?}",
+        settings.seed, seed, settings.config, settings.terrain
+    );
+    let mut generator = Generator::new(seed.rng());
+    let mut cells = generator.run(&settings.config, &settings.terrain);
+    generator.assign_biomes(&settings.config, &ClimateSettings::default(), &mut cells);
+    Map { generator, cells }
+}
+
+/// Generate the maps of the settings and write their exports or contact sheet.
+pub fn run(settings: &BatchSettings) -> Result<(), Error> {
+    fs::create_dir_all(&settings.out)
+        .with_context(|_| format_err!("Failed to create the output folder {:?}", settings.out))?;
+
+    if settings.sweeps.is_empty() {
+        export(settings, &generate(settings))
+    } else {
+        contact_sheet(settings)
+    }
+}
+
+fn export(settings: &BatchSettings, map: &Map) -> Result<(), Error> {
+    let config = &settings.config;
+    let out = &settings.out;
+
+    let eroded = || {
+        let mut heightmap =
+            Heightmap::from_cells(config, &map.cells, (config.box_size as usize).max(1));
+        heightmap.erode(&settings.erosion);
+        heightmap
+    };
+
+    for export in &settings.exports {
+        let path = out.join(match export {
+            Export::Map => "map.png",
+            Export::Biomes => "biomes.png",
+            Export::Eroded => "eroded.png",
+            Export::Heightmap16 => "map16.png",
+            Export::GeoJson => "map.geojson",
+            Export::Cells => "map.cells.ron",
+        });
+
+        match export {
+            Export::Map => {
+                map.generator
+                    .save_image(config, &path, &map.cells, ImageMode::Height)?
+            }
+            Export::Biomes => {
+                map.generator
+                    .save_image(config, &path, &map.cells, ImageMode::Biome)?
+            }
+            Export::Eroded => eroded().save_image(&path)?,
+            Export::Heightmap16 => eroded().save_png16(&path)?,
+            Export::GeoJson => FeatureCollection::from_cells(&map.cells).write(&path)?,
+            Export::Cells => CellGraph::new(config, &map.cells).write(&path)?,
+        }
+        info!("Wrote {:?}", path);
+    }
+    Ok(())
+}
+
+/// Where a map of the contact sheet is and what it was generated with.
+#[derive(Debug, Serialize)]
+struct SheetEntry {
+    column: usize,
+    row: usize,
+    /// Canonical form of the seed of the map.
+    seed: String,
+    parameters: Vec<(String, String)>,
+}
+
+fn contact_sheet(settings: &BatchSettings) -> Result<(), Error> {
+    let variants = settings.variants()?;
+    let columns = settings.columns(variants.len());
+    let rows = (variants.len() + columns - 1) / columns;
+
+    let images = variants
+        .iter()
+        .map(|(_, variant)| {
+            let map = generate(variant);
+            map.generator
+                .render_image(&variant.config, &map.cells, settings.mode())
+        })
+        .collect::<Vec<_>>();
+
+    // Maps are drawn in tiles of the largest map, with a gap between them
+    const GAP: u32 = 4;
+    let width = images.iter().map(|image| image.width()).max().unwrap_or(0) + GAP;
+    let height = images.iter().map(|image| image.height()).max().unwrap_or(0) + GAP;
+    let mut sheet = image::RgbImage::from_pixel(
+        width * columns as u32 + GAP,
+        height * rows as u32 + GAP,
+        image::Rgb([25, 25, 25]),
+    );
+
+    let mut entries = Vec::with_capacity(images.len());
+    for (i, (image, (parameters, variant))) in images.iter().zip(&variants).enumerate() {
+        let (column, row) = (i % columns, i / columns);
+        image::imageops::replace(
+            &mut sheet,
+            image,
+            column as u32 * width + GAP,
+            row as u32 * height + GAP,
+        );
+        entries.push(SheetEntry {
+            column,
+            row,
+            seed: MapSeed::new(&variant.seed).to_string(),
+            parameters: parameters.clone(),
+        });
+    }
+
+    let path = settings.out.join("contact_sheet.png");
+    sheet.save(&path)?;
+    let index = to_string_pretty(&entries, PrettyConfig::default())
+        .map_err(|e| format_err!("Unable to serialize the contact sheet index: {}", e))?;
+    fs::write(settings.out.join("contact_sheet.ron"), index)?;
+    info!(
+        "Wrote a contact sheet of {} maps to {:?}",
+        entries.len(),
+        path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn arguments_override_defaults() {
+        let settings = BatchSettings::from_args(&args(
+            "--seed hello --points 500 --terrain continent --height 0.4 --export map,geojson",
+        ))
+        .unwrap();
+        assert_eq!(settings.seed, "hello");
+        assert_eq!(settings.config.num_points, 500);
+        assert_eq!(settings.exports, vec![Export::Map, Export::GeoJson]);
+        match settings.terrain {
+            TerrainStrategy::Continent(continent) => assert_eq!(continent.height, 0.4),
+            other => panic!("Expected a continent, got {:?}", other),
+        }
+
+        assert!(BatchSettings::from_args(&args("--radius 0.5 --terrain flat")).is_err());
+        assert!(BatchSettings::from_args(&args("--terrain flat --radius 0.5")).is_err());
+        assert!(BatchSettings::from_args(&args("--points")).is_err());
+        assert!(BatchSettings::from_args(&args("--sweep colour=1,2")).is_err());
+    }
+
+    #[test]
+    fn terrain_applies_before_its_parameters() {
+        let settings =
+            BatchSettings::from_args(&args("--height 0.3 --radius 0.7 --terrain island")).unwrap();
+        match &settings.terrain {
+            TerrainStrategy::Island(island) => {
+                assert_eq!((island.height, island.radius), (0.3, 0.7))
+            }
+            other => panic!("Expected an island, got {:?}", other),
+        }
+
+        // Swept terrains keep the height given for all of them
+        let settings =
+            BatchSettings::from_args(&args("--height 0.3 --sweep terrain=island,flat")).unwrap();
+        for (_, variant) in settings.variants().unwrap() {
+            match variant.terrain {
+                TerrainStrategy::Island(s) => assert_eq!(s.height, 0.3),
+                TerrainStrategy::Flat(s) => assert_eq!(s.height, 0.3),
+                other => panic!("Unexpected terrain {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn sweeps_form_a_grid() {
+        let settings =
+            BatchSettings::from_args(&args("--sweep seed=a,b,c --sweep points=100,200")).unwrap();
+        let variants = settings.variants().unwrap();
+        assert_eq!(variants.len(), 6);
+        assert_eq!(settings.columns(variants.len()), 3);
+
+        // Seeds change along a row, point counts from one row to the next
+        let grid = variants
+            .iter()
+            .map(|(_, v)| (v.seed.as_str(), v.config.num_points))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            grid,
+            vec![
+                ("a", 100),
+                ("b", 100),
+                ("c", 100),
+                ("a", 200),
+                ("b", 200),
+                ("c", 200)
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_round_trip_through_ron() {
+        let settings = BatchSettings::from_args(&args("--seed x --sweep radius=0.8,0.9")).unwrap();
+        let ron = to_string_pretty(&settings, PrettyConfig::default()).unwrap();
+        assert_eq!(ron::de::from_str::<BatchSettings>(&ron).unwrap(), settings);
+    }
+}
@@ -1,6 +1,8 @@
 extern crate amethyst;
 extern crate amethyst_imgui;
 
+mod batch;
+
 use amethyst::{
     assets::{AssetStorage, HotReloadBundle, Loader},
     core::{Transform, TransformBundle},
@@ -14,7 +16,11 @@ use amethyst::{
 };
 
 use amethyst_imgui::{imgui, imgui::im_str, ImguiState};
-use survival::mapgen::{CellData, Generator, GeneratorConfig, IslandGeneratorSettings};
+use survival::mapgen::{
+    ArchipelagoSettings, CellGraph, ClimateSettings, ContinentSettings, ErosionSettings,
+    FeatureCollection, FlatSettings, Generator, GeneratorConfig, Heightmap, ImageMode,
+    IslandGeneratorSettings, MapSeed, TerrainStrategy,
+};
 
 #[derive(Default)]
 pub struct ImguiBeginFrameSystem;
@@ -81,6 +87,14 @@ pub struct ImguiEndFrameSystem {
     box_size: f32,
     num_points: i32,
     num_lloyd: i32,
+    strategy: i32,
+    octaves: i32,
+    islands: i32,
+    show_biomes: bool,
+    hydraulic_iterations: i32,
+    thermal_iterations: i32,
+    /// Cells of the map on screen, kept for exports.
+    graph: Option<CellGraph>,
 }
 impl<'s> amethyst::ecs::System<'s> for ImguiEndFrameSystem {
     type SystemData = ();
@@ -96,6 +110,13 @@ impl<'s> amethyst::ecs::System<'s> for ImguiEndFrameSystem {
         self.box_size = g_d.box_size as f32;
         self.num_points = g_d.num_points as i32;
         self.num_lloyd = g_d.num_lloyd as i32;
+
+        self.octaves = ContinentSettings::default().octaves as i32;
+        self.islands = ArchipelagoSettings::default().islands as i32;
+
+        let e_d = ErosionSettings::default();
+        self.hydraulic_iterations = e_d.hydraulic_iterations as i32;
+        self.thermal_iterations = e_d.thermal_iterations as i32;
     }
 
     fn run(&mut self, _: Self::SystemData) {
@@ -110,17 +131,23 @@ impl<'s> amethyst::ecs::System<'s> for ImguiEndFrameSystem {
                 .size((300.0, 100.0), imgui::ImGuiCond::FirstUseEver)
                 .build(|| {
                     if ui.button(im_str!("Regenerate Island"), (0.0, 0.0)) {
-                        use sha2::{Digest, Sha256};
-                        use std::ops::Deref;
-
-                        let mut hasher = Sha256::new();
-                        hasher.input(self.state.seed.to_str().as_bytes());
-                        let result = hasher.result();
-
-                        let settings = IslandGeneratorSettings {
-                            height: f64::from(self.height),
-                            sharpness: f64::from(self.sharpness),
-                            radius: f64::from(self.radius),
+                        let seed = MapSeed::new(self.state.seed.to_str());
+
+                        let terrain = match self.strategy {
+                            1 => TerrainStrategy::Continent(ContinentSettings {
+                                octaves: self.octaves as usize,
+                                ..Default::default()
+                            }),
+                            2 => TerrainStrategy::Archipelago(ArchipelagoSettings {
+                                islands: self.islands as usize,
+                                ..Default::default()
+                            }),
+                            3 => TerrainStrategy::Flat(FlatSettings::default()),
+                            _ => TerrainStrategy::Island(IslandGeneratorSettings {
+                                height: f64::from(self.height),
+                                sharpness: f64::from(self.sharpness),
+                                radius: f64::from(self.radius),
+                            }),
                         };
 
                         let config = GeneratorConfig {
@@ -129,14 +156,29 @@ impl<'s> amethyst::ecs::System<'s> for ImguiEndFrameSystem {
                             num_lloyd: self.num_lloyd as usize,
                         };
 
-                        generate_new_map(
-                            arrayref::array_ref![result.deref(), 0, 32],
-                            &config,
-                            &settings,
-                        )
-                        .unwrap();
+                        self.graph = Some(
+                            generate_new_map(seed, &config, &terrain, &self.erosion(), self.mode())
+                                .unwrap(),
+                        );
+                    }
+                    if ui.button(im_str!("Export"), (0.0, 0.0)) {
+                        if let Some(graph) = &self.graph {
+                            if let Err(e) = export_map(graph, &self.erosion()) {
+                                log::error!("Unable to export the map: {}", e);
+                            }
+                        }
+                    }
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Load Cells"), (0.0, 0.0)) {
+                        match load_map(&self.erosion(), self.mode()) {
+                            Ok(graph) => self.graph = Some(graph),
+                            Err(e) => log::error!("Unable to load the exported cells: {}", e),
+                        }
                     }
                     ui.input_text(im_str!("Seed"), &mut self.state.seed).build();
+                    // The canonical seed can be typed in place of the text for the same map
+                    let canonical = MapSeed::new(self.state.seed.to_str());
+                    ui.text_wrapped(&im_str!("Canonical seed: {}", canonical));
                     ui.separator();
                     ui.slider_float(im_str!("Box Size"), &mut self.box_size, 1.0, 5000.0)
                         .build();
@@ -145,23 +187,78 @@ impl<'s> amethyst::ecs::System<'s> for ImguiEndFrameSystem {
                     ui.slider_int(im_str!("Lloyd Reductions"), &mut self.num_lloyd, 1, 20)
                         .build();
                     ui.separator();
-                    ui.slider_float(im_str!("Start Height"), &mut self.height, 0.1, 1.0)
-                        .build();
-                    ui.slider_float(im_str!("Radius"), &mut self.radius, 0.1, 0.99999)
-                        .build();
-                    ui.slider_float(im_str!("Sharpness"), &mut self.sharpness, 0.1, 2.0)
-                        .build();
+                    ui.radio_button(im_str!("Island"), &mut self.strategy, 0);
+                    ui.radio_button(im_str!("Continent"), &mut self.strategy, 1);
+                    ui.radio_button(im_str!("Archipelago"), &mut self.strategy, 2);
+                    ui.radio_button(im_str!("Flat"), &mut self.strategy, 3);
+                    match self.strategy {
+                        1 => {
+                            ui.slider_int(im_str!("Octaves"), &mut self.octaves, 1, 10)
+                                .build();
+                        }
+                        2 => {
+                            ui.slider_int(im_str!("Islands"), &mut self.islands, 1, 30)
+                                .build();
+                        }
+                        3 => {}
+                        _ => {
+                            ui.slider_float(im_str!("Start Height"), &mut self.height, 0.1, 1.0)
+                                .build();
+                            ui.slider_float(im_str!("Radius"), &mut self.radius, 0.1, 0.99999)
+                                .build();
+                            ui.slider_float(im_str!("Sharpness"), &mut self.sharpness, 0.1, 2.0)
+                                .build();
+                        }
+                    }
+                    ui.separator();
+                    ui.checkbox(im_str!("Biome Colors"), &mut self.show_biomes);
+                    ui.separator();
+                    ui.slider_int(
+                        im_str!("Hydraulic Erosion"),
+                        &mut self.hydraulic_iterations,
+                        0,
+                        200,
+                    )
+                    .build();
+                    ui.slider_int(
+                        im_str!("Thermal Erosion"),
+                        &mut self.thermal_iterations,
+                        0,
+                        200,
+                    )
+                    .build();
                 });
         }
     }
 }
 
+impl ImguiEndFrameSystem {
+    fn erosion(&self) -> ErosionSettings {
+        ErosionSettings {
+            hydraulic_iterations: self.hydraulic_iterations as usize,
+            thermal_iterations: self.thermal_iterations as usize,
+            ..Default::default()
+        }
+    }
+
+    fn mode(&self) -> ImageMode {
+        if self.show_biomes {
+            ImageMode::Biome
+        } else {
+            ImageMode::Height
+        }
+    }
+}
+
 struct Example;
 impl SimpleState for Example {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         let world = data.world;
+        // The map before erosion on the left and after erosion on the right
         let texture_handle = load_texture(world, "map.png");
-        let _image = init_image(world, &texture_handle);
+        let _image = init_image(world, &texture_handle, -250.0);
+        let eroded_handle = load_texture(world, "map_eroded.png");
+        let _eroded = init_image(world, &eroded_handle, 250.0);
 
         init_camera(world);
     }
@@ -180,6 +277,16 @@ impl SimpleState for Example {
 fn main() -> amethyst::Result<()> {
     amethyst::start_logger(Default::default());
 
+    // Any argument switches to the headless mode
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", batch::USAGE);
+        return Ok(());
+    }
+    if !args.is_empty() {
+        return batch::run(&batch::BatchSettings::from_args(&args)?);
+    }
+
     let resources = application_root_dir()?.join("tools/terrain_generator/resources");
     let config = DisplayConfig::load(resources.join("display_config.ron"));
     let pipe = Pipeline::build().with_stage(
@@ -207,43 +314,91 @@ fn main() -> amethyst::Result<()> {
 }
 
 fn generate_new_map(
-    seed: &[u8; 32],
+    seed: MapSeed,
     config: &GeneratorConfig,
-    settings: &IslandGeneratorSettings,
+    terrain: &TerrainStrategy,
+    erosion: &ErosionSettings,
+    mode: ImageMode,
+) -> amethyst::Result<CellGraph> {
+    let mut generator = Generator::new(seed.rng());
+
+    let mut cells = generator.run(config, terrain);
+    generator.assign_biomes(config, &ClimateSettings::default(), &mut cells);
+
+    let graph = CellGraph::new(config, &cells);
+    render_map(&graph, erosion, mode)?;
+    Ok(graph)
+}
+
+/// Draw the map of a cell graph before and after erosion.
+fn render_map(
+    graph: &CellGraph,
+    erosion: &ErosionSettings,
+    mode: ImageMode,
 ) -> amethyst::Result<()> {
     use rand::SeedableRng;
 
-    let mut generator = Generator::new(rand::rngs::StdRng::from_seed(*seed));
-
-    let mut cells = generator.gen_voronoi::<CellData>(&config);
-    generator.create_island(config, settings, &mut cells);
+    // Drawing does not use the rng of the generator
+    let generator = Generator::new(rand::rngs::StdRng::seed_from_u64(0));
+    let config = &graph.config;
+    let cells = graph.cells();
 
+    let resources = application_root_dir()?.join("tools/terrain_generator/resources");
     generator
-        .save_heightmap_image(
-            &config,
-            &application_root_dir()?.join("tools/terrain_generator/resources/map.png"),
-            &cells,
-        )
+        .save_image(config, &resources.join("map.png"), &cells, mode)
+        .unwrap();
+
+    let mut heightmap = Heightmap::from_cells(config, &cells, (config.box_size as usize).max(1));
+    heightmap.erode(erosion);
+    heightmap
+        .save_image(&resources.join("map_eroded.png"))
         .unwrap();
 
     Ok(())
 }
 
+/// Write the cells of the map, a GeoJSON of their polygons and a 16-bit heightmap of the eroded
+/// map into the export folder.
+fn export_map(graph: &CellGraph, erosion: &ErosionSettings) -> amethyst::Result<()> {
+    let folder = application_root_dir()?.join("tools/terrain_generator/resources/export");
+    std::fs::create_dir_all(&folder)?;
+
+    let cells = graph.cells();
+    graph.write(&folder.join("map.cells.ron"))?;
+    FeatureCollection::from_cells(&cells).write(&folder.join("map.geojson"))?;
+
+    let config = &graph.config;
+    let mut heightmap = Heightmap::from_cells(config, &cells, (config.box_size as usize).max(1));
+    heightmap.erode(erosion);
+    heightmap.save_png16(&folder.join("map16.png"))?;
+
+    log::info!("Exported the map to {:?}", folder);
+    Ok(())
+}
+
+/// Show the cells of the last export again without generating them.
+fn load_map(erosion: &ErosionSettings, mode: ImageMode) -> amethyst::Result<CellGraph> {
+    let folder = application_root_dir()?.join("tools/terrain_generator/resources/export");
+    let graph = CellGraph::read(&folder.join("map.cells.ron"))?;
+    render_map(&graph, erosion, mode)?;
+    Ok(graph)
+}
+
 fn init_camera(world: &mut World) {
     let mut transform = Transform::default();
     transform.set_translation_z(1.0);
     world
         .create_entity()
         .with(Camera::from(Projection::orthographic(
-            -250.0, 250.0, -250.0, 250.0,
+            -500.0, 500.0, -375.0, 375.0,
         )))
         .with(transform)
         .build();
 }
 
-fn init_image(world: &mut World, texture: &TextureHandle) -> Entity {
+fn init_image(world: &mut World, texture: &TextureHandle, x: f32) -> Entity {
     let mut transform = Transform::default();
-    transform.set_translation_x(0.0);
+    transform.set_translation_x(x);
     transform.set_translation_y(0.0);
 
     world